@@ -0,0 +1,7 @@
+fn main() {
+    // the `load_lua_module` test loads a module that finds its lua functions in the test binary,
+    // only executables are affected
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("linux") {
+        println!("cargo:rustc-link-arg=-Wl,--export-dynamic");
+    }
+}
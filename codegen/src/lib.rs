@@ -1,20 +1,34 @@
+mod lua_function;
+mod lua_module;
 pub mod tuple_impl;
 mod user_data;
 
 use proc_macro2::TokenStream;
-use quote::quote;
 use venial::{parse_declaration, Declaration};
 
-pub fn generate_user_data_impl(item: TokenStream) -> TokenStream {
-    let ty = match parse_declaration(item.clone()) {
+pub fn generate_user_data_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let ty = match parse_declaration(item) {
         Ok(Declaration::Impl(ty)) => ty,
         _ => panic!("user_data attribute can only be used with impl."),
     };
 
-    let user_data_impl = user_data::gen_user_data_impl(ty);
+    user_data::gen_user_data_impl(attr, ty)
+}
+
+pub fn generate_lua_module(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let func = match parse_declaration(item) {
+        Ok(Declaration::Function(func)) => func,
+        _ => panic!("lua_module attribute can only be used with functions."),
+    };
+
+    lua_module::gen_lua_module(attr, func)
+}
+
+pub fn generate_lua_function(item: TokenStream) -> TokenStream {
+    let func = match parse_declaration(item) {
+        Ok(Declaration::Function(func)) => func,
+        _ => panic!("lua_function attribute can only be used with functions."),
+    };
 
-    quote! {
-        #item
-        #user_data_impl
-    }
+    lua_function::gen_lua_function(func)
 }
@@ -0,0 +1,60 @@
+use proc_macro2::{Ident, TokenStream};
+use quote::quote;
+use venial::{FnParam, Function};
+
+use crate::user_data::{is_state_param, Args};
+
+// `fn clamp(x: f64, lo: f64, hi: f64) -> f64` gets a `clamp_lua` C function converting its
+// arguments the same way `#[user_data]` methods do, `fn raw(state: &State) -> i32` is called
+// with the state and returns how many values it pushed.
+pub fn gen_lua_function(func: Function) -> TokenStream {
+    if func.generic_params.is_some() {
+        panic!("generic functions not allowed.");
+    }
+    if func.qualifiers.tk_async.is_some() {
+        panic!("async functions not allowed.");
+    }
+
+    let params = func
+        .params
+        .iter()
+        .map(|(param, _)| param)
+        .collect::<Vec<_>>();
+    if params
+        .iter()
+        .any(|param| matches!(param, FnParam::Receiver(_)))
+    {
+        panic!("lua_function can't take self, use #[user_data] instead.");
+    }
+
+    let fn_ident = &func.name;
+    let body = if params.len() == 1 && is_state_param(params[0]) {
+        quote!(Ok(#fn_ident(state) as std::ffi::c_int))
+    } else {
+        let args = Args::new(&func, &params);
+        let cast_args = args.cast(1);
+        let names = args.names();
+        let call = args.push_result(quote!(#fn_ident(#(#names),*)));
+        quote! {
+            #cast_args
+            #call
+        }
+    };
+
+    let vis = &func.vis_marker;
+    let lua_ident = Ident::new(&format!("{fn_ident}_lua"), fn_ident.span());
+
+    quote! {
+        #func
+
+        #[allow(non_upper_case_globals)]
+        #vis const #lua_ident: unsafe extern "C" fn(*mut sys::lua_State) -> std::ffi::c_int = {
+            unsafe extern "C-unwind" fn step(ptr: *mut sys::lua_State) -> std::ffi::c_int {
+                State::protect(ptr, |state| {
+                    #body
+                })
+            }
+            State::raw_function(step)
+        };
+    }
+}
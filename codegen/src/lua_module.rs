@@ -0,0 +1,63 @@
+use proc_macro2::{Ident, Literal, TokenStream, TokenTree};
+use quote::quote;
+use venial::Function;
+
+// `#[lua_module(name = "game.utils")]` or the function name. Dots become underscores in the
+// entry point, the same way `require` looks it up.
+fn module_name(attr: TokenStream, func: &Function) -> String {
+    let tokens = attr.into_iter().collect::<Vec<_>>();
+    match &tokens[..] {
+        [] => func.name.to_string(),
+        [TokenTree::Ident(key), TokenTree::Punct(eq), TokenTree::Literal(name)]
+            if key == "name" && eq.as_char() == '=' =>
+        {
+            let name = name.to_string();
+            name.trim_matches('"').to_string()
+        }
+        _ => panic!("expected #[lua_module] or #[lua_module(name = \"...\")]."),
+    }
+}
+
+// whether the function returns `Result<..>`, errors are raised in lua
+fn returns_result(func: &Function) -> bool {
+    let Some(return_ty) = &func.return_ty else { return false; };
+    let last = return_ty
+        .tokens
+        .iter()
+        .take_while(|token| !matches!(token, TokenTree::Punct(punct) if punct.as_char() == '<'))
+        .filter_map(|token| match token {
+            TokenTree::Ident(ident) => Some(ident),
+            _ => None,
+        })
+        .last();
+    matches!(last, Some(ident) if ident == "Result")
+}
+
+pub fn gen_lua_module(attr: TokenStream, func: Function) -> TokenStream {
+    let name = module_name(attr, &func);
+    let entry = Ident::new(
+        &format!("luaopen_{}", name.replace('.', "_")),
+        func.name.span(),
+    );
+    let name = Literal::string(&name);
+    let fn_ident = &func.name;
+    let call = if returns_result(&func) {
+        quote!(#fn_ident(state)?)
+    } else {
+        quote!(#fn_ident(state))
+    };
+
+    quote! {
+        #func
+
+        #[doc = concat!("Entry point of the `", #name, "` lua module.")]
+        #[no_mangle]
+        pub extern "C-unwind" fn #entry(ptr: *mut sys::lua_State) -> std::ffi::c_int {
+            State::protect(ptr, |state| {
+                let module = #call;
+                state.push(module);
+                Ok(1)
+            })
+        }
+    }
+}
@@ -51,10 +51,6 @@ fn from_lua(state: &State, idx: i32) -> Option<Self::Output> {
             }
         };
 
-        if state.get_top() < Self::len() {
-            return None;
-        }
-
         let a_value = state.cast_to::<A>(idx);
         if a_value.is_none() {
             return None;
@@ -93,10 +89,11 @@ pub fn generate_from_lua_tuple_impl(_: TokenStream) -> TokenStream {
     let mut impls = vec![];
     let alphabet: Vec<char> = (b'A'..b'Z').map(|c| c as char).collect();
 
-    (2..max).for_each(|n| {
+    (1..max).for_each(|n| {
         let mut letters_a = vec![];
         let mut where_ch = vec![];
         let mut cast_impl = vec![];
+        let mut mismatch_impl = vec![];
         let len = proc_macro2::Literal::i32_unsuffixed(n as i32);
 
         (0..n).for_each(|i| {
@@ -105,12 +102,19 @@ pub fn generate_from_lua_tuple_impl(_: TokenStream) -> TokenStream {
 
             letters_a.push(ch.clone());
             cast_impl.push(gen_cast(letter));
+            let offset = proc_macro2::Literal::i32_unsuffixed(i as i32);
+            mismatch_impl.push(quote! {
+                if let Some((offset, expected)) = #ch::mismatch(ptr, idx + #offset) {
+                    return Some((#offset + offset, expected));
+                }
+            });
             where_ch.push(quote!(#ch: FromLua<'a, Output = #ch>));
         });
 
         let return_value = gen_return_value(alphabet[n - 1]);
         let letters_b = letters_a.clone();
         let letters_c = letters_a.clone();
+        let letters_d = letters_a.clone();
         impls.push(quote! {
             impl<'a, #(#letters_a,)*> FromLua<'a> for (#(#letters_b,)*)
             where
@@ -118,7 +122,7 @@ pub fn generate_from_lua_tuple_impl(_: TokenStream) -> TokenStream {
             {
                 type Output = (#(#letters_c,)*);
 
-                fn from_lua(ptr: *mut luajit2_sys::lua_State, idx: i32) -> Option<Self::Output> {
+                fn from_lua(ptr: *mut crate::sys::lua_State, idx: i32) -> Option<Self::Output> {
                     let mut state = State::from_raw(ptr);
                     let mut idx = {
                         if idx.is_negative() {
@@ -128,7 +132,10 @@ pub fn generate_from_lua_tuple_impl(_: TokenStream) -> TokenStream {
                         }
                     };
 
-                    if state.get_top() < Self::len() {
+                    // slots past the top read as none, so only optional positions accept them.
+                    // that only holds within the stack space, which is grown to cover them
+                    let missing = idx + #len - 1 - state.get_top();
+                    if missing > 0 && unsafe { crate::sys::lua_checkstack(ptr, missing) } == 0 {
                         return None;
                     }
 
@@ -137,7 +144,24 @@ pub fn generate_from_lua_tuple_impl(_: TokenStream) -> TokenStream {
                     #return_value
                 }
 
-                fn len() -> i32 { #len }
+                // a trailing `Variadic` takes every result
+                fn len() -> i32 {
+                    if [#(#letters_d::len(),)*].contains(&crate::sys::LUA_MULTRET) {
+                        crate::sys::LUA_MULTRET
+                    } else {
+                        #len
+                    }
+                }
+
+                fn mismatch(ptr: *mut crate::sys::lua_State, idx: i32) -> Option<(i32, String)> {
+                    let idx = if idx.is_negative() {
+                        State::from_raw(ptr).get_top() + idx + 1
+                    } else {
+                        idx
+                    };
+                    #(#mismatch_impl)*
+                    None
+                }
             }
         });
     });
@@ -152,8 +176,9 @@ pub fn generate_to_lua_tuple_impl(_attr: TokenStream) -> TokenStream {
     let mut parts = vec![];
     let alphabet: Vec<char> = (b'A'..b'Z').map(|c| c as char).collect();
 
-    (2..max).for_each(|n| {
+    (1..max).for_each(|n| {
         let mut state_push = vec![];
+        let mut try_push = vec![];
         let mut letters_a = vec![];
         let mut letters_b = vec![];
         let mut where_ch = vec![];
@@ -165,6 +190,7 @@ pub fn generate_to_lua_tuple_impl(_attr: TokenStream) -> TokenStream {
             let ch = Ident::new(&letter.to_string(), Span::call_site());
 
             state_push.push(quote!(state.push(self.#index);));
+            try_push.push(quote!(self.#index.try_to_lua(ptr)?;));
             letters_a.push(ch.clone());
             letters_b.push(ch.clone());
             where_ch.push(quote!(#ch: ToLua))
@@ -189,11 +215,16 @@ pub fn generate_to_lua_tuple_impl(_attr: TokenStream) -> TokenStream {
             where
                 #(#where_ch,)*
             {
-                fn to_lua(self, ptr: *mut luajit2_sys::lua_State) {
+                fn to_lua(self, ptr: *mut crate::sys::lua_State) {
                     let mut state = State::from_raw(ptr);
                     #(#state_push)*
                 }
 
+                fn try_to_lua(self, ptr: *mut crate::sys::lua_State) -> crate::Result<()> {
+                    #(#try_push)*
+                    Ok(())
+                }
+
                 fn len() -> i32 { #len }
             }
         });
@@ -1,28 +1,100 @@
-use proc_macro2::{Ident, Literal, Punct, Span, TokenStream, TokenTree};
-use quote::quote;
-use venial::{FnParam, Function, Impl, ImplMember, Punctuated};
+use proc_macro2::{Group, Ident, Literal, Span, TokenStream, TokenTree};
+use quote::{format_ident, quote, ToTokens};
+use venial::{Attribute, AttributeValue, FnParam, Function, Impl, ImplMember};
+
+// `#[lua(...)]` options naming the metamethod a function implements.
+const META_METHODS: &[(&str, &str)] = &[
+    ("add", "__add"),
+    ("sub", "__sub"),
+    ("mul", "__mul"),
+    ("div", "__div"),
+    ("mod", "__mod"),
+    ("pow", "__pow"),
+    ("unm", "__unm"),
+    ("concat", "__concat"),
+    ("len", "__len"),
+    ("eq", "__eq"),
+    ("lt", "__lt"),
+    ("le", "__le"),
+    ("call", "__call"),
+    ("tostring", "__tostring"),
+];
 
-#[allow(unused, dead_code)]
 enum ParamsInfo {
     RawMethod(bool),
 
     RawStatic,
 
-    MethodNoArgNoReturn(bool),
+    Method(bool, Args),
 
-    Method(bool, TokenStream, TokenStream),
+    Static(Args),
+}
 
-    Static(TokenStream, TokenStream),
+pub(crate) struct Args {
+    tys: Vec<TokenStream>,
+    return_ty: Option<TokenStream>,
+    is_async: bool,
 }
 
-fn get_args_ty(func: &Function) -> (TokenStream, TokenStream) {
-    let mut params_ty = vec![];
-    for (param, _) in &func.params[1..] {
-        let FnParam::Typed(param) = param else { continue; };
-        params_ty.push(param.ty.clone());
+impl Args {
+    pub(crate) fn new(func: &Function, params: &[&FnParam]) -> Self {
+        let tys = params
+            .iter()
+            .filter_map(|param| match param {
+                FnParam::Typed(param) => Some(param.ty.to_token_stream()),
+                FnParam::Receiver(_) => None,
+            })
+            .collect();
+        let return_ty = func.return_ty.as_ref().map(|ty| ty.to_token_stream());
+        let is_async = func.qualifiers.tk_async.is_some();
+        Self {
+            tys,
+            return_ty,
+            is_async,
+        }
+    }
+
+    // args start right after self for methods, optional trailing args missing from the stack are
+    // read as `None` by the tuple conversion.
+    pub(crate) fn cast(&self, first_idx: i32) -> TokenStream {
+        if self.tys.is_empty() {
+            return quote!();
+        }
+
+        let tys = &self.tys;
+        quote! {
+            let args = state.cast_args::<(#(#tys,)*)>(#first_idx)?;
+        }
+    }
+
+    pub(crate) fn names(&self) -> Vec<TokenStream> {
+        (0..self.tys.len())
+            .map(|i| {
+                let index = syn::Index::from(i);
+                quote!(args.#index)
+            })
+            .collect()
+    }
+
+    // async functions push their future instead, the arguments it borrows are kept alive with it
+    pub(crate) fn push_result(&self, call: TokenStream) -> TokenStream {
+        if self.is_async {
+            return quote! {
+                Ok(unsafe { state.push_future(#call) })
+            };
+        }
+
+        match &self.return_ty {
+            Some(_) => quote! {
+                let result = #call;
+                state.try_push_values(result)
+            },
+            None => quote! {
+                #call;
+                Ok(0)
+            },
+        }
     }
-    let return_ty = func.return_ty.clone();
-    (quote!((#(#params_ty,)*)), quote!(#return_ty))
 }
 
 fn is_self_param(param: &FnParam) -> Option<bool> {
@@ -34,50 +106,72 @@ fn is_self_param(param: &FnParam) -> Option<bool> {
     }
 }
 
-fn is_state_param(tokens: &Vec<TokenTree>) -> bool {
-    let TokenTree::Punct(punct) = &tokens[0] else { return false; };
+pub(crate) fn is_state_param(param: &FnParam) -> bool {
+    let FnParam::Typed(param) = param else { return false; };
+    let tokens = &param.ty.tokens;
+    let Some(TokenTree::Punct(punct)) = tokens.first() else { return false; };
 
     if punct.as_char() != '&' {
         return false;
     }
 
-    let TokenTree::Ident(ident) = &tokens[1] else { return false; };
+    let Some(TokenTree::Ident(ident)) = tokens.get(1) else { return false; };
     ident.to_string().rfind("State").is_some()
 }
 
+fn is_lua_attr(attr: &Attribute) -> bool {
+    matches!(&attr.path[..], [TokenTree::Ident(ident)] if ident == "lua")
+}
+
+// idents inside every `#[lua(...)]` of the function
+fn lua_options(func: &Function) -> Vec<String> {
+    func.attributes
+        .iter()
+        .filter(|attr| is_lua_attr(attr))
+        .flat_map(|attr| match &attr.value {
+            AttributeValue::Group(_, tokens) => tokens
+                .iter()
+                .filter_map(|token| match token {
+                    TokenTree::Ident(ident) => Some(ident.to_string()),
+                    TokenTree::Punct(punct) if punct.as_char() == ',' => None,
+                    _ => panic!("expected #[lua(option, ...)]."),
+                })
+                .collect::<Vec<_>>(),
+            _ => panic!("expected #[lua(option, ...)]."),
+        })
+        .collect()
+}
+
+fn meta_name(option: &str) -> &'static str {
+    META_METHODS
+        .iter()
+        .find(|(name, _)| *name == option)
+        .map(|(_, meta)| *meta)
+        .unwrap_or_else(|| panic!("unknown lua option `{option}`."))
+}
+
 fn params_info(func: &Function) -> Option<ParamsInfo> {
-    // raw static | instance with no args
-    if func.params.len() == 1 {
-        match &func.params[0].0 {
-            FnParam::Receiver(param) => {
-                Some(ParamsInfo::MethodNoArgNoReturn(param.tk_mut.is_some()))
-            }
-            FnParam::Typed(param) => {
-                if is_state_param(&param.ty.tokens) {
-                    Some(ParamsInfo::RawStatic)
-                } else {
-                    None
-                }
-            }
-        }
-    // raw method | method with single arg
-    } else if func.params.len() == 2 {
-        let Some(is_mut) = is_self_param(&func.params[0].0) else { return None; };
-        let FnParam::Typed(second_param) = &func.params[1].0 else { return None; };
-        if is_state_param(&second_param.ty.tokens) {
-            Some(ParamsInfo::RawMethod(is_mut))
-        } else {
-            None
-        }
-    // method | static => not yet
-    } else {
-        if let Some(is_mut) = is_self_param(&func.params[0].0) {
-            let (args_ty, return_ty) = get_args_ty(&func);
-            Some(ParamsInfo::Method(is_mut, args_ty, return_ty))
-        } else {
-            let (args_ty, return_ty) = get_args_ty(&func);
-            Some(ParamsInfo::Static(args_ty, return_ty))
-        }
+    let params = func
+        .params
+        .iter()
+        .map(|(param, _)| param)
+        .collect::<Vec<_>>();
+    let receiver = match params.first() {
+        Some(param @ FnParam::Receiver(_)) => Some(is_self_param(param)?),
+        _ => None,
+    };
+    let rest = &params[receiver.is_some() as usize..];
+    let raw = rest.len() == 1 && is_state_param(rest[0]);
+
+    match (receiver, raw) {
+        // (&self, &State) | (&mut self, &State)
+        (Some(is_mut), true) => Some(ParamsInfo::RawMethod(is_mut)),
+        // (&State)
+        (None, true) => Some(ParamsInfo::RawStatic),
+        // (&self, ...) | (&mut self, ...)
+        (Some(is_mut), false) => Some(ParamsInfo::Method(is_mut, Args::new(func, rest))),
+        // (...)
+        (None, false) => Some(ParamsInfo::Static(Args::new(func, rest))),
     }
 }
 
@@ -106,160 +200,396 @@ fn params_info(func: &Function) -> Option<ParamsInfo> {
 //         }
 //     }
 // }
-fn get_self_ty(is_mut: bool, ty: &Ident) -> TokenStream {
-    if is_mut {
-        quote!(&mut #ty)
-    } else {
-        quote!(&#ty)
-    }
+// the type of the impl block, generic impls carry their parameters into every generated
+// function since nested functions can't use the outer ones.
+struct Target {
+    ty: TokenStream,
+    params: TokenStream,
+    args: TokenStream,
+    where_clause: TokenStream,
 }
 
-fn gen_raw_method(
-    is_mut: bool,
-    ty_ident: &Ident,
-    fn_ident: &Ident,
-    fn_str: Literal,
-) -> TokenStream {
-    let ty_self = get_self_ty(is_mut, ty_ident);
-
-    quote! {
-        sys::luaL_Reg {
-            name: cstr!(#fn_str),
-            func: {
-                unsafe extern "C" fn step(raw_state: *mut sys::lua_State) -> std::ffi::c_int {
-                    let mut state = State::from_raw(raw_state);
-                    let self_mut_ref = state.cast_to::<#ty_self>(1).unwrap();
-                    let n = #ty_ident::#fn_ident(self_mut_ref, &state);
-                    n as std::ffi::c_int
+impl Target {
+    fn new(ty: &Impl) -> Self {
+        let (params, args, type_params) = match &ty.impl_generic_params {
+            Some(generics) => {
+                if generics.params.iter().any(|(param, _)| param.is_lifetime()) {
+                    panic!("#[user_data] types can't borrow, lifetime parameters aren't allowed.");
                 }
-                Some(step)
-            },
+                let args = generics.params.iter().map(|(param, _)| &param.name);
+                let type_params = generics
+                    .params
+                    .iter()
+                    .filter(|(param, _)| param.is_ty())
+                    .map(|(param, _)| &param.name)
+                    .collect::<Vec<_>>();
+                (
+                    generics.to_token_stream(),
+                    quote!(::<#(#args),*>),
+                    type_params,
+                )
+            }
+            None => (quote!(), quote!(), vec![]),
+        };
+
+        // lua owns the values, `UserData` requires them to be 'static
+        let mut where_clause = ty.where_clause.to_token_stream();
+        if !type_params.is_empty() {
+            let trailing = where_clause.clone().into_iter().last().is_some_and(
+                |token| matches!(token, TokenTree::Punct(punct) if punct.as_char() == ','),
+            );
+            let bounds = quote!(#(#type_params: 'static),*);
+            where_clause = match (ty.where_clause.is_some(), trailing) {
+                (false, _) => quote!(where #bounds),
+                (true, false) => quote!(#where_clause, #bounds),
+                (true, true) => quote!(#where_clause #bounds),
+            };
+        }
+
+        Self {
+            ty: ty.self_ty.to_token_stream(),
+            params,
+            args,
+            where_clause,
         }
     }
-}
 
-// (state: &State)
-fn gen_raw_static(ty_ident: &Ident, fn_ident: &Ident, fn_str: Literal) -> TokenStream {
-    quote! {
-        luajit2_sys::luaL_Reg {
-            name: cstr!(#fn_str),
-            func: {
-                extern "C" fn step(ptr: *mut sys::lua_State) -> std::ffi::c_int {
-                    let state = State::from_raw(ptr);
-                    <#ty_ident>::#fn_ident(&state) as std::ffi::c_int
+    // `Self` isn't visible inside the generated functions
+    fn resolve(&self, tokens: TokenStream) -> TokenStream {
+        tokens
+            .into_iter()
+            .map(|token| match token {
+                TokenTree::Ident(ident) if ident == "Self" => self.ty.clone(),
+                TokenTree::Group(group) => {
+                    let mut resolved = Group::new(group.delimiter(), self.resolve(group.stream()));
+                    resolved.set_span(group.span());
+                    TokenTree::Group(resolved).into_token_stream()
                 }
-                Some(step)
-            },
+                token => token.into_token_stream(),
+            })
+            .collect()
+    }
+
+    // plain paths are named after their last segment, anything with generic arguments is named
+    // at runtime so every instantiation gets its own metatable.
+    fn name(&self) -> TokenStream {
+        let tokens = self.ty.clone().into_iter().collect::<Vec<_>>();
+        let generic = tokens
+            .iter()
+            .any(|token| matches!(token, TokenTree::Punct(punct) if punct.as_char() == '<'));
+        match tokens.last() {
+            Some(TokenTree::Ident(ident)) if !generic => {
+                let name = Literal::string(&ident.to_string());
+                quote!(cstr!(#name))
+            }
+            _ => quote!(State::user_data_name::<Self>()),
+        }
+    }
+
+    // metatable key, unique to the type: the path is qualified with the module of the impl, which
+    // can only resolve it to one type. Generic types use their full type name at runtime.
+    fn key(&self) -> TokenStream {
+        let tokens = self.ty.clone().into_iter().collect::<Vec<_>>();
+        let generic = tokens
+            .iter()
+            .any(|token| matches!(token, TokenTree::Punct(punct) if punct.as_char() == '<'));
+        if generic {
+            return quote!(State::user_data_key::<Self>());
+        }
+
+        let path = Literal::string(&self.ty.to_string().replace(' ', ""));
+        quote!(cstr!(concat!(module_path!(), "::", #path)))
+    }
+
+    fn self_ty(&self, is_mut: bool) -> TokenStream {
+        let ty = &self.ty;
+        if is_mut {
+            quote!(&mut #ty)
+        } else {
+            quote!(&#ty)
         }
     }
+
+    // C function running `body` inside `State::protect`
+    fn step(&self, body: TokenStream) -> TokenStream {
+        self.step_with(quote!(State::protect), body)
+    }
+
+    // C function running `body` inside `State::protect_async`, yielding the future it pushed
+    fn step_async(&self, body: TokenStream) -> TokenStream {
+        self.step_with(quote!(State::protect_async), body)
+    }
+
+    fn step_with(&self, protect: TokenStream, body: TokenStream) -> TokenStream {
+        let Target {
+            params,
+            args,
+            where_clause,
+            ..
+        } = self;
+        quote! {{
+            unsafe extern "C-unwind" fn step #params (ptr: *mut sys::lua_State) -> std::ffi::c_int
+            #where_clause
+            {
+                #protect(ptr, |state| {
+                    #body
+                })
+            }
+            State::cfunction(step #args)
+        }}
+    }
 }
 
-fn gen_static(
-    ident: &Ident,
-    fn_ident: &Ident,
-    fn_str: Literal,
-    args_ty: TokenStream,
-    return_ty: TokenStream,
-) -> TokenStream {
+fn gen_reg(name: Literal, func: TokenStream) -> TokenStream {
     quote! {
-        luajit2_sys::luaL_Reg {
-            name: cstr!(#fn_str),
-            func: {
-                extern "C" fn step(ptr: *mut sys::lua_State) -> std::ffi::c_int {
-                    let state = State::from_raw(ptr);
-                    let len = <#args_ty as FromLua>::len();
-                    let idx = len * -1;
-
-                    let args = state.cast_to::<#args_ty>(idx).unwrap();
-                    let result = <#ident>::#fn_ident(args);
-
-                    state.push(result);
-                    <#return_ty as ToLua>::len() as std::ffi::c_int
-                }
-                Some(step)
-            },
+        sys::luaL_Reg {
+            name: cstr!(#name),
+            func: #func,
         }
     }
 }
 
+// (&self, state: &State) | (&mut self, state: &State)
+fn gen_raw_method(is_mut: bool, target: &Target, fn_ident: &Ident, fn_str: Literal) -> TokenStream {
+    let ty = &target.ty;
+    let self_ty = target.self_ty(is_mut);
+    gen_reg(
+        fn_str,
+        target.step(quote! {
+            let ud = state.cast_args::<#self_ty>(1)?;
+            Ok(<#ty>::#fn_ident(ud, state) as std::ffi::c_int)
+        }),
+    )
+}
+
+// (state: &State)
+fn gen_raw_static(target: &Target, fn_ident: &Ident, fn_str: Literal) -> TokenStream {
+    let ty = &target.ty;
+    gen_reg(
+        fn_str,
+        target.step(quote!(Ok(<#ty>::#fn_ident(state) as std::ffi::c_int))),
+    )
+}
+
+fn gen_static(target: &Target, fn_ident: &Ident, fn_str: Literal, args: Args) -> TokenStream {
+    let ty = &target.ty;
+    let cast_args = target.resolve(args.cast(1));
+    let names = args.names();
+    let call = target.resolve(args.push_result(quote!(<#ty>::#fn_ident(#(#names),*))));
+    let body = quote! {
+        #cast_args
+        #call
+    };
+
+    gen_reg(
+        fn_str,
+        if args.is_async {
+            target.step_async(body)
+        } else {
+            target.step(body)
+        },
+    )
+}
+
 fn gen_method(
     is_mut: bool,
-    ident: &Ident,
+    target: &Target,
     fn_ident: &Ident,
     fn_str: Literal,
-    args_ty: TokenStream,
-    return_ty: TokenStream,
+    args: Args,
 ) -> TokenStream {
-    let self_ty = get_self_ty(is_mut, ident);
-    quote! {
-        luajit2_sys::luaL_Reg {
-            name: cstr!(#fn_str),
-            func: {
-                extern "C" fn step(ptr: *mut sys::lua_State) -> std::ffi::c_int {
-                    let state = State::from_raw(ptr);
-                    let len = <#args_ty as FromLua>::len() + 1;
-                    let idx = len * -1;
-
-                    let ud = state.cast_to::<#self_ty>(idx).unwrap();
-                    let args = state.cast_to::<#args_ty>(idx + 1).unwrap();
-                    let result = ud.#fn_ident(args);
-
-                    state.push(result);
-                    <#return_ty as ToLua>::len() as std::ffi::c_int
+    let self_ty = target.self_ty(is_mut);
+    let cast_args = target.resolve(args.cast(2));
+    let names = args.names();
+    let call = target.resolve(args.push_result(quote!(ud.#fn_ident(#(#names),*))));
+    let body = quote! {
+        let ud = state.cast_args::<#self_ty>(1)?;
+        #cast_args
+        #call
+    };
+
+    gen_reg(
+        fn_str,
+        if args.is_async {
+            target.step_async(body)
+        } else {
+            target.step(body)
+        },
+    )
+}
+
+// everything `#[user_data(...)]` accepts: std traits implementing metamethods,
+// `fields(name, mut health)` listing properties (`mut` ones can be assigned from lua) and
+// `extend` for blocks adding to the type of another `#[user_data]` block.
+struct Options {
+    traits: TokenStream,
+    fields: Vec<(Ident, bool)>,
+    extend: bool,
+}
+
+fn parse_options(attr: TokenStream) -> Options {
+    let mut options = Options {
+        traits: TokenStream::new(),
+        fields: vec![],
+        extend: false,
+    };
+    let mut tokens = attr.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match (&token, tokens.peek()) {
+            (TokenTree::Ident(ident), Some(TokenTree::Group(group))) if ident == "fields" => {
+                let mut is_mut = false;
+                for token in group.stream() {
+                    match token {
+                        TokenTree::Ident(ident) if ident == "mut" => is_mut = true,
+                        TokenTree::Ident(ident) => {
+                            options.fields.push((ident, is_mut));
+                            is_mut = false;
+                        }
+                        TokenTree::Punct(punct) if punct.as_char() == ',' => {}
+                        _ => panic!("expected fields(name, mut name, ...)."),
+                    }
                 }
-                Some(step)
-            },
+                tokens.next();
+            }
+            (TokenTree::Ident(ident), _) if ident == "extend" => options.extend = true,
+            _ => options.traits.extend([token]),
         }
     }
+    options
 }
 
-fn gen_method_no_arg_no_return(
-    is_mut: bool,
-    ud_ty: &Ident,
-    fn_ident: &Ident,
-    fn_str: Literal,
-) -> TokenStream {
-    let self_ty = get_self_ty(is_mut, ud_ty);
+// every lua name becomes an associated const of the type, so a name registered twice, even
+// from different blocks, fails to compile as a duplicate definition.
+fn guard(name: &str, span: Span) -> Ident {
+    format_ident!("__lua_{}", name, span = span)
+}
+
+// getters push a clone of the field, setters convert the value at 3 to the field type.
+fn gen_property(target: &Target, field: &Ident, is_mut: bool) -> TokenStream {
+    let name = Literal::string(&field.to_string());
+    let self_ty = target.self_ty(false);
+    let get = target.step(quote! {
+        let ud = state.cast_args::<#self_ty>(1)?;
+        state.push(ud.#field.clone());
+        Ok(1)
+    });
+    let set = if is_mut {
+        let self_ty = target.self_ty(true);
+        target.step(quote! {
+            let ud = state.cast_args::<#self_ty>(1)?;
+            ud.#field = state.cast_arg(3)?;
+            Ok(0)
+        })
+    } else {
+        quote!(None)
+    };
+
     quote! {
-        luajit2_sys::luaL_Reg {
-            name: cstr!(#fn_str),
-            func: {
-                extern "C" fn step(ptr: *mut sys::lua_State) -> std::ffi::c_int {
-                    let state = State::from_raw(ptr);
-                    let ud = state.cast_to::<#self_ty>(-1).unwrap();
-                    ud.#fn_ident();
-                    0
-                }
-                Some(step)
-            },
+        Property {
+            name: cstr!(#name),
+            get: #get,
+            set: #set,
         }
     }
 }
 
-pub fn gen_user_data_impl(ty: Impl) -> TokenStream {
-    let ty_ident = {
-        if let TokenTree::Ident(ident) = ty.self_ty.tokens[0].clone() {
-            ident
-        } else {
-            unreachable!()
+// metamethods implemented through the std traits listed in `#[user_data(...)]`, operators
+// take their operands by value so they need `Clone`.
+fn gen_meta_traits(
+    target: &Target,
+    attr: TokenStream,
+    guards: &mut Vec<Ident>,
+) -> Vec<TokenStream> {
+    let self_ty = target.self_ty(false);
+    let binary = |op: TokenStream| {
+        quote! {
+            let (a, b) = state.cast_args::<(#self_ty, #self_ty)>(1)?;
+            state.push(#op);
+            Ok(1)
         }
     };
+    let unary = |op: TokenStream| {
+        quote! {
+            let a = state.cast_args::<#self_ty>(1)?;
+            state.push(#op);
+            Ok(1)
+        }
+    };
+
+    let mut decls = vec![];
+    for token in attr {
+        let TokenTree::Ident(ident) = token else {
+            continue;
+        };
+        let metas = match ident.to_string().as_str() {
+            "Add" => vec![("__add", binary(quote!(a.clone() + b.clone())))],
+            "Sub" => vec![("__sub", binary(quote!(a.clone() - b.clone())))],
+            "Mul" => vec![("__mul", binary(quote!(a.clone() * b.clone())))],
+            "Div" => vec![("__div", binary(quote!(a.clone() / b.clone())))],
+            "Rem" => vec![("__mod", binary(quote!(a.clone() % b.clone())))],
+            "PartialEq" => vec![("__eq", binary(quote!(a == b)))],
+            "PartialOrd" => vec![
+                ("__lt", binary(quote!(a < b))),
+                ("__le", binary(quote!(a <= b))),
+            ],
+            "Neg" => vec![("__unm", unary(quote!(-a.clone())))],
+            "Display" => vec![("__tostring", unary(quote!(a.to_string())))],
+            other => panic!("`{other}` can't be used as a metamethod."),
+        };
+
+        for (name, body) in metas {
+            guards.push(guard(name, ident.span()));
+            decls.push(gen_reg(Literal::string(name), target.step(body)));
+        }
+    }
+    decls
+}
+
+pub fn gen_user_data_impl(attr: TokenStream, mut ty: Impl) -> TokenStream {
+    let target = Target::new(&ty);
+
+    let options = parse_options(attr);
+    let mut guards = options
+        .fields
+        .iter()
+        .map(|(field, _)| guard(&field.to_string(), field.span()))
+        .collect::<Vec<_>>();
+    let properties = options
+        .fields
+        .iter()
+        .map(|(field, is_mut)| gen_property(&target, field, *is_mut))
+        .collect::<Vec<_>>();
 
     let mut decls: Vec<TokenStream> = vec![];
+    let mut meta_decls = gen_meta_traits(&target, options.traits, &mut guards);
     for item in ty.body_items.iter() {
         if let ImplMember::Method(func) = item {
-            // only collect public methods
-            if func.vis_marker.is_none() {
+            let options = lua_options(func);
+            let constructor = options.iter().any(|option| option == "constructor");
+            let meta = options
+                .iter()
+                .filter(|option| *option != "constructor")
+                .map(|option| meta_name(option))
+                .next();
+
+            // only collect public methods, metamethods and constructors
+            if func.vis_marker.is_none() && meta.is_none() && !constructor {
                 continue;
             }
 
-            // no async method allowed
-            if func.qualifiers.tk_async.is_some() {
-                panic!("async functions not allowed.")
+            let is_async = func.qualifiers.tk_async.is_some();
+            if is_async && (meta.is_some() || constructor) {
+                panic!("metamethods and constructors can't be async.");
             }
 
             let fn_ident = &func.name;
-            let fn_str = proc_macro2::Literal::string(&fn_ident.to_string());
+            // constructors are published as `new`
+            let lua_name = match meta {
+                Some(meta) => meta.to_string(),
+                None if constructor => "new".to_string(),
+                None => fn_ident.to_string(),
+            };
+            let fn_str = proc_macro2::Literal::string(&lua_name);
 
             // raw methods:
             // - (&self, &State)
@@ -274,25 +604,45 @@ pub fn gen_user_data_impl(ty: Impl) -> TokenStream {
             //
             // static:
             // - (...)
-            // - (...)
+            //
+            // trailing `Option<T>` args can be left out by the caller.
 
-            if let Some(info) = params_info(&func) {
-                match info {
-                    ParamsInfo::RawMethod(is_mut) => {
-                        decls.push(gen_raw_method(is_mut, &ty_ident, fn_ident, fn_str))
+            if let Some(info) = params_info(func) {
+                if constructor && matches!(info, ParamsInfo::Method(..) | ParamsInfo::RawMethod(_))
+                {
+                    panic!("constructors can't take self.");
+                }
+                if is_async {
+                    match info {
+                        ParamsInfo::RawMethod(_) | ParamsInfo::RawStatic => {
+                            panic!("async functions can't take &State.")
+                        }
+                        // other calls may run on the value while the future waits
+                        ParamsInfo::Method(true, _) => {
+                            panic!("async methods can't take &mut self.")
+                        }
+                        _ => {}
                     }
-                    ParamsInfo::RawStatic => {
-                        decls.push(gen_raw_static(&ty_ident, fn_ident, fn_str))
+                }
+
+                let decl = match info {
+                    ParamsInfo::RawMethod(is_mut) => {
+                        gen_raw_method(is_mut, &target, fn_ident, fn_str)
                     }
-                    ParamsInfo::Static(args_ty, return_ty) => {
-                        decls.push(gen_static(&ty_ident, fn_ident, fn_str, args_ty, return_ty))
+                    ParamsInfo::RawStatic => gen_raw_static(&target, fn_ident, fn_str),
+                    ParamsInfo::Static(args) => gen_static(&target, fn_ident, fn_str, args),
+                    ParamsInfo::Method(is_mut, args) => {
+                        gen_method(is_mut, &target, fn_ident, fn_str, args)
                     }
-                    ParamsInfo::Method(is_mut, args_ty, return_ty) => decls.push(gen_method(
-                        is_mut, &ty_ident, fn_ident, fn_str, args_ty, return_ty,
-                    )),
-                    ParamsInfo::MethodNoArgNoReturn(is_mut) => decls.push(
-                        gen_method_no_arg_no_return(is_mut, &ty_ident, fn_ident, fn_str),
-                    ),
+                };
+
+                guards.push(guard(&lua_name, fn_ident.span()));
+
+                // metamethods live in the metatable instead of the method table
+                if meta.is_some() {
+                    meta_decls.push(decl);
+                } else {
+                    decls.push(decl);
                 }
             }
 
@@ -300,16 +650,126 @@ pub fn gen_user_data_impl(ty: Impl) -> TokenStream {
         }
     }
 
-    let ty_str = proc_macro2::Literal::string(&ty_ident.to_string());
+    // public consts become fields of the class table
+    let mut constants = vec![];
+    for item in ty.body_items.iter() {
+        if let ImplMember::Constant(constant) = item {
+            if constant.vis_marker.is_none() || constant.tk_const_or_static != "const" {
+                continue;
+            }
+
+            let ty = &target.ty;
+            let const_ident = &constant.name;
+            let const_str = Literal::string(&const_ident.to_string());
+            guards.push(guard(&const_ident.to_string(), const_ident.span()));
+            constants.push(gen_reg(
+                const_str,
+                target.step(quote! {
+                    state.push(<#ty>::#const_ident);
+                    Ok(1)
+                }),
+            ));
+        }
+    }
+
+    // `#[lua]` is only meaningful to this macro
+    for item in ty.body_items.iter_mut() {
+        if let ImplMember::Method(func) = item {
+            func.attributes.retain(|attr| !is_lua_attr(attr));
+        }
+    }
+
+    let Target {
+        ty: self_ty,
+        params,
+        where_clause,
+        ..
+    } = &target;
+    let cfgs = ty
+        .attributes
+        .iter()
+        .filter(|attr| matches!(&attr.path[..], [TokenTree::Ident(ident)] if ident == "cfg"))
+        .collect::<Vec<_>>();
+    let guards = quote! {
+        #(#cfgs)*
+        #[allow(non_upper_case_globals, dead_code)]
+        impl #params #self_ty #where_clause {
+            #(
+                #[doc(hidden)]
+                const #guards: () = ();
+            )*
+        }
+    };
+
+    // extensions are looked up by type name once the metatable is created
+    if options.extend {
+        if !params.is_empty() {
+            panic!("#[user_data(extend)] can't be used on generic impls.");
+        }
+
+        return quote! {
+            #ty
+
+            #guards
+
+            #(#cfgs)*
+            inventory::submit! {
+                State::extension::<#self_ty>(
+                    || vec![#(#decls),*],
+                    || vec![#(#meta_decls),*],
+                    || vec![#(#properties),*],
+                    || vec![#(#constants),*],
+                )
+            }
+        };
+    }
+
+    let properties = (!properties.is_empty()).then(|| {
+        quote! {
+            fn properties() -> Vec<Property> {
+                vec![
+                    #(#properties),*
+                ]
+            }
+        }
+    });
+    let constants = (!constants.is_empty()).then(|| {
+        quote! {
+            fn constants() -> Vec<sys::luaL_Reg> {
+                vec![
+                    #(#constants),*
+                ]
+            }
+        }
+    });
+    let name = target.name();
+    let key = target.key();
 
     quote! {
-        impl UserData for #ty_ident {
-            fn name() -> *const i8 { cstr!(#ty_str) }
+        #ty
+
+        #guards
+
+        impl #params UserData for #self_ty #where_clause {
+            fn name() -> *const i8 { #name }
+            fn key() -> *const i8 { #key }
             fn functions() -> Vec<sys::luaL_Reg> {
                 vec![
                     #(#decls),*
                 ]
             }
+
+            #constants
+        }
+
+        impl #params MetaTable for #self_ty #where_clause {
+            fn meta_methods() -> Vec<sys::luaL_Reg> {
+                vec![
+                    #(#meta_decls),*
+                ]
+            }
+
+            #properties
         }
     }
 }
@@ -0,0 +1,16 @@
+//! Lua module built as a shared object, `require "greeter"` finds `luaopen_greeter` in it.
+
+use lofy::sys;
+use lofy::{state::State, Table};
+use macros::lua_module;
+
+#[lua_module]
+fn greeter(state: &State) -> lofy::Result<Table> {
+    let module = state.create_table(0, 2);
+    module.set(
+        "greet",
+        state.create_function(|name: String| format!("hello, {name}")),
+    )?;
+    module.set("version", 1)?;
+    Ok(module)
+}
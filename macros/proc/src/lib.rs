@@ -11,6 +11,16 @@ pub fn generate_from_lua_tuple_impl(attr: TokenStream) -> TokenStream {
 }
 
 #[proc_macro_attribute]
-pub fn user_data(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    codegen::generate_user_data_impl(item.into()).into()
+pub fn user_data(attr: TokenStream, item: TokenStream) -> TokenStream {
+    codegen::generate_user_data_impl(attr.into(), item.into()).into()
+}
+
+#[proc_macro_attribute]
+pub fn lua_module(attr: TokenStream, item: TokenStream) -> TokenStream {
+    codegen::generate_lua_module(attr.into(), item.into()).into()
+}
+
+#[proc_macro_attribute]
+pub fn lua_function(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    codegen::generate_lua_function(item.into()).into()
 }
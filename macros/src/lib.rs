@@ -20,10 +20,14 @@ macro_rules! lua_func {
         sys::luaL_Reg {
             name: cstr!($name),
             func: {
-                unsafe extern "C" fn trampoline(raw_state: *mut sys::lua_State) -> std::ffi::c_int {
-                    $method(&mut State::from_raw(raw_state)) as std::ffi::c_int
+                unsafe extern "C-unwind" fn trampoline(
+                    raw_state: *mut sys::lua_State,
+                ) -> std::ffi::c_int {
+                    State::protect(raw_state, |_| {
+                        Ok($method(&mut State::from_raw(raw_state)) as std::ffi::c_int)
+                    })
                 }
-                Some(trampoline)
+                State::cfunction(trampoline)
             },
         }
     }};
@@ -35,16 +39,18 @@ macro_rules! lua_method {
         sys::luaL_Reg {
             name: cstr!($name),
             func: {
-                unsafe extern "C" fn trampoline(raw_state: *mut sys::lua_State) -> std::ffi::c_int {
-                    let mut state = State::from_raw(raw_state);
-                    let mut user_data = unsafe { sys::lua_touserdata(raw_state, 1) as *mut $type };
+                unsafe extern "C-unwind" fn trampoline(
+                    raw_state: *mut sys::lua_State,
+                ) -> std::ffi::c_int {
+                    State::protect(raw_state, |_| {
+                        let mut state = State::from_raw(raw_state);
+                        let mut_ref = state.cast_args::<&mut $type>(1)?;
+                        let n = $method(mut_ref, &mut state);
 
-                    let mut_ref = &mut *user_data;
-                    let n = $method(mut_ref, &mut state);
-
-                    n as std::ffi::c_int
+                        Ok(n as std::ffi::c_int)
+                    })
                 }
-                Some(trampoline)
+                State::cfunction(trampoline)
             },
         }
     }};
@@ -0,0 +1,111 @@
+use std::{borrow::Cow, ops::Deref};
+
+use crate::sys;
+use macros::cstr;
+
+/// Registry field set while strings that aren't valid UTF-8 are read lossily.
+const LOSSY_KEY: *const i8 = cstr!("lofy.utf8_lossy");
+
+/// How `String` reads lua strings that aren't valid UTF-8, see
+/// [`State::set_utf8_policy`](crate::state::State::set_utf8_policy). `&str` borrows the string
+/// as it is and always needs valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Policy {
+    /// The conversion fails.
+    #[default]
+    Strict,
+    /// Invalid sequences are replaced with `U+FFFD` in a copy, the lua string is left alone.
+    Lossy,
+}
+
+/// Borrowed byte string, pushed as a lua string without any UTF-8 or NUL checks.
+///
+/// `&[u8]` and `Vec<u8>` convert the same way, the wrappers name the intent where a byte slice
+/// could pass for a sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes<'a>(pub &'a [u8]);
+
+/// Owned byte string, see [`Bytes`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ByteBuf(pub Vec<u8>);
+
+impl<'a> Deref for Bytes<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl Deref for ByteBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> From<&'a [u8]> for Bytes<'a> {
+    fn from(value: &'a [u8]) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Vec<u8>> for ByteBuf {
+    fn from(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+}
+
+impl From<ByteBuf> for Vec<u8> {
+    fn from(value: ByteBuf) -> Self {
+        value.0
+    }
+}
+
+pub(crate) fn push_bytes(ptr: *mut sys::lua_State, bytes: &[u8]) {
+    unsafe { sys::lua_pushlstring(ptr, bytes.as_ptr() as *const i8, bytes.len()) }
+}
+
+/// Reads a string (or a number, converted in place) including embedded NULs.
+pub(crate) fn read_bytes<'a>(ptr: *mut sys::lua_State, idx: i32) -> Option<&'a [u8]> {
+    if unsafe { sys::lua_isstring(ptr, idx) } == 0 {
+        return None;
+    }
+
+    let mut len = 0;
+    let data = unsafe { sys::lua_tolstring(ptr, idx, &mut len) };
+    Some(unsafe { std::slice::from_raw_parts(data as *const u8, len) })
+}
+
+pub(crate) fn set_policy(ptr: *mut sys::lua_State, policy: Utf8Policy) {
+    unsafe {
+        sys::lua_pushboolean(ptr, (policy == Utf8Policy::Lossy) as i32);
+        sys::lua_setfield(ptr, sys::LUA_REGISTRYINDEX, LOSSY_KEY);
+    }
+}
+
+pub(crate) fn policy(ptr: *mut sys::lua_State) -> Utf8Policy {
+    let lossy = unsafe {
+        sys::lua_getfield(ptr, sys::LUA_REGISTRYINDEX, LOSSY_KEY);
+        let lossy = sys::lua_toboolean(ptr, -1) != 0;
+        sys::lua_pop(ptr, 1);
+        lossy
+    };
+    if lossy {
+        Utf8Policy::Lossy
+    } else {
+        Utf8Policy::Strict
+    }
+}
+
+/// Reads a string as UTF-8 following the [`Utf8Policy`] of the state, borrowed when it is valid
+/// and fixed up in an owned copy when lossy.
+pub(crate) fn read_str<'a>(ptr: *mut sys::lua_State, idx: i32) -> Option<Cow<'a, str>> {
+    let bytes = read_bytes(ptr, idx)?;
+    match std::str::from_utf8(bytes) {
+        Ok(str) => Some(Cow::Borrowed(str)),
+        Err(_) if policy(ptr) == Utf8Policy::Lossy => Some(String::from_utf8_lossy(bytes)),
+        Err(_) => None,
+    }
+}
@@ -0,0 +1,203 @@
+use std::{cell::RefCell, ffi::c_int};
+
+use crate::sys;
+use macros::cstr;
+
+use crate::{error::Error, ffi, from_lua::FromLua, state::State, to_lua::ToLua};
+
+/// Type erased closure, returns how many values it pushed or the error to raise.
+type Callback = Box<dyn Fn(&State) -> crate::Result<c_int>>;
+
+pub(crate) fn wrap<A, R, F>(func: F) -> Callback
+where
+    A: for<'x> FromLua<'x, Output = A>,
+    R: ToLua,
+    F: Fn(A) -> R + 'static,
+{
+    Box::new(move |state: &State| {
+        let args = state.cast_args::<A>(1)?;
+        state.try_push_values(func(args))
+    })
+}
+
+/// Same as [`wrap`], calling the closure again while it runs raises an error instead of
+/// aliasing the `&mut`.
+pub(crate) fn wrap_mut<A, R, F>(func: F) -> Callback
+where
+    A: for<'x> FromLua<'x, Output = A>,
+    R: ToLua,
+    F: FnMut(A) -> R + 'static,
+{
+    let func = RefCell::new(func);
+    Box::new(move |state: &State| {
+        let args = state.cast_args::<A>(1)?;
+        let mut func = func
+            .try_borrow_mut()
+            .map_err(|_| Error::runtime("rust function called recursively"))?;
+        let result = func(args);
+        state.try_push_values(result)
+    })
+}
+
+const CLOSURE_KEY: *const i8 = cstr!("lofy.closure");
+
+/// Pushes a C closure owning `callback` through a userdata upvalue, freed by its `__gc`.
+pub(crate) fn push(ptr: *mut sys::lua_State, callback: Callback) {
+    ffi::push_udata(ptr, CLOSURE_KEY, callback, |_| {});
+    unsafe { sys::lua_pushcclosure(ptr, ffi::cfunction(call), 1) };
+}
+
+unsafe extern "C-unwind" fn call(ptr: *mut sys::lua_State) -> c_int {
+    match ffi::get_udata::<Callback>(ptr, ffi::upvalue_index(1), CLOSURE_KEY) {
+        Some(callback) => ffi::protect(ptr, &*callback),
+        None => ffi::raise(ptr, Error::runtime("rust function was dropped")),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::{state::State, LuaFunction};
+
+    #[test]
+    fn call_closure_from_lua() {
+        let state = State::new();
+        let offset = 100;
+        state
+            .set_global_fn("add", move |(a, b): (i32, i32)| a + b + offset)
+            .unwrap();
+        state.do_string("result = add(1, 2)").unwrap();
+
+        assert_eq!(state.get_global::<i32>("result").unwrap(), 103);
+    }
+
+    #[test]
+    fn call_closure_from_rust() {
+        let state = State::new();
+        let greet = state.create_function(|(name, greeting): (String, Option<String>)| {
+            format!("{}, {}", greeting.as_deref().unwrap_or("hello"), name)
+        });
+
+        assert_eq!(
+            greet.call(("soreto".to_string(), None)).unwrap(),
+            "hello, soreto"
+        );
+        let hi = Some("hi".to_string());
+        assert_eq!(
+            greet.call(("soreto".to_string(), hi)).unwrap(),
+            "hi, soreto"
+        );
+        assert_eq!(state.get_top(), 0);
+    }
+
+    #[test]
+    fn closure_without_args() {
+        let state = State::new();
+        let counter = Rc::new(Cell::new(0));
+        let captured = counter.clone();
+        state
+            .set_global_fn("tick", move |()| captured.set(captured.get() + 1))
+            .unwrap();
+        state.do_string("tick(); tick()").unwrap();
+
+        assert_eq!(counter.get(), 2);
+    }
+
+    #[test]
+    fn mutable_closure() {
+        let state = State::new();
+        let mut total = 0;
+        state
+            .set_global_fn_mut("push", move |value: i32| {
+                total += value;
+                total
+            })
+            .unwrap();
+        state
+            .do_string("push(1); push(2); result = push(3)")
+            .unwrap();
+
+        assert_eq!(state.get_global::<i32>("result").unwrap(), 6);
+    }
+
+    #[test]
+    fn mutable_closure_is_not_reentrant() {
+        let state = State::new();
+        state
+            .set_global_fn_mut("reenter", |func: LuaFunction<(), ()>| {
+                func.call(()).err().map(|error| error.to_string())
+            })
+            .unwrap();
+        state
+            .do_string("result = reenter(function() reenter(function() end) end)")
+            .unwrap();
+
+        let result = state.get_global::<String>("result").unwrap();
+        assert!(result.contains("rust function called recursively"));
+    }
+
+    #[test]
+    fn bad_arguments_raise_errors() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("double", |value: i32| value * 2)
+            .unwrap();
+        state.do_string("ok, err = pcall(double, 'foo')").unwrap();
+
+        assert!(!state.get_global::<bool>("ok").unwrap());
+        assert!(state
+            .get_global::<String>("err")
+            .unwrap()
+            .contains("bad argument #1 (i32 expected, got string)"));
+    }
+
+    #[test]
+    fn closure_is_dropped_when_collected() {
+        let state = State::new();
+        state.open_libs();
+        let captured = Rc::new(());
+        let inner = captured.clone();
+        state
+            .set_global_fn("hold", move |()| {
+                let _ = &inner;
+            })
+            .unwrap();
+        assert_eq!(Rc::strong_count(&captured), 2);
+
+        state.do_string("hold = nil; collectgarbage()").unwrap();
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn closure_is_dropped_once() {
+        let state = State::new();
+        state.open_libs();
+        let captured = Rc::new(());
+        let inner = captured.clone();
+        state
+            .set_global_fn("hold", move |()| {
+                let _ = &inner;
+            })
+            .unwrap();
+        state
+            .do_string(
+                r#"
+                local _, data = debug.getupvalue(hold, 1)
+                hidden = getmetatable(data) == false
+                local gc = debug.getmetatable(data).__gc
+                gc(data); gc(data); gc(io.stdout)
+                ok, err = pcall(hold)
+                "#,
+            )
+            .unwrap();
+
+        assert!(state.get_global::<bool>("hidden").unwrap());
+        assert_eq!(Rc::strong_count(&captured), 1);
+        assert!(!state.get_global::<bool>("ok").unwrap());
+        let err = state.get_global::<String>("err").unwrap();
+        assert!(err.contains("rust function was dropped"), "{err}");
+        state.do_string("hold = nil; collectgarbage()").unwrap();
+    }
+}
@@ -0,0 +1,456 @@
+use std::{ffi::c_void, marker::PhantomData};
+
+use crate::sys;
+
+use crate::{
+    error::Error, from_lua::FromLua, reference::Reference, state::State, to_lua::ToLua, LuaFunction,
+};
+
+/// Handle to a lua thread pinned in the registry, so it isn't collected while rust holds it.
+///
+/// Created with [`State::create_thread`] or read from lua (`coroutine.create`), and driven
+/// from rust with [`Coroutine::resume`].
+pub struct Coroutine {
+    reference: Reference,
+    thread: *mut sys::lua_State,
+}
+
+/// What a coroutine did when it was resumed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resume<T> {
+    /// It called `coroutine.yield` with these values and can be resumed again.
+    Yielded(T),
+    /// Its function returned these values.
+    Finished(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    /// Not started yet or waiting in `coroutine.yield`.
+    Suspended,
+    /// Running, or waiting for a coroutine it resumed.
+    Running,
+    /// Its function returned, see [`Coroutine::reset`] to run it again.
+    Finished,
+    /// Its function raised an error.
+    Error,
+}
+
+impl Coroutine {
+    pub(crate) fn new(ptr: *mut sys::lua_State, idx: i32) -> Self {
+        Self {
+            reference: Reference::new(ptr, idx),
+            thread: unsafe { sys::lua_tothread(ptr, idx) },
+        }
+    }
+
+    /// Creates a thread that will run `func` on its first resume.
+    pub(crate) fn create<A, B>(ptr: *mut sys::lua_State, func: &LuaFunction<A, B>) -> Self {
+        let thread = unsafe { sys::lua_newthread(ptr) };
+        func.push(thread);
+        Self {
+            reference: Reference::pop(ptr),
+            thread,
+        }
+    }
+
+    pub(crate) fn push(&self, ptr: *mut sys::lua_State) {
+        self.reference.push(ptr)
+    }
+
+    /// State the thread is anchored in, where its results are moved.
+    pub(crate) fn ptr(&self) -> *mut sys::lua_State {
+        self.reference.ptr()
+    }
+
+    pub(crate) fn to_pointer(&self) -> *const c_void {
+        self.reference.to_pointer()
+    }
+
+    pub fn status(&self) -> CoroutineStatus {
+        let thread = self.thread;
+        let mut frame = unsafe { std::mem::zeroed::<sys::lua_Debug>() };
+        match unsafe { sys::lua_status(thread) } as u32 {
+            sys::LUA_YIELD => CoroutineStatus::Suspended,
+            0 if self.is_main() || unsafe { sys::lua_getstack(thread, 0, &mut frame) } != 0 => {
+                CoroutineStatus::Running
+            }
+            0 if unsafe { sys::lua_gettop(thread) } == 0 => CoroutineStatus::Finished,
+            0 => CoroutineStatus::Suspended,
+            _ => CoroutineStatus::Error,
+        }
+    }
+
+    fn is_main(&self) -> bool {
+        let is_main = unsafe { sys::lua_pushthread(self.thread) } != 0;
+        unsafe { sys::lua_pop(self.thread, 1) };
+        is_main
+    }
+
+    /// Starts the coroutine or continues it from the `coroutine.yield` it is waiting in, which
+    /// returns `args`. Runs until the next yield, the end of the function or an error.
+    ///
+    /// Missing values read as `nil`, the coroutine can only be resumed while
+    /// [`CoroutineStatus::Suspended`]. The results are popped before returning, so only types
+    /// that don't borrow from lua can be read.
+    pub fn resume<A, R>(&self, args: A) -> crate::Result<Resume<<R as FromLua<'static>>::Output>>
+    where
+        A: ToLua,
+        R: for<'x> FromLua<'x, Output: 'static>,
+    {
+        let (finished, top) = self.resume_raw(args, R::len())?;
+        let ptr = self.reference.ptr();
+        let values = R::from_lua(ptr, top + 1);
+        let values = values.ok_or_else(|| Error::conversion::<R>(ptr, top + 1, None));
+        State::from_raw(ptr).set_top(top);
+
+        Ok(match finished {
+            true => Resume::Finished(values?),
+            false => Resume::Yielded(values?),
+        })
+    }
+
+    /// Resumes the thread and moves its results above `top`, at least `len` of them. Returns
+    /// whether it finished and `top`.
+    pub(crate) fn resume_raw(&self, args: impl ToLua, len: i32) -> crate::Result<(bool, i32)> {
+        match self.status() {
+            CoroutineStatus::Suspended => {}
+            CoroutineStatus::Running => {
+                return Err(Error::runtime("cannot resume non-suspended coroutine"))
+            }
+            _ => return Err(Error::runtime("cannot resume dead coroutine")),
+        }
+
+        let thread = State::from_raw(self.thread);
+        let nargs = thread.push_values(args);
+        let status = unsafe { sys::lua_resume(self.thread, nargs) };
+        if status as u32 != sys::LUA_YIELD && status != 0 {
+            return Err(Error::pop(self.thread, status));
+        }
+
+        // move the results out like `coroutine.resume`, handles read from them must not point
+        // into a thread that may be collected first
+        let ptr = self.reference.ptr();
+        let state = State::from_raw(ptr);
+        let top = state.get_top();
+        let count = thread.get_top();
+        unsafe {
+            sys::lua_checkstack(ptr, count.max(len));
+            sys::lua_xmove(self.thread, ptr, count);
+        }
+        (count..len).for_each(|_| state.push(()));
+        Ok((status == 0, top))
+    }
+
+    /// Makes the coroutine run `func` from the start. A thread that finished is reused, one
+    /// that failed or is waiting in a yield can't be rewound and is replaced by a new thread,
+    /// which handles cloned before keep referring to the old one.
+    pub fn reset<A, B>(&mut self, func: &LuaFunction<A, B>) -> crate::Result<()> {
+        match self.status() {
+            CoroutineStatus::Running => Err(Error::runtime("cannot reset a running coroutine")),
+            CoroutineStatus::Finished => {
+                func.push(self.thread);
+                Ok(())
+            }
+            _ => {
+                *self = Coroutine::create(self.reference.ptr(), func);
+                Ok(())
+            }
+        }
+    }
+
+    /// Iterator over the values the coroutine yields, resuming it without arguments each time.
+    /// It ends when the function returns, its results are dropped, or after the first error.
+    pub fn iter<R>(&self) -> Yields<R>
+    where
+        R: for<'x> FromLua<'x, Output: 'static>,
+    {
+        Yields {
+            coroutine: Some(self.clone()),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl Clone for Coroutine {
+    fn clone(&self) -> Self {
+        Self {
+            reference: self.reference.clone(),
+            thread: self.thread,
+        }
+    }
+}
+
+/// Iterator returned by [`Coroutine::iter`].
+pub struct Yields<R> {
+    coroutine: Option<Coroutine>,
+    marker: PhantomData<R>,
+}
+
+impl<R> Iterator for Yields<R>
+where
+    R: for<'x> FromLua<'x, Output: 'static>,
+{
+    type Item = crate::Result<<R as FromLua<'static>>::Output>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let coroutine = self.coroutine.as_ref()?;
+        if coroutine.status() != CoroutineStatus::Suspended {
+            self.coroutine = None;
+            return None;
+        }
+
+        let ptr = coroutine.reference.ptr();
+        match coroutine.resume_raw((), R::len()) {
+            Ok((false, top)) => {
+                let values = R::from_lua(ptr, top + 1)
+                    .ok_or_else(|| Error::conversion::<R>(ptr, top + 1, None));
+                State::from_raw(ptr).set_top(top);
+                Some(values)
+            }
+            Ok((true, top)) => {
+                State::from_raw(ptr).set_top(top);
+                self.coroutine = None;
+                None
+            }
+            Err(error) => {
+                self.coroutine = None;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl<'a> FromLua<'a> for Coroutine {
+    type Output = Coroutine;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        if unsafe { sys::lua_isthread(ptr, idx) } != 0 {
+            Some(Coroutine::new(ptr, idx))
+        } else {
+            None
+        }
+    }
+}
+
+impl ToLua for Coroutine {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        self.push(state)
+    }
+}
+
+impl ToLua for &Coroutine {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        self.push(state)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{CoroutineStatus, Resume};
+    use crate::{state::State, Coroutine, Error, LuaFunction, Value, Variadic};
+
+    fn function(state: &State, code: &str) -> LuaFunction<'static, (), ()> {
+        state.do_string(&format!("return {code}")).unwrap();
+        let func = state.cast_to::<LuaFunction<(), ()>>(-1).unwrap();
+        state.pop(1);
+        func
+    }
+
+    #[test]
+    fn resume_until_finished() {
+        let state = State::new();
+        state.open_libs();
+        let func = function(
+            &state,
+            "function(a, b)
+                local c = coroutine.yield(a + b)
+                local d, e = coroutine.yield(c * 2)
+                return d .. e
+            end",
+        );
+        let thread = state.create_thread(&func);
+        assert_eq!(thread.status(), CoroutineStatus::Suspended);
+
+        assert_eq!(thread.resume::<_, i32>((1, 2)).unwrap(), Resume::Yielded(3));
+        assert_eq!(thread.resume::<_, i32>(5).unwrap(), Resume::Yielded(10));
+        assert_eq!(thread.status(), CoroutineStatus::Suspended);
+        assert_eq!(
+            thread.resume::<_, String>(("x", "y")).unwrap(),
+            Resume::Finished("xy".to_string())
+        );
+        assert_eq!(thread.status(), CoroutineStatus::Finished);
+        assert_eq!(state.get_top(), 0);
+
+        let error = thread.resume::<_, ()>(()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "runtime error: cannot resume dead coroutine"
+        );
+    }
+
+    #[test]
+    fn errors_and_values() {
+        let state = State::new();
+        state.open_libs();
+        let failing = function(&state, "function() coroutine.yield() error('boom', 0) end");
+        let thread = state.create_thread(&failing);
+        assert_eq!(thread.resume::<_, ()>(()).unwrap(), Resume::Yielded(()));
+        let error = thread.resume::<_, ()>(()).unwrap_err();
+        assert!(matches!(error, Error::Runtime { message, .. } if message == "boom"));
+        assert_eq!(thread.status(), CoroutineStatus::Error);
+
+        let multiple = function(&state, "function() coroutine.yield(1, 'a', {}) return end");
+        let thread = state.create_thread(&multiple);
+        let Resume::Yielded(values) = thread.resume::<_, Variadic<Value>>(()).unwrap() else {
+            panic!("expected a yield");
+        };
+        assert_eq!(values.len(), 3);
+        assert!(values[2].as_table().is_some());
+        assert_eq!(
+            thread.resume::<_, Option<i32>>(()).unwrap(),
+            Resume::Finished(None)
+        );
+
+        let typed = function(&state, "function() coroutine.yield('x') end");
+        let thread = state.create_thread(&typed);
+        let error = thread.resume::<_, i32>(()).unwrap_err();
+        assert_eq!(error.to_string(), "i32 expected, got string");
+        assert_eq!(state.get_top(), 0);
+    }
+
+    #[test]
+    fn threads_from_lua() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string(
+                "
+                co = coroutine.create(function(n)
+                    for i = 1, n do coroutine.yield(i) end
+                    return 'done'
+                end)
+                ",
+            )
+            .unwrap();
+
+        let thread = state.get_global::<Coroutine>("co").unwrap();
+        assert_eq!(thread.resume::<_, i32>(2).unwrap(), Resume::Yielded(1));
+        state
+            .do_string("assert(select(2, coroutine.resume(co)) == 2)")
+            .unwrap();
+        assert_eq!(
+            thread.resume::<_, String>(()).unwrap(),
+            Resume::Finished("done".to_string())
+        );
+        state
+            .do_string("assert(coroutine.status(co) == 'dead')")
+            .unwrap();
+    }
+
+    #[test]
+    fn running_coroutines() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("status", |thread: Coroutine| {
+                format!("{:?}", thread.status())
+            })
+            .unwrap();
+        state
+            .set_global_fn("resume", |thread: Coroutine| {
+                thread.resume::<_, ()>(()).unwrap_err().to_string()
+            })
+            .unwrap();
+        state
+            .do_string(
+                "
+                co = coroutine.create(function()
+                    inner, err = status(co), resume(co)
+                end)
+                coroutine.resume(co)
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("inner").unwrap(), "Running");
+        assert_eq!(
+            state.get_global::<String>("err").unwrap(),
+            "runtime error: cannot resume non-suspended coroutine"
+        );
+    }
+
+    #[test]
+    fn reset() {
+        let state = State::new();
+        state.open_libs();
+        let func = function(&state, "function() coroutine.yield(1) return 2 end");
+        let mut thread = state.create_thread(&func);
+        state.set_global("co", &thread).unwrap();
+
+        assert_eq!(thread.resume::<_, i32>(()).unwrap(), Resume::Yielded(1));
+        assert_eq!(thread.resume::<_, i32>(()).unwrap(), Resume::Finished(2));
+        thread.reset(&func).unwrap();
+        assert_eq!(thread.status(), CoroutineStatus::Suspended);
+        assert_eq!(thread.resume::<_, i32>(()).unwrap(), Resume::Yielded(1));
+        // the finished thread was reused
+        assert_eq!(
+            state.get_global::<Value>("co").unwrap(),
+            Value::Thread(thread.clone())
+        );
+
+        // mid-way, a fresh thread takes its place
+        thread.reset(&func).unwrap();
+        assert_ne!(
+            state.get_global::<Value>("co").unwrap(),
+            Value::Thread(thread.clone())
+        );
+        assert_eq!(thread.resume::<_, i32>(()).unwrap(), Resume::Yielded(1));
+
+        let failing = function(&state, "function() error('boom') end");
+        thread.reset(&failing).unwrap();
+        assert!(thread.resume::<_, ()>(()).is_err());
+        thread.reset(&func).unwrap();
+        assert_eq!(thread.resume::<_, i32>(()).unwrap(), Resume::Yielded(1));
+    }
+
+    #[test]
+    fn iterate_yields() {
+        let state = State::new();
+        state.open_libs();
+        let func = function(
+            &state,
+            "function() for i = 1, 3 do coroutine.yield(i * i) end return 'ignored' end",
+        );
+        let thread = state.create_thread(&func);
+        let squares = thread.iter::<i32>().collect::<crate::Result<Vec<_>>>();
+        assert_eq!(squares.unwrap(), vec![1, 4, 9]);
+        assert_eq!(thread.status(), CoroutineStatus::Finished);
+        assert_eq!(thread.iter::<i32>().count(), 0);
+
+        let failing = function(&state, "function() coroutine.yield(1) error('boom', 0) end");
+        let mut yields = state.create_thread(&failing).iter::<i32>();
+        assert_eq!(yields.next().unwrap().unwrap(), 1);
+        assert!(yields.next().unwrap().is_err());
+        assert!(yields.next().is_none());
+    }
+
+    #[test]
+    fn rust_values_survive_collection() {
+        let state = State::new();
+        state.open_libs();
+        let func = function(&state, "function(t) coroutine.yield(t) return t.n end");
+        let thread = state.create_thread(&func);
+        let table = state.create_table(0, 1);
+        table.set("n", 42).unwrap();
+
+        assert!(matches!(
+            thread.resume::<_, Value>(&table).unwrap(),
+            Resume::Yielded(Value::Table(_))
+        ));
+        state.do_string("collectgarbage()").unwrap();
+        assert_eq!(thread.resume::<_, i32>(()).unwrap(), Resume::Finished(42));
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    any::Any,
+    ffi::CStr,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use crate::sys;
+
+use crate::{
+    ffi,
+    from_lua::{abs_index, FromLua},
+    traceback::{self, Frame},
+    user_data,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong when running lua code or exchanging values with it.
+///
+/// Messages are copied out of the lua stack, so the error can outlive the state it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The chunk failed to compile.
+    Syntax(String),
+    /// An error raised while running lua code.
+    Runtime {
+        message: String,
+        /// Call stack at the point of the error, empty when a custom message handler is
+        /// installed.
+        traceback: Vec<Frame>,
+    },
+    /// Lua failed to allocate memory.
+    Memory(String),
+    /// The message handler raised an error of its own.
+    ErrorHandler(String),
+    /// A value couldn't be converted to the requested rust type.
+    Conversion {
+        /// Rust type that was requested.
+        expected: String,
+        /// Lua type that was found, `no value` when it was missing.
+        actual: String,
+        /// 1-based argument position, `None` for anything that isn't an argument.
+        position: Option<i32>,
+    },
+    /// Rust code called from lua panicked.
+    Panic(Panic),
+    /// A rust value couldn't be serialized into a lua value.
+    #[cfg(feature = "serde")]
+    Serialize(String),
+    /// A lua value couldn't be deserialized into the requested rust type.
+    #[cfg(feature = "serde")]
+    Deserialize(String),
+}
+
+/// Panic caught at the boundary between lua and the rust function that raised it.
+///
+/// The original payload travels through lua with the error, so it can be inspected or resumed
+/// once the error comes back out.
+#[derive(Clone)]
+pub struct Panic {
+    message: String,
+    payload: Arc<Mutex<Option<Box<dyn Any + Send>>>>,
+}
+
+impl Panic {
+    pub(crate) fn new(payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "Box<dyn Any>".to_string()
+        };
+
+        Self {
+            message,
+            payload: Arc::new(Mutex::new(Some(payload))),
+        }
+    }
+
+    /// Message the panic was raised with.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Takes the original payload, `None` if it was already taken through a clone.
+    pub fn take_payload(&self) -> Option<Box<dyn Any + Send>> {
+        self.payload.lock().ok()?.take()
+    }
+
+    /// Continues unwinding with the original payload.
+    pub fn resume(self) -> ! {
+        match self.take_payload() {
+            Some(payload) => std::panic::resume_unwind(payload),
+            None => panic!("{}", self.message),
+        }
+    }
+}
+
+impl fmt::Debug for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Panic").field(&self.message).finish()
+    }
+}
+
+impl PartialEq for Panic {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.payload, &other.payload)
+    }
+}
+
+impl Eq for Panic {}
+
+impl Error {
+    /// Pops the error object left by a failed `lua_pcall`/`luaL_load*` with `status`.
+    pub(crate) fn pop(ptr: *mut sys::lua_State, status: i32) -> Self {
+        let traceback = traceback::unwrap(ptr).unwrap_or_default();
+        if let Some(panic) = ffi::get_udata::<Panic>(ptr, -1, ffi::PANIC_KEY) {
+            let panic = unsafe { (*panic).clone() };
+            unsafe { sys::lua_pop(ptr, 1) };
+            return Error::Panic(panic);
+        }
+
+        let message = message(ptr, -1);
+        unsafe { sys::lua_pop(ptr, 1) };
+
+        match status as u32 {
+            sys::LUA_ERRSYNTAX => Error::Syntax(message),
+            sys::LUA_ERRMEM => Error::Memory(message),
+            sys::LUA_ERRERR => Error::ErrorHandler(message),
+            _ => Error::Runtime { message, traceback },
+        }
+    }
+
+    /// Error raised from rust code, without a traceback.
+    pub(crate) fn runtime(message: impl Into<String>) -> Self {
+        Error::Runtime {
+            message: message.into(),
+            traceback: vec![],
+        }
+    }
+
+    /// Describes why the values starting at `idx` don't convert to `T`. `position` is the
+    /// argument position of `idx`, if it holds arguments.
+    pub(crate) fn conversion<'a, T: FromLua<'a>>(
+        ptr: *mut sys::lua_State,
+        idx: i32,
+        position: Option<i32>,
+    ) -> Self {
+        let idx = abs_index(ptr, idx);
+        let (offset, expected) =
+            T::mismatch(ptr, idx).unwrap_or_else(|| (0, type_name::<T::Output>()));
+
+        Error::Conversion {
+            expected,
+            actual: type_name_at(ptr, idx + offset),
+            position: position.map(|position| position + offset),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Syntax(message) => write!(f, "syntax error: {message}"),
+            Error::Runtime { message, traceback } => {
+                write!(f, "runtime error: {message}")?;
+                if !traceback.is_empty() {
+                    write!(f, "\nstack traceback:")?;
+                }
+                traceback
+                    .iter()
+                    .try_for_each(|frame| write!(f, "\n\t{frame}"))
+            }
+            Error::Memory(message) => write!(f, "memory error: {message}"),
+            Error::ErrorHandler(message) => write!(f, "error in error handling: {message}"),
+            Error::Conversion {
+                expected,
+                actual,
+                position: Some(position),
+            } => write!(
+                f,
+                "bad argument #{position} ({expected} expected, got {actual})"
+            ),
+            Error::Conversion {
+                expected,
+                actual,
+                position: None,
+            } => write!(f, "{expected} expected, got {actual}"),
+            Error::Panic(panic) => write!(f, "rust panic: {}", panic.message),
+            #[cfg(feature = "serde")]
+            Error::Serialize(message) => write!(f, "serialize error: {message}"),
+            #[cfg(feature = "serde")]
+            Error::Deserialize(message) => write!(f, "deserialize error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Reads an error object as a message, non-string objects are described by their type.
+fn message(ptr: *mut sys::lua_State, idx: i32) -> String {
+    if unsafe { sys::lua_isstring(ptr, idx) } != 0 {
+        crate::bytes::read_bytes(ptr, idx)
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+            .unwrap_or_default()
+    } else {
+        format!("(error object is a {} value)", type_name_at(ptr, idx))
+    }
+}
+
+/// Lua type of the value at `idx`, userdata pushed from rust are named after their type.
+fn type_name_at(ptr: *mut sys::lua_State, idx: i32) -> String {
+    if let Some(name) = user_data::describe(ptr, idx) {
+        return name;
+    }
+
+    let name = unsafe { CStr::from_ptr(sys::lua_typename(ptr, sys::lua_type(ptr, idx))) };
+    name.to_string_lossy().into_owned()
+}
+
+/// `std::any::type_name` without module paths, `Option<alloc::string::String>` becomes
+/// `Option<String>`.
+pub(crate) fn type_name<T: ?Sized>() -> String {
+    let mut name = String::new();
+    let mut segment = 0;
+    let mut chars = std::any::type_name::<T>().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            name.truncate(segment);
+        } else {
+            name.push(c);
+            if !c.is_alphanumeric() && c != '_' {
+                segment = name.len();
+            }
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{state::State, Error, LuaFunction};
+
+    #[test]
+    fn syntax_error() {
+        let state = State::new();
+        let error = state.do_string("local = 1").unwrap_err();
+
+        assert!(matches!(error, Error::Syntax(_)));
+        assert_eq!(state.get_top(), 0);
+    }
+
+    #[test]
+    fn runtime_error() {
+        let state = State::new();
+        state.open_libs();
+        let error = state.do_string("error('boom')").unwrap_err();
+
+        assert!(matches!(&error, Error::Runtime { message, .. } if message.ends_with("boom")));
+        assert!(error.to_string().starts_with("runtime error: "));
+    }
+
+    #[test]
+    fn non_string_error_object() {
+        let state = State::new();
+        state.open_libs();
+        let error = state.do_string("error({})").unwrap_err();
+
+        assert!(matches!(
+            error,
+            Error::Runtime { message, .. } if message == "(error object is a table value)"
+        ));
+    }
+
+    #[test]
+    fn output_conversion_error() {
+        let state = State::new();
+        state
+            .do_string("function pair() return 1, 'two' end")
+            .unwrap();
+
+        let pair = state
+            .get_global::<LuaFunction<(), (i32, i32)>>("pair")
+            .unwrap();
+        assert_eq!(
+            pair.call(()).unwrap_err(),
+            Error::Conversion {
+                expected: "i32".to_string(),
+                actual: "string".to_string(),
+                position: None,
+            }
+        );
+    }
+
+    #[test]
+    fn argument_conversion_error() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("repeat_str", |(text, count): (String, u32)| {
+                text.repeat(count as usize)
+            })
+            .unwrap();
+        state
+            .do_string("ok, err = pcall(repeat_str, 'a', {})")
+            .unwrap();
+
+        assert_eq!(
+            state.get_global::<String>("err").unwrap(),
+            "bad argument #2 (u32 expected, got table)"
+        );
+    }
+
+    #[test]
+    fn composes_with_anyhow() {
+        fn run(state: &State) -> anyhow::Result<()> {
+            state.do_string("return nil + 1")?;
+            Ok(())
+        }
+
+        let state = State::new();
+        let error = run(&state).unwrap_err();
+        assert!(error.downcast_ref::<Error>().is_some());
+    }
+
+    #[test]
+    fn panics_are_recoverable() {
+        #[derive(Debug, PartialEq)]
+        struct Custom(i32);
+
+        let state = State::new();
+        state
+            .set_global_fn("explode", |()| -> i32 { std::panic::panic_any(Custom(42)) })
+            .unwrap();
+
+        let Error::Panic(panic) = state.do_string("explode()").unwrap_err() else {
+            panic!("expected a panic");
+        };
+        assert_eq!(panic.message(), "Box<dyn Any>");
+
+        let payload = std::panic::catch_unwind(|| panic.resume()).unwrap_err();
+        assert_eq!(payload.downcast_ref::<Custom>(), Some(&Custom(42)));
+    }
+
+    #[test]
+    fn panics_seen_from_lua() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("explode", |()| -> i32 { panic!("boom") })
+            .unwrap();
+        let error = state
+            .do_string("local ok, err = pcall(explode); message = tostring(err); error(err)")
+            .unwrap_err();
+
+        assert!(matches!(error, Error::Panic(_)));
+        assert_eq!(
+            state.get_global::<String>("message").unwrap(),
+            "rust panic: boom"
+        );
+    }
+
+    #[test]
+    fn panic_metatable_is_hidden() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("explode", |()| -> i32 { panic!("boom") })
+            .unwrap();
+        state
+            .do_string(
+                r#"
+                local ok, err = pcall(explode)
+                hidden = getmetatable(err) == false
+                local gc = debug.getmetatable(err).__gc
+                gc(err); gc(err); gc("not a panic")
+                message = tostring(err)
+                "#,
+            )
+            .unwrap();
+
+        assert!(state.get_global::<bool>("hidden").unwrap());
+        assert_eq!(state.get_global::<String>("message").unwrap(), "panic");
+    }
+
+    #[test]
+    fn short_type_names() {
+        assert_eq!(super::type_name::<String>(), "String");
+        assert_eq!(
+            super::type_name::<Option<Vec<String>>>(),
+            "Option<Vec<String>>"
+        );
+        assert_eq!(
+            super::type_name::<std::collections::HashMap<String, i32>>(),
+            "HashMap<String, i32>"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    ffi::{c_int, c_void},
+    mem::size_of,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::sys;
+use macros::cstr;
+
+use crate::{
+    error::{Error, Panic},
+    from_lua::abs_index,
+    state::State,
+    to_lua::is_valid_key,
+    user_data, RawFunction,
+};
+
+pub(crate) const PANIC_KEY: *const i8 = cstr!("lofy.panic");
+
+/// C function allowed to unwind. LuaJIT raises errors by unwinding the native stack, which
+/// aborts the process when it crosses a plain `extern "C"` rust frame.
+pub(crate) type UnwindFunction = unsafe extern "C-unwind" fn(*mut sys::lua_State) -> c_int;
+
+extern "C-unwind" {
+    /// `lua_error` as declared by luajit2-sys can't unwind into the caller.
+    pub(crate) fn lua_error(state: *mut sys::lua_State) -> c_int;
+
+    /// Raises an error when the coroutine can't yield, see `lua_error`.
+    pub(crate) fn lua_yield(state: *mut sys::lua_State, nresults: c_int) -> c_int;
+
+    /// Raises the errors of `__index`, only called through [`pcall`].
+    fn lua_gettable(state: *mut sys::lua_State, idx: c_int);
+
+    /// Raises the errors of `__newindex` and for nil or NaN keys, only called through [`pcall`].
+    fn lua_settable(state: *mut sys::lua_State, idx: c_int);
+
+    /// Raises an error when the key isn't in the table, only called through [`pcall`].
+    fn lua_next(state: *mut sys::lua_State, idx: c_int) -> c_int;
+
+    #[cfg(test)]
+    pub(crate) fn luaL_error(state: *mut sys::lua_State, fmt: *const i8, ...) -> c_int;
+}
+
+pub(crate) const fn upvalue_index(idx: i32) -> i32 {
+    sys::LUA_GLOBALSINDEX - idx
+}
+
+/// luajit2-sys types every C function as `extern "C"`, the ABI is the same so only the unwinding
+/// guarantees are lost.
+pub(crate) fn cfunction(func: UnwindFunction) -> sys::lua_CFunction {
+    Some(unsafe { std::mem::transmute::<UnwindFunction, RawFunction>(func) })
+}
+
+/// Pushes `value` as a full userdata dropped by its `__gc`, read back with [`get_udata`]. `key`
+/// names the metatable in the registry, `init` can add more fields to it when it's created.
+/// Scripts can't get to the metatable, `getmetatable` returns `false`.
+pub(crate) fn push_udata<T>(
+    ptr: *mut sys::lua_State,
+    key: *const i8,
+    value: T,
+    init: impl FnOnce(*mut sys::lua_State),
+) {
+    unsafe {
+        let data = sys::lua_newuserdata(ptr, size_of::<Option<T>>()) as *mut Option<T>;
+        data.write(Some(value));
+
+        if sys::luaL_newmetatable(ptr, key) != 0 {
+            sys::lua_pushlightuserdata(ptr, key as *mut c_void);
+            sys::lua_pushcclosure(ptr, cfunction(drop_udata::<T>), 1);
+            sys::lua_setfield(ptr, -2, cstr!("__gc"));
+            sys::lua_pushboolean(ptr, 0);
+            sys::lua_setfield(ptr, -2, cstr!("__metatable"));
+            init(ptr);
+        }
+        sys::lua_setmetatable(ptr, -2);
+    }
+}
+
+/// The value pushed by [`push_udata`] at `idx`, `None` for other values or once it was dropped.
+pub(crate) fn get_udata<T>(ptr: *mut sys::lua_State, idx: i32, key: *const i8) -> Option<*mut T> {
+    let data = test_udata::<Option<T>>(ptr, idx, key)?;
+    unsafe { (*data).as_mut().map(|value| value as *mut T) }
+}
+
+/// Drops the value once, whatever `__gc` is called on. The key of the metatable is the upvalue.
+unsafe extern "C-unwind" fn drop_udata<T>(ptr: *mut sys::lua_State) -> c_int {
+    let key = sys::lua_touserdata(ptr, upvalue_index(1)) as *const i8;
+    if let Some(data) = test_udata::<Option<T>>(ptr, 1, key) {
+        drop((*data).take());
+    }
+    0
+}
+
+/// `luaL_testudata` from lua 5.2, the userdata at `idx` if its metatable is the one registered
+/// under `key`.
+pub(crate) fn test_udata<T>(ptr: *mut sys::lua_State, idx: i32, key: *const i8) -> Option<*mut T> {
+    unsafe {
+        let data = sys::lua_touserdata(ptr, idx);
+        if data.is_null() || sys::lua_getmetatable(ptr, idx) == 0 {
+            return None;
+        }
+        sys::lua_getfield(ptr, sys::LUA_REGISTRYINDEX, key);
+        let matches = sys::lua_rawequal(ptr, -1, -2) != 0;
+        sys::lua_pop(ptr, 2);
+        matches.then_some(data as *mut T)
+    }
+}
+
+/// Calls `f` in protected mode with the `nargs` values on top of the stack at 1, 2, ... and keeps
+/// `nresults` of the values it says it returned. Lua errors raised by `f` are returned instead of
+/// unwinding through the caller.
+///
+/// `f` must not panic: a panic can't unwind through the lua frames in between and aborts.
+pub(crate) fn pcall(
+    ptr: *mut sys::lua_State,
+    nargs: i32,
+    nresults: i32,
+    f: impl FnOnce(*mut sys::lua_State) -> c_int,
+) -> crate::Result<()> {
+    unsafe extern "C-unwind" fn trampoline<F: FnOnce(*mut sys::lua_State) -> c_int>(
+        ptr: *mut sys::lua_State,
+    ) -> c_int {
+        let f = &mut *(sys::lua_touserdata(ptr, upvalue_index(1)) as *mut Option<F>);
+        f.take().map_or(0, |f| f(ptr))
+    }
+
+    fn push_trampoline<F: FnOnce(*mut sys::lua_State) -> c_int>(
+        ptr: *mut sys::lua_State,
+        f: &mut Option<F>,
+    ) {
+        unsafe {
+            sys::lua_pushlightuserdata(ptr, f as *mut Option<F> as *mut c_void);
+            sys::lua_pushcclosure(ptr, cfunction(trampoline::<F>), 1);
+        }
+    }
+
+    let mut f = Some(f);
+    push_trampoline(ptr, &mut f);
+    unsafe { sys::lua_insert(ptr, -nargs - 1) };
+    match unsafe { sys::lua_pcall(ptr, nargs, nresults, 0) } {
+        0 => Ok(()),
+        status => Err(Error::pop(ptr, status)),
+    }
+}
+
+/// Whether the value at `idx` is a table without a metatable, which the raw functions can access
+/// without raising errors.
+fn is_plain_table(ptr: *mut sys::lua_State, idx: i32) -> bool {
+    unsafe {
+        if sys::lua_istable(ptr, idx) == 0 {
+            return false;
+        }
+        if sys::lua_getmetatable(ptr, idx) == 0 {
+            return true;
+        }
+        sys::lua_pop(ptr, 1);
+        false
+    }
+}
+
+/// `lua_gettable` returning the errors of `__index`: replaces the key on top with `t[key]`, or
+/// pops it on error.
+pub(crate) fn get_table(ptr: *mut sys::lua_State, idx: i32) -> crate::Result<()> {
+    let idx = abs_index(ptr, idx);
+    if is_plain_table(ptr, idx) {
+        unsafe { sys::lua_rawget(ptr, idx) };
+        return Ok(());
+    }
+
+    unsafe {
+        sys::lua_pushvalue(ptr, idx);
+        sys::lua_insert(ptr, -2);
+    }
+    pcall(ptr, 2, 1, |ptr| unsafe {
+        lua_gettable(ptr, 1);
+        1
+    })
+}
+
+/// `lua_settable` returning the errors of `__newindex` and of invalid keys, the key and value on
+/// top are popped either way.
+pub(crate) fn set_table(ptr: *mut sys::lua_State, idx: i32) -> crate::Result<()> {
+    let idx = abs_index(ptr, idx);
+    if is_plain_table(ptr, idx) && is_valid_key(ptr, -2) {
+        unsafe { sys::lua_rawset(ptr, idx) };
+        return Ok(());
+    }
+
+    unsafe {
+        sys::lua_pushvalue(ptr, idx);
+        sys::lua_insert(ptr, -3);
+    }
+    pcall(ptr, 3, 0, |ptr| unsafe {
+        lua_settable(ptr, 1);
+        0
+    })
+}
+
+/// `lua_next` returning the error raised for a key that isn't in the table: pops the key on top
+/// and pushes the next key and value if there's one.
+pub(crate) fn next(ptr: *mut sys::lua_State, idx: i32) -> crate::Result<bool> {
+    let idx = abs_index(ptr, idx);
+    let top = unsafe { sys::lua_gettop(ptr) } - 1;
+    unsafe {
+        sys::lua_pushvalue(ptr, idx);
+        sys::lua_insert(ptr, -2);
+    }
+    pcall(ptr, 2, sys::LUA_MULTRET, |ptr| unsafe {
+        if lua_next(ptr, 1) != 0 {
+            2
+        } else {
+            0
+        }
+    })?;
+    Ok(unsafe { sys::lua_gettop(ptr) } > top)
+}
+
+/// Runs the body of a C function called by lua. Errors and panics are only raised once `f` has
+/// returned: lua errors can't unwind through `catch_unwind`, so `f` itself must not raise them.
+/// The lua functions that may raise are called through [`pcall`] for that reason.
+pub(crate) fn protect(
+    ptr: *mut sys::lua_State,
+    f: impl FnOnce(&State) -> crate::Result<c_int>,
+) -> c_int {
+    let result =
+        user_data::scope(|| panic::catch_unwind(AssertUnwindSafe(|| f(&State::from_raw(ptr)))));
+    match result {
+        Ok(Ok(n)) => n,
+        Ok(Err(error)) => raise(ptr, error),
+        Err(payload) => raise(ptr, Error::Panic(Panic::new(payload))),
+    }
+}
+
+/// Raises `error` as a lua error. Panics are raised as userdata so the payload survives the trip
+/// through lua.
+pub(crate) fn raise(ptr: *mut sys::lua_State, error: Error) -> c_int {
+    let state = State::from_raw(ptr);
+    match error {
+        Error::Panic(panic) => push_udata(ptr, PANIC_KEY, panic, |ptr| unsafe {
+            sys::lua_pushcfunction(ptr, cfunction(panic_to_string));
+            sys::lua_setfield(ptr, -2, cstr!("__tostring"));
+        }),
+        Error::Runtime { message, .. } => state.push(message),
+        error => state.push(error.to_string()),
+    }
+    unsafe { lua_error(ptr) }
+}
+
+unsafe extern "C-unwind" fn panic_to_string(ptr: *mut sys::lua_State) -> c_int {
+    let message = match get_udata::<Panic>(ptr, 1, PANIC_KEY) {
+        Some(panic) => Error::Panic((*panic).clone()).to_string(),
+        None => "panic".to_string(),
+    };
+    State::from_raw(ptr).push(message);
+    1
+}
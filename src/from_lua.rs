@@ -1,7 +1,14 @@
-use std::ffi::CStr;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    hash::{BuildHasher, Hash},
+};
 
-use crate::{state::State, to_lua::ToLua, LuaFunction, RelativeValue, UserData};
-use luajit2_sys as sys;
+use crate::sys;
+use crate::{
+    bytes, error, int64, state::State, to_lua::ToLua, user_data, ByteBuf, Bytes, Int64,
+    LuaFunction, RelativeValue, Table, UInt64, UserData,
+};
 use macros::generate_from_lua_tuple_impl;
 
 pub trait FromLua<'a> {
@@ -12,6 +19,79 @@ pub trait FromLua<'a> {
     fn len() -> i32 {
         1
     }
+
+    /// Finds the value that makes `from_lua` fail, as its offset from `idx` and the name of the
+    /// expected type. Multiple values report the first one that doesn't convert.
+    fn mismatch(ptr: *mut sys::lua_State, idx: i32) -> Option<(i32, String)> {
+        match Self::from_lua(ptr, idx) {
+            Some(_) => None,
+            None => Some((0, error::type_name::<Self::Output>())),
+        }
+    }
+
+    /// Reads a `Vec` of this type, a sequence unless overridden.
+    #[doc(hidden)]
+    fn read_vec(ptr: *mut sys::lua_State, idx: i32) -> Option<Vec<Self::Output>>
+    where
+        Self: Sized,
+    {
+        read_sequence::<Self>(ptr, idx)?.collect()
+    }
+}
+
+/// Turns a relative index into an absolute one so it survives pushes, pseudo indices are kept.
+pub(crate) fn abs_index(ptr: *mut sys::lua_State, idx: i32) -> i32 {
+    if idx < 0 && idx > sys::LUA_REGISTRYINDEX {
+        unsafe { sys::lua_gettop(ptr) + idx + 1 }
+    } else {
+        idx
+    }
+}
+
+fn read_sequence<'a, T: FromLua<'a>>(
+    ptr: *mut sys::lua_State,
+    idx: i32,
+) -> Option<impl Iterator<Item = Option<T::Output>>> {
+    if unsafe { sys::lua_istable(ptr, idx) } == 0 {
+        return None;
+    }
+
+    let idx = abs_index(ptr, idx);
+    let len = unsafe { sys::lua_objlen(ptr, idx) } as i32;
+    Some((1..=len).map(move |i| unsafe {
+        sys::lua_rawgeti(ptr, idx, i);
+        let value = T::from_lua(ptr, -1);
+        sys::lua_pop(ptr, 1);
+        value
+    }))
+}
+
+fn read_map<'a, K: FromLua<'a>, V: FromLua<'a>>(
+    ptr: *mut sys::lua_State,
+    idx: i32,
+    mut f: impl FnMut(K::Output, V::Output),
+) -> Option<()> {
+    if unsafe { sys::lua_istable(ptr, idx) } == 0 {
+        return None;
+    }
+
+    let idx = abs_index(ptr, idx);
+    unsafe { sys::lua_pushnil(ptr) };
+    while unsafe { sys::lua_next(ptr, idx) } != 0 {
+        // convert a copy of the key, `lua_tolstring` would change it in place and break `lua_next`
+        unsafe { sys::lua_pushvalue(ptr, -2) };
+        let entry = (K::from_lua(ptr, -1), V::from_lua(ptr, -2));
+        unsafe { sys::lua_pop(ptr, 2) };
+
+        match entry {
+            (Some(key), Some(value)) => f(key, value),
+            _ => {
+                unsafe { sys::lua_pop(ptr, 1) };
+                return None;
+            }
+        }
+    }
+    Some(())
 }
 
 impl<'a, T> FromLua<'a> for RelativeValue<T>
@@ -30,15 +110,49 @@ where
     }
 }
 
-impl<'a> FromLua<'a> for i32 {
-    type Output = i32;
+// integral numbers and 64-bit cdata are accepted as long as they fit the target type.
+macro_rules! impl_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl<'a> FromLua<'a> for $ty {
+                type Output = $ty;
+
+                fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+                    int64::read_integer(ptr, idx)?.try_into().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, i128, isize, u16, u32, u64, u128, usize);
+
+impl<'a> FromLua<'a> for u8 {
+    type Output = u8;
 
     fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
-        if unsafe { sys::lua_isnumber(ptr, idx) != 0 } {
-            Some(unsafe { sys::lua_tonumber(ptr, idx) } as i32)
-        } else {
-            None
-        }
+        int64::read_integer(ptr, idx)?.try_into().ok()
+    }
+
+    /// `Vec<u8>` is read from a string, like `ByteBuf`.
+    fn read_vec(ptr: *mut sys::lua_State, idx: i32) -> Option<Vec<Self::Output>> {
+        bytes::read_bytes(ptr, idx).map(<[u8]>::to_vec)
+    }
+}
+
+impl<'a> FromLua<'a> for Int64 {
+    type Output = Int64;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        Some(Int64(int64::read_integer(ptr, idx)?.try_into().ok()?))
+    }
+}
+
+impl<'a> FromLua<'a> for UInt64 {
+    type Output = UInt64;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        Some(UInt64(int64::read_integer(ptr, idx)?.try_into().ok()?))
     }
 }
 
@@ -78,31 +192,75 @@ impl<'a> FromLua<'a> for bool {
     }
 }
 
+/// Borrows the lua string, which must be valid UTF-8 whatever the policy of the state. Use
+/// `String` or `Cow<str>` to read invalid strings lossily or `&[u8]` to get the raw content.
 impl<'a> FromLua<'a> for &str {
     type Output = &'a str;
 
     fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
-        if unsafe { sys::lua_isstring(ptr, idx) != 0 } {
-            let ptr = unsafe { sys::lua_tostring(ptr, idx) };
-            let cstr = unsafe { CStr::from_ptr(ptr) };
-            cstr.to_str().ok()
-        } else {
-            None
-        }
+        std::str::from_utf8(bytes::read_bytes(ptr, idx)?).ok()
+    }
+
+    fn mismatch(ptr: *mut sys::lua_State, idx: i32) -> Option<(i32, String)> {
+        utf8_mismatch::<Self>(ptr, idx)
     }
 }
 
+/// Strings that aren't valid UTF-8 follow the [`Utf8Policy`] of the state: rejected when strict,
+/// fixed up when lossy.
+///
+/// [`Utf8Policy`]: crate::Utf8Policy
 impl<'a> FromLua<'a> for String {
     type Output = String;
 
     fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
-        if unsafe { sys::lua_isstring(ptr, idx) != 0 } {
-            let ptr = unsafe { sys::lua_tostring(ptr, idx) };
-            let cstr = unsafe { CStr::from_ptr(ptr) };
-            Some(cstr.to_str().ok()?.to_string())
-        } else {
-            None
-        }
+        bytes::read_str(ptr, idx).map(Cow::into_owned)
+    }
+
+    fn mismatch(ptr: *mut sys::lua_State, idx: i32) -> Option<(i32, String)> {
+        utf8_mismatch::<Self>(ptr, idx)
+    }
+}
+
+/// Tells strings rejected by the strict policy apart from values that aren't strings at all.
+fn utf8_mismatch<'a, T: FromLua<'a>>(ptr: *mut sys::lua_State, idx: i32) -> Option<(i32, String)> {
+    match bytes::read_bytes(ptr, idx) {
+        Some(_) if T::from_lua(ptr, idx).is_none() => Some((0, "valid UTF-8".to_string())),
+        Some(_) => None,
+        None => Some((0, error::type_name::<T::Output>())),
+    }
+}
+
+/// Lossy, invalid UTF-8 sequences are replaced with `U+FFFD`.
+impl<'a> FromLua<'a> for Cow<'a, str> {
+    type Output = Cow<'a, str>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        Some(String::from_utf8_lossy(bytes::read_bytes(ptr, idx)?))
+    }
+}
+
+impl<'a> FromLua<'a> for Bytes<'a> {
+    type Output = Bytes<'a>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        bytes::read_bytes(ptr, idx).map(Bytes)
+    }
+}
+
+impl<'a> FromLua<'a> for &[u8] {
+    type Output = &'a [u8];
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        bytes::read_bytes(ptr, idx)
+    }
+}
+
+impl<'a> FromLua<'a> for ByteBuf {
+    type Output = ByteBuf;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        bytes::read_bytes(ptr, idx).map(|bytes| ByteBuf(bytes.to_vec()))
     }
 }
 
@@ -110,7 +268,7 @@ impl<'a> FromLua<'a> for () {
     type Output = ();
 
     fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
-        if unsafe { sys::lua_isnil(ptr, idx) != 0 } {
+        if unsafe { sys::lua_isnoneornil(ptr, idx) != 0 } {
             Some(())
         } else {
             None
@@ -118,15 +276,34 @@ impl<'a> FromLua<'a> for () {
     }
 }
 
+/// Nil and missing values (past the top of the stack) are read as `None`.
+impl<'a, T: FromLua<'a>> FromLua<'a> for Option<T> {
+    type Output = Option<T::Output>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        if unsafe { sys::lua_isnoneornil(ptr, idx) != 0 } {
+            Some(None)
+        } else {
+            T::from_lua(ptr, idx).map(Some)
+        }
+    }
+
+    fn len() -> i32 {
+        T::len()
+    }
+}
+
 impl<'a, T: UserData + 'a> FromLua<'a> for &'a T {
     type Output = &'a T;
 
     fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
-        if unsafe { sys::lua_isuserdata(ptr, idx) != 0 } {
-            let ud_ptr = unsafe { sys::lua_touserdata(ptr, idx) };
-            unsafe { (ud_ptr as *const T).as_ref() }
-        } else {
-            None
+        user_data::get::<T>(ptr, idx).map(|value| unsafe { &*value })
+    }
+
+    fn mismatch(ptr: *mut sys::lua_State, idx: i32) -> Option<(i32, String)> {
+        match Self::from_lua(ptr, idx) {
+            Some(_) => None,
+            None => Some((0, user_data::name::<T>())),
         }
     }
 }
@@ -135,36 +312,133 @@ impl<'a, T: UserData + 'a> FromLua<'a> for &'a mut T {
     type Output = &'a mut T;
 
     fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
-        if unsafe { sys::lua_isuserdata(ptr, idx) != 0 } {
-            let ud_ptr = unsafe { sys::lua_touserdata(ptr, idx) };
-            unsafe { (ud_ptr as *mut T).as_mut() }
-        } else {
-            None
+        user_data::get_mut::<T>(ptr, idx).map(|value| unsafe { &mut *value })
+    }
+
+    fn mismatch(ptr: *mut sys::lua_State, idx: i32) -> Option<(i32, String)> {
+        match Self::from_lua(ptr, idx) {
+            Some(_) => None,
+            None => Some((0, user_data::name::<T>())),
         }
     }
 }
 
-impl<'a, A, B> FromLua<'a> for LuaFunction<'a, A, B>
+impl<'x, 'a, A, B> FromLua<'x> for LuaFunction<'a, A, B>
 where
     A: ToLua,
     B: FromLua<'a>,
 {
-    type Output = Box<dyn Fn(A) -> Result<B::Output, &'a str>>;
-
-    fn from_lua(ptr: *mut sys::lua_State, _: i32) -> Option<Self::Output> {
-        Some(Box::new(move |args: A| {
-            A::to_lua(args, ptr);
-            if unsafe { sys::lua_pcall(ptr, A::len(), B::len(), 0) } != 0 {
-                let msg = <&str as FromLua<'a>>::from_lua(ptr, -1).unwrap();
-                Err(msg)
-            } else {
-                if let Some(value) = B::from_lua(ptr, B::len() * -1) {
-                    Ok(value)
-                } else {
-                    Err("Failed to cast output.")
-                }
+    type Output = LuaFunction<'a, A, B>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        if unsafe { sys::lua_isfunction(ptr, idx) != 0 } {
+            Some(LuaFunction::new(ptr, idx))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> FromLua<'a> for Table {
+    type Output = Table;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        if unsafe { sys::lua_istable(ptr, idx) != 0 } {
+            Some(Table::new(ptr, idx))
+        } else {
+            None
+        }
+    }
+}
+
+// Elements are popped once converted, so collections only hold types that don't borrow from lua.
+impl<'a, T> FromLua<'a> for Vec<T>
+where
+    T: for<'x> FromLua<'x, Output: 'static>,
+{
+    type Output = Vec<<T as FromLua<'a>>::Output>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        T::read_vec(ptr, idx)
+    }
+}
+
+impl<'a, T> FromLua<'a> for VecDeque<T>
+where
+    T: for<'x> FromLua<'x, Output: 'static>,
+{
+    type Output = VecDeque<<T as FromLua<'a>>::Output>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        read_sequence::<T>(ptr, idx)?.collect()
+    }
+}
+
+/// Only succeeds when the sequence has exactly `N` elements.
+impl<'a, T, const N: usize> FromLua<'a> for [T; N]
+where
+    T: for<'x> FromLua<'x, Output: 'static>,
+{
+    type Output = [<T as FromLua<'a>>::Output; N];
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        let values = read_sequence::<T>(ptr, idx)?.collect::<Option<Vec<_>>>()?;
+        values.try_into().ok()
+    }
+}
+
+impl<'a, K, V, S> FromLua<'a> for HashMap<K, V, S>
+where
+    K: for<'x> FromLua<'x, Output: 'static>,
+    V: for<'x> FromLua<'x, Output: 'static>,
+    <K as FromLua<'a>>::Output: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    type Output = HashMap<<K as FromLua<'a>>::Output, <V as FromLua<'a>>::Output, S>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        let mut map = HashMap::default();
+        read_map::<K, V>(ptr, idx, |key, value| {
+            map.insert(key, value);
+        })?;
+        Some(map)
+    }
+}
+
+impl<'a, K, V> FromLua<'a> for BTreeMap<K, V>
+where
+    K: for<'x> FromLua<'x, Output: 'static>,
+    V: for<'x> FromLua<'x, Output: 'static>,
+    <K as FromLua<'a>>::Output: Ord,
+{
+    type Output = BTreeMap<<K as FromLua<'a>>::Output, <V as FromLua<'a>>::Output>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        let mut map = BTreeMap::new();
+        read_map::<K, V>(ptr, idx, |key, value| {
+            map.insert(key, value);
+        })?;
+        Some(map)
+    }
+}
+
+/// Read from `{ [value] = true }`, keys mapped to `false` are left out.
+impl<'a, T, S> FromLua<'a> for HashSet<T, S>
+where
+    T: for<'x> FromLua<'x, Output: 'static>,
+    <T as FromLua<'a>>::Output: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    type Output = HashSet<<T as FromLua<'a>>::Output, S>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        let mut set = HashSet::default();
+        read_map::<T, bool>(ptr, idx, |value, present| {
+            if present {
+                set.insert(value);
             }
-        }))
+        })?;
+        Some(set)
     }
 }
 
@@ -0,0 +1,60 @@
+use std::{ffi::c_void, marker::PhantomData};
+
+use crate::sys;
+
+use crate::{
+    error::Error, from_lua::FromLua, reference::Reference, state::State, to_lua::ToLua, traceback,
+};
+
+/// Handle to a lua function pinned in the registry.
+///
+/// The function stays alive for as long as the handle (or any of its clones) exists, so it can
+/// be stored and called any number of times regardless of what happens to the stack.
+pub struct LuaFunction<'a, A, B>(Reference, PhantomData<&'a A>, PhantomData<B>);
+
+impl<'a, A, B> LuaFunction<'a, A, B> {
+    pub(crate) fn new(ptr: *mut sys::lua_State, idx: i32) -> Self {
+        Self(Reference::new(ptr, idx), PhantomData, PhantomData)
+    }
+
+    pub(crate) fn push(&self, ptr: *mut sys::lua_State) {
+        self.0.push(ptr)
+    }
+
+    pub(crate) fn to_pointer(&self) -> *const c_void {
+        self.0.to_pointer()
+    }
+}
+
+impl<'a, A: ToLua, B> LuaFunction<'a, A, B>
+where
+    B: for<'x> FromLua<'x, Output: 'static>,
+{
+    /// Calls the function in protected mode, leaving the stack as it was. The results are popped
+    /// before returning, so only types that don't borrow from lua can be read (`String` rather
+    /// than `&str`).
+    pub fn call(&self, args: A) -> crate::Result<<B as FromLua<'a>>::Output> {
+        let ptr = self.0.ptr();
+        let state = State::from_raw(ptr);
+        let top = state.get_top();
+
+        traceback::insert_handler(ptr, top + 1);
+        self.push(ptr);
+        let nargs = state.push_values(args);
+        let result = match unsafe { sys::lua_pcall(ptr, nargs, B::len(), top + 1) } {
+            0 => {
+                B::from_lua(ptr, top + 2).ok_or_else(|| Error::conversion::<B>(ptr, top + 2, None))
+            }
+            status => Err(Error::pop(ptr, status)),
+        };
+
+        state.set_top(top);
+        result
+    }
+}
+
+impl<'a, A, B> Clone for LuaFunction<'a, A, B> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), PhantomData, PhantomData)
+    }
+}
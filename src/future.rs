@@ -0,0 +1,419 @@
+use std::{
+    ffi::{c_int, c_void},
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::sys;
+use macros::cstr;
+
+use crate::{
+    coroutine::Coroutine, error::Error, ffi, from_lua::FromLua, reference::Reference, state::State,
+    to_lua::ToLua, user_data, LuaFunction,
+};
+
+const FUTURE_KEY: *const i8 = cstr!("lofy.future");
+
+/// First value yielded by async functions, tells their future apart from regular yields.
+static MARKER: u8 = 0;
+
+fn marker() -> *mut c_void {
+    &MARKER as *const u8 as *mut c_void
+}
+
+/// Pushes the results of a completed future onto the stack of the coroutine that awaited it.
+struct Push<'a>(Box<dyn FnOnce(&State) -> c_int + 'a>);
+
+/// Future of an async function waiting to be polled by [`AsyncCall`], its borrows are erased.
+type Pending = Option<Pin<Box<dyn Future<Output = Push<'static>>>>>;
+
+impl ToLua for Push<'_> {
+    fn to_lua(self, ptr: *mut sys::lua_State) {
+        (self.0)(&State::from_raw(ptr));
+    }
+
+    /// Only known once pushed, see `Variadic`.
+    fn len() -> i32 {
+        0
+    }
+}
+
+/// Pushes the marker and `future` as a userdata, which also keeps the arguments of the call
+/// alive as the future may borrow from them. Userdata arguments are marked as borrowed until the
+/// future completes or is dropped, so they can't be mutated or moved out from under it.
+pub(crate) unsafe fn push<'a, F>(ptr: *mut sys::lua_State, future: F) -> c_int
+where
+    F: Future + 'a,
+    F::Output: ToLua + 'a,
+{
+    type Borrowing<'a> = Pin<Box<dyn Future<Output = Push<'a>> + 'a>>;
+    let args = State::from_raw(ptr).get_top();
+    let borrows = (1..=args)
+        .filter_map(|idx| user_data::borrow(ptr, idx))
+        .collect::<Vec<_>>();
+    let future: Borrowing<'a> = Box::pin(async move {
+        let value = future.await;
+        drop(borrows);
+        Push(Box::new(move |state: &State| state.push_values(value)))
+    });
+    // the borrows live as long as the userdata anchoring the arguments
+    let future = unsafe { std::mem::transmute::<Borrowing<'a>, Borrowing<'static>>(future) };
+    let future: Pending = Some(future);
+
+    unsafe {
+        sys::lua_pushlightuserdata(ptr, marker());
+        ffi::push_udata(ptr, FUTURE_KEY, future, |_| {});
+        sys::lua_createtable(ptr, args, 0);
+        for i in 1..=args {
+            sys::lua_pushvalue(ptr, i);
+            sys::lua_rawseti(ptr, -2, i);
+        }
+        sys::lua_setfenv(ptr, -2);
+    }
+    2
+}
+
+/// Yields the `nresults` values pushed by [`push`] to the coroutine's driver.
+pub(crate) fn yield_future(ptr: *mut sys::lua_State, nresults: c_int) -> c_int {
+    let is_main = unsafe { sys::lua_pushthread(ptr) } != 0;
+    unsafe { sys::lua_pop(ptr, 1) };
+    if is_main {
+        return ffi::raise(
+            ptr,
+            Error::runtime("async function called outside a coroutine"),
+        );
+    }
+    unsafe { ffi::lua_yield(ptr, nresults) }
+}
+
+/// Future returned by [`State::run_async`].
+///
+/// Runs the function in a coroutine. Every async function it calls yields its future, which is
+/// polled with the context of this one, and the coroutine is resumed with the output once it's
+/// ready. Plain `coroutine.yield` calls give control back to the executor and continue on the
+/// next poll: like `yield_now` in async runtimes the task wakes itself right away, so a script
+/// that keeps yielding keeps the executor busy rather than waiting for an event.
+pub struct AsyncCall<'a, A, R> {
+    thread: Coroutine,
+    args: Option<A>,
+    pending: Option<(Reference, *mut Pending)>,
+    marker: PhantomData<&'a R>,
+}
+
+impl<'a, A, R> AsyncCall<'a, A, R> {
+    pub(crate) fn new(ptr: *mut sys::lua_State, func: &LuaFunction<'a, A, R>, args: A) -> Self {
+        Self {
+            thread: Coroutine::create(ptr, func),
+            args: Some(args),
+            pending: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, A, R> Unpin for AsyncCall<'a, A, R> {}
+
+impl<'a, A, R> Drop for AsyncCall<'a, A, R> {
+    // don't leave a future borrowing the arguments to the collector, which may free them first
+    fn drop(&mut self) {
+        if let Some((_, future)) = self.pending.take() {
+            unsafe { *future = None };
+        }
+    }
+}
+
+/// The results are popped before the future completes, so only types that don't borrow from lua
+/// can be read.
+impl<'a, A, R> Future for AsyncCall<'a, A, R>
+where
+    A: ToLua,
+    R: for<'x> FromLua<'x, Output: 'static>,
+{
+    type Output = crate::Result<<R as FromLua<'static>>::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let ptr = this.thread.ptr();
+        loop {
+            let resumed = match this.pending.take() {
+                Some((reference, future)) => {
+                    let pending = unsafe { &mut *future };
+                    let Some(future) = pending.as_mut() else {
+                        return Poll::Ready(Err(Error::runtime("future polled after completion")));
+                    };
+                    match future.as_mut().poll(cx) {
+                        Poll::Pending => {
+                            this.pending = Some((reference, pending));
+                            return Poll::Pending;
+                        }
+                        Poll::Ready(push) => {
+                            *pending = None;
+                            this.thread.resume_raw(push, R::len())
+                        }
+                    }
+                }
+                None => match this.args.take() {
+                    Some(args) => this.thread.resume_raw(args, R::len()),
+                    None => this.thread.resume_raw((), R::len()),
+                },
+            };
+
+            let state = State::from_raw(ptr);
+            let (finished, top) = match resumed {
+                Ok(resumed) => resumed,
+                Err(error) => return Poll::Ready(Err(error)),
+            };
+            if finished {
+                let values = R::from_lua(ptr, top + 1)
+                    .ok_or_else(|| Error::conversion::<R>(ptr, top + 1, None));
+                state.set_top(top);
+                return Poll::Ready(values);
+            }
+
+            let future = (unsafe { sys::lua_touserdata(ptr, top + 1) } == marker())
+                .then(|| ffi::get_udata::<Pending>(ptr, top + 2, FUTURE_KEY))
+                .flatten();
+            let Some(future) = future else {
+                // a plain yield, polled again as soon as the executor gets to it
+                state.set_top(top);
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            };
+
+            this.pending = Some((Reference::new(ptr, top + 2), future));
+            state.set_top(top);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        future::Future,
+        pin::Pin,
+        rc::Rc,
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::sys;
+    use macros::{cstr, user_data};
+
+    use crate::{
+        from_lua::FromLua, state::State, to_lua::ToLua, LuaFunction, MetaTable, Property, UserData,
+        Value,
+    };
+
+    type Records = Rc<RefCell<HashMap<u32, String>>>;
+
+    /// Ready once the record was stored.
+    struct Lookup {
+        records: Records,
+        id: u32,
+    }
+
+    impl Future for Lookup {
+        type Output = String;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<String> {
+            match self.records.borrow_mut().remove(&self.id) {
+                Some(record) => Poll::Ready(record),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    struct Store {
+        records: Records,
+        hits: i32,
+    }
+
+    #[user_data(fields(mut hits))]
+    impl Store {
+        pub fn clear(&mut self) {
+            self.records.borrow_mut().clear();
+        }
+
+        pub async fn fetch(&self, id: u32) -> String {
+            Lookup {
+                records: self.records.clone(),
+                id,
+            }
+            .await
+        }
+
+        pub async fn label(&self, prefix: &str, id: u32) -> String {
+            format!("{prefix}{}", self.fetch(id).await)
+        }
+
+        pub async fn ready(value: i32) -> i32 {
+            value
+        }
+    }
+
+    fn setup() -> (State, Records) {
+        let state = State::new();
+        state.open_libs();
+        let records = Records::default();
+        state
+            .set_global(
+                "store",
+                Store {
+                    records: records.clone(),
+                    hits: 0,
+                },
+            )
+            .unwrap();
+        (state, records)
+    }
+
+    fn function<A: ToLua, R: FromLua<'static>>(
+        state: &State,
+        code: &str,
+    ) -> LuaFunction<'static, A, R> {
+        state.do_string(&format!("return {code}")).unwrap();
+        let func = state.cast_to::<LuaFunction<A, R>>(-1).unwrap();
+        state.pop(1);
+        func
+    }
+
+    fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        Pin::new(future).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn await_futures() {
+        let (state, records) = setup();
+        let func =
+            function::<u32, String>(&state, "function(id) return store:fetch(id) .. '!' end");
+
+        let mut call = state.run_async(&func, 1);
+        assert!(poll(&mut call).is_pending());
+        assert!(poll(&mut call).is_pending());
+        records.borrow_mut().insert(1, "first".to_string());
+        match poll(&mut call) {
+            Poll::Ready(result) => assert_eq!(result.unwrap(), "first!"),
+            Poll::Pending => panic!("expected the call to finish"),
+        }
+        assert_eq!(state.get_top(), 0);
+    }
+
+    #[test]
+    fn borrowed_arguments() {
+        let (state, records) = setup();
+        records.borrow_mut().insert(2, "second".to_string());
+        let func = function::<(), (String, i32)>(
+            &state,
+            "function() return store:label(string.rep('#', 2), 2), store.ready(3) end",
+        );
+
+        let mut call = state.run_async(&func, ());
+        match poll(&mut call) {
+            Poll::Ready(result) => assert_eq!(result.unwrap(), ("##second".to_string(), 3)),
+            Poll::Pending => panic!("expected the call to finish"),
+        }
+    }
+
+    #[test]
+    fn plain_yields() {
+        let (state, _) = setup();
+        let func = function::<(), i32>(&state, "function() coroutine.yield(1) return 5 end");
+
+        let mut call = state.run_async(&func, ());
+        assert!(poll(&mut call).is_pending());
+        match poll(&mut call) {
+            Poll::Ready(result) => assert_eq!(result.unwrap(), 5),
+            Poll::Pending => panic!("expected the call to finish"),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let (state, records) = setup();
+        let error = state.do_string("store:fetch(1)").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("async function called outside a coroutine"));
+
+        let func = function::<(), ()>(
+            &state,
+            "function() error('failed after ' .. store:fetch(1)) end",
+        );
+        let mut call = state.run_async(&func, ());
+        assert!(poll(&mut call).is_pending());
+        records.borrow_mut().insert(1, "first".to_string());
+        match poll(&mut call) {
+            Poll::Ready(result) => assert!(result
+                .unwrap_err()
+                .to_string()
+                .contains("failed after first")),
+            Poll::Pending => panic!("expected the call to fail"),
+        }
+
+        let func = function::<(), ()>(&state, "function() store:fetch('one') end");
+        let mut call = state.run_async(&func, ());
+        assert!(matches!(poll(&mut call), Poll::Ready(Err(_))));
+    }
+
+    #[test]
+    fn pending_calls_borrow_the_receiver() {
+        let (state, records) = setup();
+        let func = function::<u32, String>(&state, "function(id) return store:fetch(id) end");
+        let check = "
+            clear_ok, clear_err = pcall(store.clear, store)
+            set_ok = pcall(function() store.hits = 1 end)
+        ";
+
+        let mut call = state.run_async(&func, 1);
+        assert!(poll(&mut call).is_pending());
+        state.do_string(check).unwrap();
+        assert!(!state.get_global::<bool>("clear_ok").unwrap());
+        assert_eq!(
+            state.get_global::<String>("clear_err").unwrap(),
+            "bad argument #1 (Store expected, got borrowed Store)"
+        );
+        assert!(!state.get_global::<bool>("set_ok").unwrap());
+        state.get_global::<Value>("store").unwrap();
+        assert!(state.take::<Store>(-1).is_none());
+        state.pop(1);
+
+        records.borrow_mut().insert(1, "first".to_string());
+        assert!(matches!(poll(&mut call), Poll::Ready(Ok(_))));
+        state.do_string(check).unwrap();
+        assert!(state.get_global::<bool>("clear_ok").unwrap());
+        assert!(state.get_global::<bool>("set_ok").unwrap());
+
+        // dropping a pending call releases the borrow too
+        let mut call = state.run_async(&func, 2);
+        assert!(poll(&mut call).is_pending());
+        drop(call);
+        state.do_string("collectgarbage()").unwrap();
+        state.get_global::<Value>("store").unwrap();
+        assert!(state.take::<Store>(-1).is_some());
+    }
+
+    #[test]
+    fn interleaved_calls() {
+        let (state, records) = setup();
+        let func = function::<u32, String>(&state, "function(id) return store:fetch(id) end");
+
+        let mut first = state.run_async(&func, 1);
+        let mut second = state.run_async(&func, 2);
+        let mut dropped = state.run_async(&func, 3);
+        assert!(poll(&mut first).is_pending());
+        assert!(poll(&mut second).is_pending());
+        assert!(poll(&mut dropped).is_pending());
+        drop(dropped);
+
+        records.borrow_mut().insert(2, "second".to_string());
+        assert!(poll(&mut first).is_pending());
+        assert!(matches!(poll(&mut second), Poll::Ready(Ok(record)) if record == "second"));
+
+        state.do_string("collectgarbage()").unwrap();
+        records.borrow_mut().insert(1, "first".to_string());
+        assert!(matches!(poll(&mut first), Poll::Ready(Ok(record)) if record == "first"));
+    }
+}
@@ -0,0 +1,283 @@
+use std::ffi::{c_int, CString};
+
+use crate::sys;
+use macros::cstr;
+
+use crate::{error::Error, from_lua::abs_index};
+
+/// `int64_t` boxed cdata, whatever the value. `i64` is only boxed past 2^53, smaller values are
+/// pushed as numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Int64(pub i64);
+
+/// `uint64_t` boxed cdata, see [`Int64`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct UInt64(pub u64);
+
+pub(crate) const LUA_TCDATA: c_int = 10;
+
+const HELPER_KEY: *const i8 = cstr!("lofy.int64");
+
+const HELPER: &str = r#"
+local ffi = ...
+local int64, uint64 = ffi.typeof("int64_t"), ffi.typeof("uint64_t")
+local istype = ffi.istype
+return {
+    int64,
+    uint64,
+    function(value)
+        if istype(int64, value) then return 1 end
+        if istype(uint64, value) then return 2 end
+        return 0
+    end,
+}
+"#;
+
+extern "C" {
+    fn luaopen_ffi(state: *mut sys::lua_State) -> c_int;
+}
+
+/// Integers up to 2^53 are exactly representable as doubles, along with every one below them.
+pub(crate) const MAX_SAFE_INTEGER: u64 = 1 << 53;
+
+/// Pushes the helper table, loading the ffi library on first use. On error nothing is pushed.
+fn push_helper(ptr: *mut sys::lua_State) -> crate::Result<()> {
+    let top = unsafe { sys::lua_gettop(ptr) };
+    let fail = |status| {
+        let error = Error::pop(ptr, status);
+        unsafe { sys::lua_settop(ptr, top) };
+        Err(error)
+    };
+
+    unsafe {
+        sys::lua_getfield(ptr, sys::LUA_REGISTRYINDEX, HELPER_KEY);
+        if sys::lua_istable(ptr, -1) != 0 {
+            return Ok(());
+        }
+        sys::lua_pop(ptr, 1);
+
+        // reuse the module if `open_libs` already loaded it, opening it twice resets its state
+        sys::lua_getfield(ptr, sys::LUA_REGISTRYINDEX, cstr!("_LOADED"));
+        if sys::lua_istable(ptr, -1) != 0 {
+            sys::lua_getfield(ptr, -1, cstr!("ffi"));
+            sys::lua_remove(ptr, -2);
+        }
+        if sys::lua_istable(ptr, -1) == 0 {
+            sys::lua_pop(ptr, 1);
+            sys::lua_pushcfunction(ptr, Some(luaopen_ffi));
+            match sys::lua_pcall(ptr, 0, 1, 0) {
+                0 => {}
+                status => return fail(status),
+            }
+        }
+
+        let code = CString::new(HELPER).unwrap();
+        match sys::luaL_loadstring(ptr, code.as_ptr()) {
+            0 => {}
+            status => return fail(status),
+        }
+        sys::lua_insert(ptr, -2);
+        match sys::lua_pcall(ptr, 1, 1, 0) {
+            0 => {}
+            status => return fail(status),
+        }
+
+        sys::lua_pushvalue(ptr, -1);
+        sys::lua_setfield(ptr, sys::LUA_REGISTRYINDEX, HELPER_KEY);
+    }
+    Ok(())
+}
+
+fn push_cdata<T>(ptr: *mut sys::lua_State, ctype: i32, value: T) -> crate::Result<()> {
+    push_helper(ptr)?;
+    unsafe {
+        sys::lua_rawgeti(ptr, -1, ctype);
+        sys::lua_remove(ptr, -2);
+        match sys::lua_pcall(ptr, 0, 1, 0) {
+            0 => {}
+            status => return Err(Error::pop(ptr, status)),
+        }
+
+        let payload = sys::lua_topointer(ptr, -1) as *mut T;
+        payload.write_unaligned(value);
+    }
+    Ok(())
+}
+
+/// On error nothing is pushed.
+pub(crate) fn push_int64(ptr: *mut sys::lua_State, value: i64) -> crate::Result<()> {
+    push_cdata(ptr, 1, value)
+}
+
+/// On error nothing is pushed.
+pub(crate) fn push_uint64(ptr: *mut sys::lua_State, value: u64) -> crate::Result<()> {
+    push_cdata(ptr, 2, value)
+}
+
+/// Pushes `value` as a number up to 2^53 and as `int64_t` or `uint64_t` cdata past that, so it
+/// keeps every digit.
+///
+/// # Panics
+///
+/// If `value` doesn't fit in 64 bits, or the ffi library fails to load.
+pub(crate) fn push_integer(ptr: *mut sys::lua_State, value: i128) {
+    let pushed = if value.unsigned_abs() <= MAX_SAFE_INTEGER as u128 {
+        unsafe { sys::lua_pushnumber(ptr, value as f64) };
+        Ok(())
+    } else if let Ok(value) = i64::try_from(value) {
+        push_int64(ptr, value)
+    } else if let Ok(value) = u64::try_from(value) {
+        push_uint64(ptr, value)
+    } else {
+        panic!("{value} doesn't fit in 64 bits");
+    };
+
+    if let Err(error) = pushed {
+        panic!("can't push {value} as 64-bit cdata: {error}");
+    }
+}
+
+/// Value of an `int64_t` or `uint64_t` cdata.
+pub(crate) enum Boxed {
+    Int64(i64),
+    UInt64(u64),
+}
+
+/// Reads an `int64_t` or `uint64_t` cdata keeping its type, any other value gives `None`.
+pub(crate) fn read_boxed(ptr: *mut sys::lua_State, idx: i32) -> Option<Boxed> {
+    if unsafe { sys::lua_type(ptr, idx) } != LUA_TCDATA {
+        return None;
+    }
+
+    let idx = abs_index(ptr, idx);
+    push_helper(ptr).ok()?;
+    let kind = unsafe {
+        sys::lua_rawgeti(ptr, -1, 3);
+        sys::lua_pushvalue(ptr, idx);
+        let kind = if sys::lua_pcall(ptr, 1, 1, 0) == 0 {
+            sys::lua_tointeger(ptr, -1)
+        } else {
+            0
+        };
+        sys::lua_pop(ptr, 2);
+        kind
+    };
+
+    let payload = unsafe { sys::lua_topointer(ptr, idx) };
+    match kind {
+        1 => Some(Boxed::Int64(unsafe {
+            (payload as *const i64).read_unaligned()
+        })),
+        2 => Some(Boxed::UInt64(unsafe {
+            (payload as *const u64).read_unaligned()
+        })),
+        _ => None,
+    }
+}
+
+/// Reads an `int64_t` or `uint64_t` cdata, any other value gives `None`.
+pub(crate) fn read_cdata(ptr: *mut sys::lua_State, idx: i32) -> Option<i128> {
+    match read_boxed(ptr, idx)? {
+        Boxed::Int64(value) => Some(value as i128),
+        Boxed::UInt64(value) => Some(value as i128),
+    }
+}
+
+/// Reads an integral number or a 64-bit cdata, non-integral numbers give `None`.
+pub(crate) fn read_integer(ptr: *mut sys::lua_State, idx: i32) -> Option<i128> {
+    if unsafe { sys::lua_isnumber(ptr, idx) } != 0 {
+        let value = unsafe { sys::lua_tonumber(ptr, idx) };
+        if value.fract() == 0.0 && value.abs() < 2f64.powi(127) {
+            Some(value as i128)
+        } else {
+            None
+        }
+    } else {
+        read_cdata(ptr, idx)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{state::State, Int64, UInt64};
+
+    #[test]
+    fn push_boxed_int64() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("big", Int64(i64::MAX)).unwrap();
+        state.set_global("ubig", UInt64(u64::MAX)).unwrap();
+        state
+            .do_string("kind = type(big); str = tostring(big); ustr = tostring(ubig)")
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("kind").unwrap(), "cdata");
+        assert_eq!(
+            state.get_global::<String>("str").unwrap(),
+            "9223372036854775807LL"
+        );
+        assert_eq!(
+            state.get_global::<String>("ustr").unwrap(),
+            "18446744073709551615ULL"
+        );
+        assert_eq!(state.get_global::<i64>("big").unwrap(), i64::MAX);
+        assert_eq!(
+            state.get_global::<UInt64>("ubig").unwrap(),
+            UInt64(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn read_cdata_created_by_scripts() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string(
+                "id = 0x7fffffffffffffffLL - 1; hash = 0xffffffffffffffffULL; negative = -5LL",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<i64>("id").unwrap(), i64::MAX - 1);
+        assert_eq!(state.get_global::<u64>("hash").unwrap(), u64::MAX);
+        assert!(state.get_global::<i64>("hash").is_err());
+        assert_eq!(state.get_global::<i8>("negative").unwrap(), -5);
+        assert!(state.get_global::<u32>("negative").is_err());
+    }
+
+    #[test]
+    fn large_integers_keep_their_precision() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("safe", 1_i64 << 53).unwrap();
+        state.set_global("big", (1_i64 << 53) + 1).unwrap();
+        state.set_global("ubig", u64::MAX).unwrap();
+        state.set_global("wide", i128::from(i64::MIN)).unwrap();
+        state
+            .do_string("safe_kind, big_kind, ubig_kind = type(safe), type(big), type(ubig)")
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("safe_kind").unwrap(), "number");
+        assert_eq!(state.get_global::<String>("big_kind").unwrap(), "cdata");
+        assert_eq!(state.get_global::<String>("ubig_kind").unwrap(), "cdata");
+        assert_eq!(state.get_global::<i64>("big").unwrap(), (1 << 53) + 1);
+        assert_eq!(state.get_global::<u64>("ubig").unwrap(), u64::MAX);
+        assert_eq!(state.get_global::<i128>("wide").unwrap(), i64::MIN as i128);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit in 64 bits")]
+    fn integers_past_64_bits_panic() {
+        State::new().push(u128::MAX);
+    }
+
+    #[test]
+    fn boxed_int64_without_open_libs() {
+        let state = State::new();
+        state.push(Int64(-1));
+
+        assert!(state.is::<i64>(-1));
+        assert_eq!(state.cast_to::<Int64>(-1).unwrap(), Int64(-1));
+        assert!(state.cast_to::<f64>(-1).is_none());
+        assert_eq!(state.get_top(), 1);
+    }
+}
@@ -1,17 +1,50 @@
+use crate::sys;
 use crate::{
-    from_lua::FromLua, to_lua::ToLua, AnyLuaFunction, AnyUserData, Coroutine, LightUserData,
-    LuaFunction, NativeFunction, Table, UserData,
+    from_lua::FromLua, int64, to_lua::ToLua, user_data, AnyLuaFunction, AnyUserData, ByteBuf,
+    Bytes, Coroutine, Int64, LightUserData, LuaFunction, NativeFunction, Table, UInt64, UserData,
+};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
 };
-use luajit2_sys as sys;
 
 pub trait IsType {
     fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool;
+
+    /// Whether the value is a `Vec` or slice of this type, a table unless overridden.
+    #[doc(hidden)]
+    fn is_vec(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_istable(ptr, idx) != 0 }
+    }
+}
+
+macro_rules! impl_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl IsType for $ty {
+                #[inline]
+                fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+                    let is_number = unsafe { sys::lua_isnumber(ptr, idx) != 0 };
+                    is_number || int64::read_cdata(ptr, idx).is_some()
+                }
+            }
+        )*
+    };
 }
 
-impl IsType for i32 {
+impl_integer!(i8, i16, i32, i64, i128, isize, u16, u32, u64, u128, usize, Int64, UInt64);
+
+impl IsType for u8 {
     #[inline]
     fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
-        unsafe { sys::lua_isnumber(ptr, idx) != 0 }
+        let is_number = unsafe { sys::lua_isnumber(ptr, idx) != 0 };
+        is_number || int64::read_cdata(ptr, idx).is_some()
+    }
+
+    /// `Vec<u8>` and `&[u8]` are strings.
+    #[inline]
+    fn is_vec(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_isstring(ptr, idx) != 0 }
     }
 }
 
@@ -50,10 +83,38 @@ impl IsType for String {
     }
 }
 
+impl IsType for Cow<'_, str> {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_isstring(ptr, idx) != 0 }
+    }
+}
+
+impl IsType for Bytes<'_> {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_isstring(ptr, idx) != 0 }
+    }
+}
+
+impl IsType for ByteBuf {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_isstring(ptr, idx) != 0 }
+    }
+}
+
+impl<T: IsType> IsType for Option<T> {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_isnoneornil(ptr, idx) != 0 || T::is_type(ptr, idx) }
+    }
+}
+
 impl<T: UserData> IsType for T {
     #[inline]
     fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
-        unsafe { sys::lua_isuserdata(ptr, idx) != 0 }
+        user_data::is::<T>(ptr, idx)
     }
 }
 
@@ -116,3 +177,52 @@ impl IsType for () {
         unsafe { sys::lua_isnil(ptr, idx) != 0 }
     }
 }
+
+impl<T: IsType> IsType for Vec<T> {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        T::is_vec(ptr, idx)
+    }
+}
+
+impl<T> IsType for VecDeque<T> {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_istable(ptr, idx) != 0 }
+    }
+}
+
+impl<T, const N: usize> IsType for [T; N] {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_istable(ptr, idx) != 0 }
+    }
+}
+
+impl<T: IsType> IsType for &[T] {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        T::is_vec(ptr, idx)
+    }
+}
+
+impl<K, V, S> IsType for HashMap<K, V, S> {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_istable(ptr, idx) != 0 }
+    }
+}
+
+impl<K, V> IsType for BTreeMap<K, V> {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_istable(ptr, idx) != 0 }
+    }
+}
+
+impl<T, S> IsType for HashSet<T, S> {
+    #[inline]
+    fn is_type(ptr: *mut sys::lua_State, idx: i32) -> bool {
+        unsafe { sys::lua_istable(ptr, idx) != 0 }
+    }
+}
@@ -1,29 +1,54 @@
 use std::marker::PhantomData;
 
-use from_lua::FromLua;
-use to_lua::ToLua;
+#[cfg(feature = "serde")]
+pub use crate::serde::LuaSerde;
+pub use bytes::{ByteBuf, Bytes, Utf8Policy};
+pub use coroutine::{Coroutine, CoroutineStatus, Resume, Yields};
+pub use error::{Error, Panic, Result};
+pub use function::LuaFunction;
+pub use future::AsyncCall;
+pub use int64::{Int64, UInt64};
+pub use sandbox::{Sandbox, SandboxBuilder};
+pub use table::{Pairs, Sequence, Table};
+pub use traceback::Frame;
+pub use value::{AnyUserData, CData, LightUserData, MultiValue, Value};
+pub use variadic::Variadic;
 
+#[doc(hidden)]
+pub use inventory;
+#[cfg(feature = "vendored")]
+pub use luajit2_sys as sys;
+
+mod bytes;
+mod closure;
+mod coroutine;
+mod error;
+mod ffi;
 mod from_lua;
+mod function;
+mod future;
+mod int64;
 mod is_type;
+mod reference;
+mod sandbox;
+#[cfg(feature = "serde")]
+pub mod serde;
 pub mod state;
+#[cfg(not(feature = "vendored"))]
+pub mod sys;
+mod table;
 mod to_lua;
+mod traceback;
+mod user_data;
+mod value;
+mod variadic;
 
-pub type RawFunction = unsafe extern "C" fn(state: *mut luajit2_sys::lua_State) -> std::ffi::c_int;
+pub type RawFunction = unsafe extern "C" fn(state: *mut sys::lua_State) -> std::ffi::c_int;
 
 pub struct NativeFunction;
 
 pub struct AnyLuaFunction;
 
-pub struct LuaFunction<'a, A: ToLua, B: FromLua<'a>>(i32, PhantomData<&'a A>, PhantomData<B>);
-
-pub struct AnyUserData;
-
-pub struct LightUserData;
-
-pub struct Coroutine;
-
-pub struct Table;
-
 pub struct RelativeValue<T>(i32, PhantomData<T>);
 
 impl<T> RelativeValue<T> {
@@ -32,32 +57,63 @@ impl<T> RelativeValue<T> {
     }
 }
 
-pub trait UserData {
+/// A rust type lua can hold. Lua decides when the value is dropped, so it can't borrow anything.
+pub trait UserData: MetaTable + 'static {
+    /// Name of the type in lua, for error messages, `__name` and the class table.
     fn name() -> *const i8;
-    fn functions() -> Vec<luajit2_sys::luaL_Reg>;
+    fn functions() -> Vec<sys::luaL_Reg>;
+
+    /// Registry key of the metatable, which unlike [`UserData::name`] must be unique to the
+    /// type. `#[user_data]` qualifies the type with the module of the impl, the default uses the
+    /// full type name.
+    fn key() -> *const i8
+    where
+        Self: Sized,
+    {
+        user_data::key::<Self>()
+    }
+
+    /// Associated consts published in the class table by [`State::register_type`], each function
+    /// pushes the value of the const it's named after.
+    ///
+    /// [`State::register_type`]: state::State::register_type
+    fn constants() -> Vec<sys::luaL_Reg> {
+        Vec::new()
+    }
 }
 
-// TODO: ???
+/// Metamethods (`__add`, `__eq`, `__tostring`, ...) set on the metatable of a [`UserData`].
+///
+/// `#[user_data]` implements it from the functions marked with `#[lua(add)]`, `#[lua(eq)]`, ...
+/// and the std traits listed in `#[user_data(Add, PartialEq, Display)]`.
 pub trait MetaTable {
-    fn index(&mut self) {}
-    fn new_index(&mut self) {}
-    fn mode(&mut self) {}
-    fn call(&mut self) {}
-    fn metatable(&mut self) {}
-    fn to_string(&mut self) {}
-    fn gc(&mut self) {}
-    fn name(&mut self) {}
-    //
-    fn unary_minus(&mut self) {}
-    fn add(&mut self) {}
-    fn sub(&mut self) {}
-    fn mul(&mut self) {}
-    fn div(&mut self) {}
-    fn modulo(&mut self) {}
-    fn pow(&mut self) {}
-    fn concat(&mut self) {}
-    //
-    fn eq(&mut self) {}
-    fn lt(&mut self) {}
-    fn le(&mut self) {}
+    fn meta_methods() -> Vec<sys::luaL_Reg> {
+        Vec::new()
+    }
+
+    /// Fields read and written as `obj.field`, listed in `#[user_data(fields(...))]`.
+    fn properties() -> Vec<Property> {
+        Vec::new()
+    }
 }
+
+/// Accessors of a field exposed to lua, both called with the userdata at 1 and the key at 2,
+/// `set` with the new value at 3. A field without `set` is read-only.
+pub struct Property {
+    pub name: *const i8,
+    pub get: sys::lua_CFunction,
+    pub set: sys::lua_CFunction,
+}
+
+/// Functions of a `#[user_data(extend)]` block, merged into the metatable of the type named by
+/// `ty` when it's created.
+#[doc(hidden)]
+pub struct Extension {
+    pub ty: fn() -> &'static str,
+    pub functions: fn() -> Vec<sys::luaL_Reg>,
+    pub meta_methods: fn() -> Vec<sys::luaL_Reg>,
+    pub properties: fn() -> Vec<Property>,
+    pub constants: fn() -> Vec<sys::luaL_Reg>,
+}
+
+inventory::collect!(Extension);
@@ -0,0 +1,142 @@
+use std::{
+    ffi::c_void,
+    mem::size_of,
+    rc::{Rc, Weak},
+};
+
+use crate::sys;
+use macros::cstr;
+
+/// Registry field holding the [`Main`] of a state.
+const MAIN_KEY: *const i8 = cstr!("lofy.main");
+
+/// Where the references into a state are used from, kept in its registry.
+#[derive(Clone, Copy)]
+struct Main {
+    /// Thread that lives as long as the state, unlike the coroutine a reference may be created
+    /// on.
+    ptr: *mut sys::lua_State,
+    /// Held by the `State` that closes it, null for states opened by someone else.
+    open: *const (),
+}
+
+/// Records `ptr` as the main thread of a state opened by `State::new`, open until `open` is
+/// dropped.
+pub(crate) fn init(ptr: *mut sys::lua_State, open: &Rc<()>) {
+    store(
+        ptr,
+        Main {
+            ptr,
+            open: Rc::as_ptr(open),
+        },
+    );
+}
+
+fn store(ptr: *mut sys::lua_State, main: Main) {
+    unsafe {
+        let data = sys::lua_newuserdata(ptr, size_of::<Main>()) as *mut Main;
+        data.write(main);
+        sys::lua_setfield(ptr, sys::LUA_REGISTRYINDEX, MAIN_KEY);
+    }
+}
+
+/// The [`Main`] of the state `ptr` belongs to. States that weren't opened through `State::new`
+/// get a thread of their own, pinned for as long as the state exists.
+fn main(ptr: *mut sys::lua_State) -> Main {
+    unsafe {
+        sys::lua_getfield(ptr, sys::LUA_REGISTRYINDEX, MAIN_KEY);
+        let data = sys::lua_touserdata(ptr, -1) as *const Main;
+        sys::lua_pop(ptr, 1);
+        if !data.is_null() {
+            return *data;
+        }
+
+        let main = Main {
+            ptr: sys::lua_newthread(ptr),
+            open: std::ptr::null(),
+        };
+        sys::luaL_ref(ptr, sys::LUA_REGISTRYINDEX);
+        store(ptr, main);
+        main
+    }
+}
+
+/// A value pinned in the registry through `luaL_ref`, released on drop.
+///
+/// The state may be closed while references into it still exist: using them panics, dropping
+/// them does nothing.
+pub(crate) struct Reference {
+    ptr: *mut sys::lua_State,
+    key: i32,
+    open: Option<Weak<()>>,
+}
+
+impl Reference {
+    /// Pins the value at `idx`, leaving the stack untouched.
+    pub(crate) fn new(ptr: *mut sys::lua_State, idx: i32) -> Self {
+        unsafe {
+            sys::lua_pushvalue(ptr, idx);
+            Self::pop(ptr)
+        }
+    }
+
+    /// Pins the value on top of the stack, popping it.
+    pub(crate) fn pop(ptr: *mut sys::lua_State) -> Self {
+        let main = main(ptr);
+        let key = unsafe { sys::luaL_ref(ptr, sys::LUA_REGISTRYINDEX) };
+        // the `Rc` is alive as long as the state is open, which it is to create a reference
+        let open = (!main.open.is_null()).then(|| unsafe {
+            Rc::increment_strong_count(main.open);
+            Rc::downgrade(&Rc::from_raw(main.open))
+        });
+
+        Self {
+            ptr: main.ptr,
+            key,
+            open,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.open
+            .as_ref()
+            .is_none_or(|open| open.strong_count() > 0)
+    }
+
+    /// Main thread of the state, where the value can be pushed whatever thread pinned it.
+    pub(crate) fn ptr(&self) -> *mut sys::lua_State {
+        assert!(self.is_open(), "handle used after its lua state was closed");
+        self.ptr
+    }
+
+    /// Pushes the referenced value onto the stack of `ptr`.
+    pub(crate) fn push(&self, ptr: *mut sys::lua_State) {
+        assert!(self.is_open(), "handle used after its lua state was closed");
+        unsafe { sys::lua_rawgeti(ptr, sys::LUA_REGISTRYINDEX, self.key) }
+    }
+
+    /// Address of the referenced object, only meaningful for identity and printing.
+    pub(crate) fn to_pointer(&self) -> *const c_void {
+        let ptr = self.ptr();
+        self.push(ptr);
+        let pointer = unsafe { sys::lua_topointer(ptr, -1) };
+        unsafe { sys::lua_pop(ptr, 1) };
+        pointer
+    }
+}
+
+impl Clone for Reference {
+    fn clone(&self) -> Self {
+        let ptr = self.ptr();
+        self.push(ptr);
+        Self::pop(ptr)
+    }
+}
+
+impl Drop for Reference {
+    fn drop(&mut self) {
+        if self.is_open() {
+            unsafe { sys::luaL_unref(self.ptr, sys::LUA_REGISTRYINDEX, self.key) }
+        }
+    }
+}
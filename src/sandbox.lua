@@ -0,0 +1,27 @@
+-- Replacements of the functions that would hand sandboxed code the host environment, metatables
+-- shared with the host or bytecode. Called with the environment of the sandbox.
+local env = ...
+local getmetatable, type, load = getmetatable, type, load
+
+local safe = {}
+
+-- strings and userdata share their metatables with the host, only tables have their own
+function safe.getmetatable(value)
+	if type(value) == "table" then
+		return getmetatable(value)
+	end
+	return nil
+end
+
+-- every function, level and thread (`getfenv(0)`) gives the sandbox environment
+function safe.getfenv()
+	return env
+end
+
+-- text chunks only, running in the sandbox
+function safe.load(chunk, name)
+	return load(chunk, name, "t", env)
+end
+safe.loadstring = safe.load
+
+return safe
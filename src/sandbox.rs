@@ -0,0 +1,515 @@
+use crate::sys;
+use macros::cstr;
+
+use crate::{error::Error, state::State, to_lua::ToLua, LuaFunction, Table, Value};
+
+const SAFE: &str = include_str!("./sandbox.lua");
+const STRING_METHODS: &str = include_str!("./string_methods.lua");
+
+/// Globals that reach outside of any environment: the host's through `setfenv`, `debug` and the
+/// loaders, native code through `ffi` and `jit`, bytecode through `string.dump`.
+const REFUSED: &[&str] = &[
+    "setfenv",
+    "debug",
+    "ffi",
+    "jit",
+    "package",
+    "require",
+    "module",
+    "dofile",
+    "loadfile",
+    "string.dump",
+];
+
+/// Globals given in the versions of `sandbox.lua`.
+const REPLACED: &[&str] = &["getmetatable", "getfenv", "load", "loadstring"];
+
+/// Allowed by [`SandboxBuilder::allow_defaults`].
+const DEFAULTS: &[&str] = &[
+    "assert",
+    "error",
+    "ipairs",
+    "next",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawset",
+    "select",
+    "setmetatable",
+    "getmetatable",
+    "tonumber",
+    "tostring",
+    "type",
+    "unpack",
+    "xpcall",
+    "coroutine",
+    "math",
+    "string",
+    "table",
+    "os.clock",
+    "os.date",
+    "os.difftime",
+    "os.time",
+];
+
+fn is_refused(name: &str) -> bool {
+    REFUSED.iter().any(|refused| {
+        name.strip_prefix(refused)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+/// Builds a [`Sandbox`], see [`State::sandbox`].
+pub struct SandboxBuilder<'a> {
+    state: &'a State,
+    allowed: Vec<String>,
+    globals: Table,
+}
+
+impl<'a> SandboxBuilder<'a> {
+    pub(crate) fn new(state: &'a State) -> Self {
+        Self {
+            state,
+            allowed: vec![],
+            globals: state.create_table(0, 0),
+        }
+    }
+
+    /// Gives scripts the global `name` of the state, or a single function of a library with
+    /// `"lib.name"`. Libraries are copied, scripts changing them don't affect the host.
+    pub fn allow(mut self, name: &str) -> Self {
+        self.allowed.push(name.to_string());
+        self
+    }
+
+    /// Allows the base functions and libraries that can't reach outside of the sandbox: `string`,
+    /// `table`, `math`, `coroutine` and the clock functions of `os`.
+    pub fn allow_defaults(self) -> Self {
+        DEFAULTS
+            .iter()
+            .fold(self, |builder, name| builder.allow(name))
+    }
+
+    /// Sets a global of the sandbox, taking precedence over allowed ones.
+    pub fn global(self, name: &str, value: impl ToLua) -> Self {
+        // string keys are always valid
+        let _ = self.globals.raw_set(name, value);
+        self
+    }
+
+    /// Creates the environment. Fails if an allowed name isn't a global of the state (its
+    /// library may not be open) or could be used to escape.
+    pub fn build(self) -> crate::Result<Sandbox> {
+        let state = self.state;
+        let host = state.globals();
+        let env = state.create_table(0, 0);
+        let mut safe = None;
+
+        for name in &self.allowed {
+            if is_refused(name) {
+                return Err(Error::runtime(format!(
+                    "`{name}` can't be allowed in a sandbox"
+                )));
+            }
+            if name == "_G" {
+                continue;
+            }
+
+            let unknown = || Error::runtime(format!("`{name}` isn't a global"));
+            match name.split_once('.') {
+                Some((lib, member)) => {
+                    let Some(Value::Table(source)) = host.raw_get::<_, Value>(lib) else {
+                        return Err(unknown());
+                    };
+                    let value = source.raw_get::<_, Value>(member).unwrap_or_default();
+                    if value.is_nil() {
+                        return Err(unknown());
+                    }
+                    library(state, &env, lib)?.raw_set(member, value)?;
+                }
+                None => match host.raw_get::<_, Value>(name.as_str()).unwrap_or_default() {
+                    Value::Nil => return Err(unknown()),
+                    _ if REPLACED.contains(&name.as_str()) => {
+                        let safe = match &safe {
+                            Some(safe) => safe,
+                            None => safe.insert(replacements(state, &env)?),
+                        };
+                        env.raw_set(
+                            name.as_str(),
+                            safe.raw_get::<_, Value>(name.as_str()).unwrap_or_default(),
+                        )?;
+                    }
+                    Value::Table(source) => {
+                        let copy = library(state, &env, name)?;
+                        for (key, value) in source.pairs::<Value, Value>() {
+                            let member = key.as_str().map(|key| format!("{name}.{key}"));
+                            if !member.is_some_and(|member| is_refused(&member)) {
+                                copy.raw_set(key, value)?;
+                            }
+                        }
+                    }
+                    value => env.raw_set(name.as_str(), value)?,
+                },
+            }
+        }
+
+        env.raw_set("_G", &env)?;
+        for (name, value) in self.globals.pairs::<Value, Value>() {
+            env.raw_set(name, value)?;
+        }
+        hide_string_dump(state)?;
+
+        Ok(Sandbox { env })
+    }
+}
+
+/// The table of library `name` in `env`, created the first time.
+fn library(state: &State, env: &Table, name: &str) -> crate::Result<Table> {
+    if let Some(Value::Table(table)) = env.raw_get::<_, Value>(name) {
+        return Ok(table);
+    }
+
+    let table = state.create_table(0, 0);
+    env.raw_set(name, &table)?;
+    Ok(table)
+}
+
+/// Runs `sandbox.lua` for `env`.
+fn replacements(state: &State, env: &Table) -> crate::Result<Table> {
+    state.load_chunk(SAFE, cstr!("t"))?;
+    state.protected_call::<_, Table>(env)
+}
+
+/// Strings index the host's `string` library through the metatable they all share, which would
+/// hand scripts `("").dump`. Their `__index` becomes a function looking methods up in the library
+/// itself, skipping `dump`, once per state.
+fn hide_string_dump(state: &State) -> crate::Result<()> {
+    let ptr = state.ptr();
+    state.push("");
+    if unsafe { sys::lua_getmetatable(ptr, -1) } == 0 {
+        state.pop(1);
+        return Ok(());
+    }
+    let metatable = Table::pop(ptr);
+    state.pop(1);
+
+    let Some(Value::Table(methods)) = metatable.raw_get::<_, Value>("__index") else {
+        // not the library, or already hidden by an earlier sandbox
+        return Ok(());
+    };
+    state.load_chunk(STRING_METHODS, cstr!("t"))?;
+    let index = state.protected_call::<_, Value>(methods)?;
+    metatable.raw_set("__index", &index)
+}
+
+/// An environment scripts run in, holding only the globals it was built with.
+///
+/// Chunks are compiled from text only and get the environment with `setfenv`. Functions that
+/// would reach outside of it are replaced: `getfenv` always returns the sandbox environment
+/// (`getfenv(0)` included), `getmetatable` only returns the metatables of tables, and
+/// `load`/`loadstring` refuse bytecode and run what they load in the sandbox too.
+pub struct Sandbox {
+    env: Table,
+}
+
+impl Sandbox {
+    /// The environment table, holding the globals of the scripts.
+    pub fn env(&self) -> &Table {
+        &self.env
+    }
+
+    /// Runs `code` in the sandbox, leaving whatever it returns on the stack.
+    pub fn do_string(&self, code: &str) -> crate::Result<()> {
+        self.push_chunk(code)?;
+        State::from_raw(self.env.ptr()).call_chunk()
+    }
+
+    /// Compiles `code` into a function running in the sandbox.
+    pub fn load<'a, A, R>(&self, code: &str) -> crate::Result<LuaFunction<'a, A, R>> {
+        self.push_chunk(code)?;
+        let ptr = self.env.ptr();
+        let func = LuaFunction::new(ptr, -1);
+        State::from_raw(ptr).pop(1);
+        Ok(func)
+    }
+
+    fn push_chunk(&self, code: &str) -> crate::Result<()> {
+        let ptr = self.env.ptr();
+        State::from_raw(ptr).load_chunk(code, cstr!("t"))?;
+        self.env.push(ptr);
+        unsafe { sys::lua_setfenv(ptr, -2) };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Sandbox;
+    use crate::{state::State, LuaFunction, Table, Value};
+
+    fn sandbox(state: &State) -> Sandbox {
+        state.sandbox().allow_defaults().build().unwrap()
+    }
+
+    // runs `code` in `sandbox`, returning its single result
+    fn eval(state: &State, sandbox: &Sandbox, code: &str) -> Value {
+        let top = state.get_top();
+        sandbox.do_string(&format!("return {code}")).unwrap();
+        let value = state.cast_to::<Value>(top + 1).unwrap();
+        state.set_top(top);
+        value
+    }
+
+    #[test]
+    fn whitelisted_globals() {
+        let state = State::new();
+        state.open_libs();
+        let sandbox = state
+            .sandbox()
+            .allow("tostring")
+            .allow("string.format")
+            .allow("os.time")
+            .global("answer", 42)
+            .build()
+            .unwrap();
+
+        assert_eq!(eval(&state, &sandbox, "answer"), Value::Number(42.0));
+        assert_eq!(
+            eval(&state, &sandbox, "string.format('%d', answer)"),
+            Value::from("42")
+        );
+        assert!(eval(&state, &sandbox, "os.time()").as_number().is_some());
+        for global in ["print", "string.rep", "os.execute", "io", "pairs"] {
+            assert!(eval(&state, &sandbox, global).is_nil(), "{global}");
+        }
+        assert_eq!(eval(&state, &sandbox, "_G.answer"), Value::Number(42.0));
+
+        let error = state.sandbox().allow("missing").build().err().unwrap();
+        assert!(error.to_string().contains("`missing` isn't a global"));
+        let error = state
+            .sandbox()
+            .allow("table.missing")
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("`table.missing` isn't a global"));
+
+        let bare = State::new();
+        assert!(bare.sandbox().allow("print").build().is_err());
+        let sandbox = bare.sandbox().global("x", 1).build().unwrap();
+        assert_eq!(eval(&bare, &sandbox, "x"), Value::Number(1.0));
+    }
+
+    #[test]
+    fn refuse_escapes() {
+        let state = State::new();
+        state.open_libs();
+        for name in [
+            "setfenv",
+            "debug",
+            "debug.getinfo",
+            "ffi",
+            "jit",
+            "require",
+            "package",
+            "loadfile",
+            "dofile",
+            "string.dump",
+        ] {
+            let error = state.sandbox().allow(name).build().err().unwrap();
+            assert!(
+                error.to_string().contains("can't be allowed in a sandbox"),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn isolated_from_host() {
+        let state = State::new();
+        state.open_libs();
+        let sandbox = sandbox(&state);
+        sandbox
+            .do_string(
+                "
+                string.format = nil
+                table.insert = print
+                print = nil
+                secret = 'sandbox'
+                setmetatable(_G, { __index = function() return 'x' end })
+                ",
+            )
+            .unwrap();
+
+        state
+            .do_string("assert(string.format and table.insert ~= print and print)")
+            .unwrap();
+        assert!(state.get_global::<Value>("secret").unwrap().is_nil());
+        assert_eq!(sandbox.env().get::<_, String>("secret").unwrap(), "sandbox");
+
+        // a second sandbox gets its own copies
+        let other = self::sandbox(&state);
+        assert!(eval(&state, &other, "string.format")
+            .as_function()
+            .is_some());
+    }
+
+    #[test]
+    fn environment_escapes() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("secret", "host").unwrap();
+        let sandbox = state
+            .sandbox()
+            .allow_defaults()
+            .allow("getfenv")
+            .allow("load")
+            .allow("loadstring")
+            .build()
+            .unwrap();
+
+        for code in [
+            "getfenv(0).secret",
+            "getfenv(1).secret",
+            "getfenv(print).secret",
+            "getfenv(string.format).secret",
+            "coroutine.wrap(function() return getfenv(0).secret end)()",
+            "load('return secret')()",
+            "loadstring('return getfenv(0).secret')()",
+            "load(function() return nil end) and nil",
+            "secret",
+            "_G.secret",
+            "rawget(_G, 'secret')",
+        ] {
+            assert!(eval(&state, &sandbox, code).is_nil(), "{code}");
+        }
+        assert_eq!(
+            eval(
+                &state,
+                &sandbox,
+                "getfenv(0) == _G and getfenv(print) == _G"
+            ),
+            Value::Boolean(true)
+        );
+        for global in [
+            "setfenv", "debug", "ffi", "jit", "require", "package", "dofile",
+        ] {
+            assert!(eval(&state, &sandbox, global).is_nil(), "{global}");
+        }
+    }
+
+    #[test]
+    fn shared_metatables() {
+        let state = State::new();
+        state.open_libs();
+        let io = state.get_global::<Table>("io").unwrap();
+        let sandbox = state
+            .sandbox()
+            .allow_defaults()
+            .global("stdout", io.get::<_, Value>("stdout").unwrap())
+            .build()
+            .unwrap();
+
+        assert!(eval(&state, &sandbox, "getmetatable('')").is_nil());
+        assert!(eval(&state, &sandbox, "getmetatable(stdout)").is_nil());
+        assert_eq!(
+            eval(
+                &state,
+                &sandbox,
+                "getmetatable(setmetatable({}, { x = 1 })).x"
+            ),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            eval(&state, &sandbox, "('abc'):upper()"),
+            Value::from("ABC")
+        );
+        assert!(eval(&state, &sandbox, "('').dump").is_nil());
+        assert!(eval(&state, &sandbox, "string.dump").is_nil());
+
+        // `string.dump` stays available to the host, the string method is gone for good
+        state
+            .do_string("assert(loadstring(string.dump(function() return 1 end))() == 1)")
+            .unwrap();
+        drop(sandbox);
+        state.do_string("assert(('').dump == nil)").unwrap();
+    }
+
+    #[test]
+    fn host_string_extensions() {
+        let state = State::new();
+        state.open_libs();
+        let sandbox = sandbox(&state);
+        let other = self::sandbox(&state);
+        state
+            .do_string(
+                "
+                function string.shout(s) return s:upper() .. '!' end
+                string.upper = function(s) return 'patched ' .. s end
+                assert(('hi'):shout() == 'patched hi!')
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            eval(&state, &sandbox, "('hi'):shout()"),
+            Value::from("patched hi!")
+        );
+        assert!(eval(&state, &other, "('').dump").is_nil());
+    }
+
+    #[test]
+    fn bytecode_loading() {
+        let state = State::new();
+        state.open_libs();
+        let sandbox = state
+            .sandbox()
+            .allow_defaults()
+            .allow("load")
+            .build()
+            .unwrap();
+
+        let error = sandbox.do_string("\x1bLJ\x02").unwrap_err();
+        assert!(error.to_string().contains("wrong mode"), "{error}");
+        assert!(sandbox.load::<(), ()>("\x1bLJ\x02").is_err());
+
+        state
+            .do_string("bytecode = string.dump(function() return getfenv(0) end)")
+            .unwrap();
+        let bytecode = state.get_global::<Value>("bytecode").unwrap();
+        sandbox.env().set("bytecode", bytecode).unwrap();
+        sandbox
+            .do_string(
+                "
+                local f, error = load(bytecode)
+                assert(f == nil and error:find('wrong mode'))
+                f, error = load(function() local chunk = bytecode; bytecode = nil; return chunk end)
+                assert(f == nil and error:find('wrong mode'))
+                ",
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn load_functions() {
+        let state = State::new();
+        state.open_libs();
+        let sandbox = sandbox(&state);
+        let counter: LuaFunction<i32, i32> = sandbox
+            .load("count = (count or 0) + ... return count")
+            .unwrap();
+        assert_eq!(counter.call(2).unwrap(), 2);
+        assert_eq!(counter.call(3).unwrap(), 5);
+        assert_eq!(sandbox.env().get::<_, i32>("count").unwrap(), 5);
+        assert!(state.get_global::<Value>("count").unwrap().is_nil());
+
+        let error = sandbox.load::<(), ()>("return +").err().unwrap();
+        assert!(matches!(error, crate::Error::Syntax(_)), "{error}");
+        let error = sandbox.do_string("os.execute('true')").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("attempt to call field 'execute'"));
+    }
+}
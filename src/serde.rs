@@ -0,0 +1,1317 @@
+//! Conversions between lua values and any type implementing serde's `Serialize` and
+//! `Deserialize`, enabled by the `serde` feature.
+//!
+//! Structs and maps become tables with string keys, sequences and tuples become 1-based
+//! tables and enums are externally tagged, `{ Variant = value }`. Integers that a double can't
+//! hold exactly are pushed as `int64_t`/`uint64_t` cdata.
+
+use std::{
+    ffi::c_int,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use crate::sys;
+use ::serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor},
+    ser::{self, Serialize},
+};
+use macros::cstr;
+
+use crate::{
+    bytes,
+    error::{self, Error},
+    from_lua::{abs_index, FromLua},
+    int64,
+    state::State,
+    to_lua::ToLua,
+    LightUserData,
+};
+
+/// Light userdata holding a null pointer, stands for `None` and `()` when
+/// [`NoneAs::Null`] is used. Always read back as `None`.
+pub const NULL: LightUserData = LightUserData(std::ptr::null_mut());
+
+const ARRAY_KEY: *const i8 = cstr!("lofy.array");
+
+/// How `None`, `()` and unit structs are pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoneAs {
+    /// `nil`, fields holding `None` disappear from their table and sequences get holes.
+    #[default]
+    Nil,
+    /// [`NULL`], keeps the key in tables and the position in sequences.
+    Null,
+}
+
+/// How enum variants without data are pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnitVariantAs {
+    /// The name of the variant as a string.
+    #[default]
+    Name,
+    /// The 0-based index of the variant as a number.
+    Index,
+}
+
+/// How sequences, tuples and sets are pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayAs {
+    /// Plain tables, empty ones can't be told apart from empty maps.
+    #[default]
+    Sequence,
+    /// Tables sharing a marker metatable, read back as sequences even when empty.
+    Marked,
+}
+
+/// Representation choices of the [`Serializer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Options {
+    pub none: NoneAs,
+    pub unit_variant: UnitVariantAs,
+    pub array: ArrayAs,
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Error::Serialize(message.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Error::Deserialize(message.to_string())
+    }
+}
+
+/// Pushes the marker metatable of arrays, created on first use.
+fn push_array_metatable(ptr: *mut sys::lua_State) {
+    unsafe {
+        sys::lua_getfield(ptr, sys::LUA_REGISTRYINDEX, ARRAY_KEY);
+        if sys::lua_istable(ptr, -1) == 0 {
+            sys::lua_pop(ptr, 1);
+            sys::lua_createtable(ptr, 0, 0);
+            sys::lua_pushvalue(ptr, -1);
+            sys::lua_setfield(ptr, sys::LUA_REGISTRYINDEX, ARRAY_KEY);
+        }
+    }
+}
+
+fn is_marked_array(ptr: *mut sys::lua_State, idx: i32) -> bool {
+    unsafe {
+        if sys::lua_getmetatable(ptr, idx) == 0 {
+            return false;
+        }
+        sys::lua_getfield(ptr, sys::LUA_REGISTRYINDEX, ARRAY_KEY);
+        let marked = sys::lua_rawequal(ptr, -1, -2) != 0;
+        sys::lua_pop(ptr, 2);
+        marked
+    }
+}
+
+/// Whether the keys of the table at `idx` are exactly `1..=n`, empty tables only count when
+/// they are marked.
+fn is_sequence(ptr: *mut sys::lua_State, idx: i32) -> bool {
+    if is_marked_array(ptr, idx) {
+        return true;
+    }
+
+    let len = unsafe { sys::lua_objlen(ptr, idx) } as f64;
+    let mut count = 0.0;
+    unsafe { sys::lua_pushnil(ptr) };
+    while unsafe { sys::lua_next(ptr, idx) } != 0 {
+        let key = unsafe { sys::lua_tonumber(ptr, -2) };
+        let is_index = unsafe { sys::lua_type(ptr, -2) } == sys::LUA_TNUMBER as c_int
+            && key.fract() == 0.0
+            && (1.0..=len).contains(&key);
+        unsafe { sys::lua_pop(ptr, if is_index { 1 } else { 2 }) };
+        if !is_index {
+            return false;
+        }
+        count += 1.0;
+    }
+    count > 0.0 && count == len
+}
+
+fn check_stack(ptr: *mut sys::lua_State) -> bool {
+    unsafe { sys::lua_checkstack(ptr, 4) != 0 }
+}
+
+/// Lua type name for error messages, `no value` reads as `nil`.
+fn type_name_at(ptr: *mut sys::lua_State, idx: i32) -> &'static str {
+    match unsafe { sys::lua_type(ptr, idx) } as u32 {
+        sys::LUA_TBOOLEAN => "boolean",
+        sys::LUA_TNUMBER => "number",
+        sys::LUA_TSTRING => "string",
+        sys::LUA_TTABLE => "table",
+        sys::LUA_TFUNCTION => "function",
+        sys::LUA_TUSERDATA | sys::LUA_TLIGHTUSERDATA => "userdata",
+        sys::LUA_TTHREAD => "thread",
+        _ if unsafe { sys::lua_type(ptr, idx) } == int64::LUA_TCDATA => "cdata",
+        _ => "nil",
+    }
+}
+
+/// Serializes a value into a single lua value pushed onto the stack.
+///
+/// Failures may leave partially built values behind, [`State::serialize`] restores the stack.
+#[derive(Clone, Copy)]
+pub struct Serializer<'a> {
+    state: &'a State,
+    options: Options,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(state: &'a State) -> Self {
+        Self::with_options(state, Options::default())
+    }
+
+    pub fn with_options(state: &'a State, options: Options) -> Self {
+        Self { state, options }
+    }
+
+    fn ptr(&self) -> *mut sys::lua_State {
+        self.state.ptr()
+    }
+
+    fn push_none(&self) {
+        match self.options.none {
+            NoneAs::Nil => self.state.push(()),
+            NoneAs::Null => self.state.push(NULL),
+        }
+    }
+
+    /// Pushes a new table, returning its absolute index.
+    fn create_table(&self, narr: usize, nrec: usize) -> crate::Result<i32> {
+        if !check_stack(self.ptr()) {
+            return Err(Error::Serialize("stack overflow".to_string()));
+        }
+        unsafe { sys::lua_createtable(self.ptr(), narr as c_int, nrec as c_int) };
+        Ok(self.state.get_top())
+    }
+
+    fn create_array(&self, len: usize) -> crate::Result<Array<'a>> {
+        let table = self.create_table(len, 0)?;
+        if self.options.array == ArrayAs::Marked {
+            push_array_metatable(self.ptr());
+            unsafe { sys::lua_setmetatable(self.ptr(), table) };
+        }
+        Ok(Array {
+            serializer: *self,
+            table,
+            len: 0,
+        })
+    }
+
+    /// Pushes the table wrapping the data of a variant and the name of the variant, the data
+    /// goes on top of them. Returns the index of the name.
+    fn create_variant(&self, variant: &'static str) -> crate::Result<i32> {
+        self.create_table(0, 1)?;
+        self.state.push(variant);
+        Ok(self.state.get_top())
+    }
+}
+
+/// Builds a sequence, tuple or tuple variant.
+pub struct Array<'a> {
+    serializer: Serializer<'a>,
+    table: i32,
+    len: i32,
+}
+
+impl Array<'_> {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> crate::Result<()> {
+        value.serialize(self.serializer)?;
+        self.len += 1;
+        unsafe { sys::lua_rawseti(self.serializer.ptr(), self.table, self.len) };
+        Ok(())
+    }
+
+    /// Stores a finished variant table in the table wrapping it.
+    fn end_variant(self) -> crate::Result<()> {
+        unsafe { sys::lua_rawset(self.serializer.ptr(), self.table - 2) };
+        Ok(())
+    }
+}
+
+/// Builds a map, struct or struct variant.
+pub struct Map<'a> {
+    serializer: Serializer<'a>,
+    table: i32,
+}
+
+impl Map<'_> {
+    fn end_variant(self) -> crate::Result<()> {
+        unsafe { sys::lua_rawset(self.serializer.ptr(), self.table - 2) };
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Array<'a>;
+    type SerializeTuple = Array<'a>;
+    type SerializeTupleStruct = Array<'a>;
+    type SerializeTupleVariant = Array<'a>;
+    type SerializeMap = Map<'a>;
+    type SerializeStruct = Map<'a>;
+    type SerializeStructVariant = Map<'a>;
+
+    fn serialize_bool(self, value: bool) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_i8(self, value: i8) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_i16(self, value: i16) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_i32(self, value: i32) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_i64(self, value: i64) -> crate::Result<()> {
+        if value.unsigned_abs() <= int64::MAX_SAFE_INTEGER {
+            self.state.push(value as f64);
+            Ok(())
+        } else {
+            int64::push_int64(self.ptr(), value)
+        }
+    }
+
+    fn serialize_i128(self, value: i128) -> crate::Result<()> {
+        match (i64::try_from(value), u64::try_from(value)) {
+            (Ok(value), _) => self.serialize_i64(value),
+            (_, Ok(value)) => self.serialize_u64(value),
+            _ => Err(Error::Serialize(format!("{value} doesn't fit in 64 bits"))),
+        }
+    }
+
+    fn serialize_u8(self, value: u8) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_u16(self, value: u16) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_u32(self, value: u32) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_u64(self, value: u64) -> crate::Result<()> {
+        if value <= int64::MAX_SAFE_INTEGER {
+            self.state.push(value as f64);
+            Ok(())
+        } else {
+            int64::push_uint64(self.ptr(), value)
+        }
+    }
+
+    fn serialize_u128(self, value: u128) -> crate::Result<()> {
+        match u64::try_from(value) {
+            Ok(value) => self.serialize_u64(value),
+            Err(_) => Err(Error::Serialize(format!("{value} doesn't fit in 64 bits"))),
+        }
+    }
+
+    fn serialize_f32(self, value: f32) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_f64(self, value: f64) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_char(self, value: char) -> crate::Result<()> {
+        self.state.push(value.encode_utf8(&mut [0; 4]) as &str);
+        Ok(())
+    }
+
+    fn serialize_str(self, value: &str) -> crate::Result<()> {
+        self.state.push(value);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> crate::Result<()> {
+        bytes::push_bytes(self.ptr(), value);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> crate::Result<()> {
+        self.push_none();
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> crate::Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> crate::Result<()> {
+        self.push_none();
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> crate::Result<()> {
+        self.push_none();
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> crate::Result<()> {
+        match self.options.unit_variant {
+            UnitVariantAs::Name => self.state.push(variant),
+            UnitVariantAs::Index => self.state.push(index),
+        }
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> crate::Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> crate::Result<()> {
+        let table = self.create_variant(variant)? - 1;
+        value.serialize(self)?;
+        unsafe { sys::lua_rawset(self.ptr(), table) };
+        Ok(())
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> crate::Result<Array<'a>> {
+        self.create_array(len.unwrap_or_default())
+    }
+
+    fn serialize_tuple(self, len: usize) -> crate::Result<Array<'a>> {
+        self.create_array(len)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> crate::Result<Array<'a>> {
+        self.create_array(len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> crate::Result<Array<'a>> {
+        self.create_variant(variant)?;
+        self.create_array(len)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> crate::Result<Map<'a>> {
+        let table = self.create_table(0, len.unwrap_or_default())?;
+        Ok(Map {
+            serializer: self,
+            table,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> crate::Result<Map<'a>> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> crate::Result<Map<'a>> {
+        self.create_variant(variant)?;
+        self.serialize_map(Some(len))
+    }
+}
+
+impl ser::SerializeSeq for Array<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> crate::Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for Array<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> crate::Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for Array<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> crate::Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for Array<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> crate::Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.end_variant()
+    }
+}
+
+impl ser::SerializeMap for Map<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> crate::Result<()> {
+        key.serialize(self.serializer)?;
+        let ptr = self.serializer.ptr();
+        let invalid = unsafe {
+            sys::lua_isnil(ptr, -1) != 0
+                || (sys::lua_type(ptr, -1) == sys::LUA_TNUMBER as c_int
+                    && sys::lua_tonumber(ptr, -1).is_nan())
+        };
+        if invalid {
+            return Err(Error::Serialize("map key is nil or NaN".to_string()));
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> crate::Result<()> {
+        value.serialize(self.serializer)?;
+        unsafe { sys::lua_rawset(self.serializer.ptr(), self.table) };
+        Ok(())
+    }
+
+    fn end(self) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for Map<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> crate::Result<()> {
+        self.serializer.state.push(key);
+        value.serialize(self.serializer)?;
+        unsafe { sys::lua_rawset(self.serializer.ptr(), self.table) };
+        Ok(())
+    }
+
+    fn end(self) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for Map<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> crate::Result<()> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> crate::Result<()> {
+        self.end_variant()
+    }
+}
+
+/// How deep tables may be nested when deserializing.
+pub const MAX_DEPTH: u32 = 128;
+
+/// Deserializes the lua value at a stack index.
+///
+/// Tables whose keys are exactly `1..=n`, or that carry the array marker, read as sequences
+/// and other tables as maps. Sequences and structs accept any table. Enums are read from a
+/// variant name, a variant index or a table with a single `{ Variant = value }` entry.
+/// Borrowed strings are only valid while lua holds on to them, like `&str` arguments. Tables
+/// nested deeper than [`MAX_DEPTH`], as a table that contains itself is, give an error.
+pub struct Deserializer<'de> {
+    ptr: *mut sys::lua_State,
+    idx: i32,
+    depth: u32,
+    marker: PhantomData<&'de ()>,
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn new(state: &State, idx: i32) -> Self {
+        Self::at(state.ptr(), abs_index(state.ptr(), idx), 0)
+    }
+
+    fn at(ptr: *mut sys::lua_State, idx: i32, depth: u32) -> Self {
+        Self {
+            ptr,
+            idx,
+            depth,
+            marker: PhantomData,
+        }
+    }
+
+    fn kind(&self) -> c_int {
+        unsafe { sys::lua_type(self.ptr, self.idx) }
+    }
+
+    /// `nil`, a missing value or [`NULL`].
+    fn is_none(&self) -> bool {
+        match self.kind() as u32 {
+            sys::LUA_TNIL => true,
+            sys::LUA_TLIGHTUSERDATA => unsafe { sys::lua_touserdata(self.ptr, self.idx) }.is_null(),
+            _ => self.kind() == sys::LUA_TNONE,
+        }
+    }
+
+    fn is_table(&self) -> bool {
+        self.kind() == sys::LUA_TTABLE as c_int
+    }
+
+    fn bytes(&self) -> Option<&'de [u8]> {
+        if self.kind() == sys::LUA_TSTRING as c_int {
+            bytes::read_bytes(self.ptr, self.idx)
+        } else {
+            None
+        }
+    }
+
+    fn invalid_type(&self, expected: &dyn de::Expected) -> Error {
+        let unexpected = match self.kind() as u32 {
+            sys::LUA_TBOOLEAN => {
+                Unexpected::Bool(unsafe { sys::lua_toboolean(self.ptr, self.idx) } != 0)
+            }
+            sys::LUA_TNUMBER => Unexpected::Float(unsafe { sys::lua_tonumber(self.ptr, self.idx) }),
+            sys::LUA_TTABLE => Unexpected::Map,
+            _ => Unexpected::Other(type_name_at(self.ptr, self.idx)),
+        };
+        de::Error::invalid_type(unexpected, expected)
+    }
+
+    /// Runs `f` on the contents of the table and drops whatever it left on the stack.
+    fn scoped<T>(&self, f: impl FnOnce() -> crate::Result<T>) -> crate::Result<T> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::Deserialize(format!(
+                "tables nested deeper than {MAX_DEPTH}, or a table that contains itself"
+            )));
+        }
+        if !check_stack(self.ptr) {
+            return Err(Error::Deserialize("stack overflow".to_string()));
+        }
+        let top = unsafe { sys::lua_gettop(self.ptr) };
+        let result = f();
+        unsafe { sys::lua_settop(self.ptr, top) };
+        result
+    }
+
+    fn visit_seq<V: Visitor<'de>>(&self, visitor: V) -> crate::Result<V::Value> {
+        let len = unsafe { sys::lua_objlen(self.ptr, self.idx) } as i32;
+        self.scoped(|| {
+            visitor.visit_seq(Seq {
+                ptr: self.ptr,
+                table: self.idx,
+                depth: self.depth + 1,
+                next: 1,
+                len,
+                marker: PhantomData,
+            })
+        })
+    }
+
+    fn visit_map<V: Visitor<'de>>(&self, visitor: V) -> crate::Result<V::Value> {
+        self.scoped(|| {
+            unsafe { sys::lua_pushnil(self.ptr) };
+            visitor.visit_map(Entries {
+                ptr: self.ptr,
+                table: self.idx,
+                depth: self.depth + 1,
+                marker: PhantomData,
+            })
+        })
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> crate::Result<V::Value> {
+        let ptr = self.ptr;
+        let idx = self.idx;
+        match self.kind() as u32 {
+            sys::LUA_TBOOLEAN => visitor.visit_bool(unsafe { sys::lua_toboolean(ptr, idx) } != 0),
+            sys::LUA_TNUMBER => {
+                let value = unsafe { sys::lua_tonumber(ptr, idx) };
+                if value.fract() == 0.0 && value.abs() < 2f64.powi(63) {
+                    visitor.visit_i64(value as i64)
+                } else {
+                    visitor.visit_f64(value)
+                }
+            }
+            sys::LUA_TSTRING => {
+                let bytes = self.bytes().unwrap_or_default();
+                match std::str::from_utf8(bytes) {
+                    Ok(value) => visitor.visit_borrowed_str(value),
+                    Err(_) => visitor.visit_borrowed_bytes(bytes),
+                }
+            }
+            sys::LUA_TTABLE if is_sequence(ptr, idx) => self.visit_seq(visitor),
+            sys::LUA_TTABLE => self.visit_map(visitor),
+            _ if self.is_none() => visitor.visit_unit(),
+            _ => match int64::read_cdata(ptr, idx) {
+                Some(value) if value < 0 => visitor.visit_i64(value as i64),
+                Some(value) => visitor.visit_u64(value as u64),
+                None => Err(self.invalid_type(&visitor)),
+            },
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> crate::Result<V::Value> {
+        if self.is_none() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> crate::Result<V::Value> {
+        if self.is_none() {
+            visitor.visit_unit()
+        } else {
+            Err(self.invalid_type(&visitor))
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> crate::Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> crate::Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> crate::Result<V::Value> {
+        match self.bytes() {
+            Some(bytes) => visitor.visit_borrowed_bytes(bytes),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> crate::Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> crate::Result<V::Value> {
+        if self.is_table() {
+            self.visit_seq(visitor)
+        } else {
+            Err(self.invalid_type(&visitor))
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> crate::Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> crate::Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> crate::Result<V::Value> {
+        if self.is_table() {
+            self.visit_map(visitor)
+        } else {
+            Err(self.invalid_type(&visitor))
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value> {
+        if let Some(bytes) = self.bytes() {
+            let variant = std::str::from_utf8(bytes).map_err(|_| self.invalid_type(&visitor))?;
+            return visitor.visit_enum(variant.into_deserializer());
+        }
+
+        match self.kind() as u32 {
+            sys::LUA_TNUMBER => {
+                let index = unsafe { sys::lua_tonumber(self.ptr, self.idx) };
+                if index.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&index) {
+                    return Err(self.invalid_type(&visitor));
+                }
+                visitor.visit_enum((index as u32).into_deserializer())
+            }
+            sys::LUA_TTABLE => self.scoped(|| {
+                let ptr = self.ptr;
+                let mut entries = 0;
+                unsafe { sys::lua_pushnil(ptr) };
+                while unsafe { sys::lua_next(ptr, self.idx) } != 0 {
+                    unsafe { sys::lua_pop(ptr, 1) };
+                    entries += 1;
+                }
+                if entries != 1 {
+                    return Err(de::Error::invalid_length(entries, &"a single variant"));
+                }
+
+                unsafe {
+                    sys::lua_pushnil(ptr);
+                    sys::lua_next(ptr, self.idx);
+                }
+                let top = unsafe { sys::lua_gettop(ptr) };
+                visitor.visit_enum(Enum {
+                    key: Deserializer::at(ptr, top - 1, self.depth + 1),
+                    value: Deserializer::at(ptr, top, self.depth + 1),
+                })
+            }),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> crate::Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string identifier
+    }
+}
+
+/// Elements `1..=len` of a table.
+struct Seq<'de> {
+    ptr: *mut sys::lua_State,
+    table: i32,
+    /// Depth of the elements.
+    depth: u32,
+    next: i32,
+    len: i32,
+    marker: PhantomData<&'de ()>,
+}
+
+impl<'de> de::SeqAccess<'de> for Seq<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> crate::Result<Option<T::Value>> {
+        if self.next > self.len {
+            return Ok(None);
+        }
+
+        unsafe { sys::lua_rawgeti(self.ptr, self.table, self.next) };
+        self.next += 1;
+        let top = unsafe { sys::lua_gettop(self.ptr) };
+        let value = seed.deserialize(Deserializer::at(self.ptr, top, self.depth));
+        unsafe { sys::lua_settop(self.ptr, top - 1) };
+        value.map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.len - self.next + 1).max(0) as usize)
+    }
+}
+
+/// Entries of a table in `lua_next` order, the current key stays on the stack between calls.
+struct Entries<'de> {
+    ptr: *mut sys::lua_State,
+    table: i32,
+    /// Depth of the keys and values.
+    depth: u32,
+    marker: PhantomData<&'de ()>,
+}
+
+impl<'de> de::MapAccess<'de> for Entries<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> crate::Result<Option<K::Value>> {
+        if unsafe { sys::lua_next(self.ptr, self.table) } == 0 {
+            return Ok(None);
+        }
+
+        let top = unsafe { sys::lua_gettop(self.ptr) };
+        seed.deserialize(Deserializer::at(self.ptr, top - 1, self.depth))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> crate::Result<V::Value> {
+        let top = unsafe { sys::lua_gettop(self.ptr) };
+        let value = seed.deserialize(Deserializer::at(self.ptr, top, self.depth));
+        unsafe { sys::lua_settop(self.ptr, top - 1) };
+        value
+    }
+}
+
+/// A `{ Variant = value }` table, with its only entry on the stack.
+struct Enum<'de> {
+    key: Deserializer<'de>,
+    value: Deserializer<'de>,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum<'de> {
+    type Error = Error;
+    type Variant = Deserializer<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> crate::Result<(V::Value, Self::Variant)> {
+        Ok((seed.deserialize(self.key)?, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> crate::Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> crate::Result<V::Value> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+/// Converts a serde type with the default [`Options`], so it can be used anywhere a [`ToLua`]
+/// or [`FromLua`] type is expected, including `#[user_data]` arguments and results.
+///
+/// Returned from a rust function called by lua, a serialization error is raised in lua. Pushing
+/// it anywhere else panics on error, use [`State::serialize`] to handle the error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct LuaSerde<T>(pub T);
+
+impl<T> LuaSerde<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for LuaSerde<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for LuaSerde<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T: Serialize> ToLua for LuaSerde<T> {
+    fn to_lua(self, ptr: *mut sys::lua_State) {
+        if let Err(error) = self.try_to_lua(ptr) {
+            panic!("{error}");
+        }
+    }
+
+    fn try_to_lua(self, ptr: *mut sys::lua_State) -> crate::Result<()> {
+        State::from_raw(ptr).serialize(&self.0)
+    }
+}
+
+impl<'a, T: DeserializeOwned> FromLua<'a> for LuaSerde<T> {
+    type Output = LuaSerde<T>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        State::from_raw(ptr).deserialize(idx).ok().map(LuaSerde)
+    }
+
+    fn mismatch(ptr: *mut sys::lua_State, idx: i32) -> Option<(i32, String)> {
+        match Self::from_lua(ptr, idx) {
+            Some(_) => None,
+            None => Some((0, error::type_name::<T>())),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use crate::sys;
+    use macros::{cstr, user_data};
+    use serde::{Deserialize, Serialize};
+
+    use super::{ArrayAs, Deserializer, LuaSerde, NoneAs, Options, UnitVariantAs, NULL};
+    use crate::{state::State, Error, MetaTable, UserData, Value};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Segment(f64, f64),
+        Rect { width: f64, height: f64 },
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        level: u8,
+        id: u64,
+        tags: Vec<String>,
+        limits: BTreeMap<String, i32>,
+        parent: Option<String>,
+        shapes: Vec<Shape>,
+    }
+
+    fn config() -> Config {
+        Config {
+            name: "soreto".to_string(),
+            level: 10,
+            id: u64::MAX,
+            tags: vec!["a".to_string(), "b".to_string()],
+            limits: BTreeMap::from([("hp".to_string(), 100), ("mp".to_string(), -5)]),
+            parent: None,
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(1.5),
+                Shape::Segment(0.0, 2.0),
+                Shape::Rect {
+                    width: 3.0,
+                    height: 4.0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = State::new();
+        state.open_libs();
+        state.serialize(&config()).unwrap();
+        assert_eq!(state.get_top(), 1);
+        assert_eq!(state.deserialize::<Config>(-1).unwrap(), config());
+        assert_eq!(state.get_top(), 1);
+
+        unsafe { sys::lua_setglobal(state.ptr(), cstr!("config")) };
+        state
+            .do_string(
+                "
+                assert(config.name == 'soreto' and config.level == 10)
+                assert(config.id == 0xffffffffffffffffULL)
+                assert(#config.tags == 2 and config.tags[2] == 'b')
+                assert(config.limits.mp == -5 and config.parent == nil)
+                assert(config.shapes[1] == 'Empty' and config.shapes[2].Circle == 1.5)
+                assert(config.shapes[3].Segment[2] == 2 and config.shapes[4].Rect.height == 4)
+                ",
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn read_lua_tables() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string(
+                "
+                return {
+                    name = 'lua', level = 3, id = 7, tags = {}, limits = { x = 1 },
+                    parent = 'root', shapes = { 'Empty', { Circle = 2 }, 0 },
+                    ignored = print,
+                }
+                ",
+            )
+            .unwrap();
+
+        let config = state.deserialize::<Config>(-1).unwrap();
+        assert_eq!(config.name, "lua");
+        assert_eq!(config.parent.as_deref(), Some("root"));
+        assert!(config.tags.is_empty());
+        assert_eq!(
+            config.shapes,
+            [Shape::Empty, Shape::Circle(2.0), Shape::Empty]
+        );
+    }
+
+    #[test]
+    fn borrowed_strings() {
+        #[derive(Deserialize)]
+        struct Message<'a> {
+            text: &'a str,
+        }
+
+        // the table stays on the stack, keeping the string alive
+        let state = State::new();
+        state.do_string("return { text = 'hello' }").unwrap();
+        let message = Message::deserialize(Deserializer::new(&state, -1)).unwrap();
+        assert_eq!(message.text, "hello");
+    }
+
+    #[test]
+    fn nesting_limit() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Node {
+            next: Option<Box<Node>>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct List(Vec<List>);
+
+        let state = State::new();
+        state
+            .do_string("local t = {}; t.next = t; return t")
+            .unwrap();
+        let error = state.deserialize::<Node>(-1).unwrap_err();
+        assert!(matches!(&error, Error::Deserialize(message) if message.contains("nested")));
+        assert_eq!(state.get_top(), 1);
+
+        state.do_string("local t = {}; t[1] = t; return t").unwrap();
+        assert!(state.deserialize::<List>(-1).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let state = State::new();
+        state.open_libs();
+        state.do_string("return { name = 'x' }").unwrap();
+        let error = state.deserialize::<Config>(-1).unwrap_err();
+        assert!(matches!(&error, Error::Deserialize(message) if message.contains("level")));
+        assert_eq!(state.get_top(), 1);
+
+        state.do_string("return print").unwrap();
+        let error = state.deserialize::<i32>(-1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "deserialize error: invalid type: function, expected i32"
+        );
+
+        state.do_string("return 300").unwrap();
+        assert!(state.deserialize::<u8>(-1).is_err());
+
+        let top = state.get_top();
+        let map = HashMap::from([(None::<i32>, 1)]);
+        let error = state.serialize(&vec![map]).unwrap_err();
+        assert!(matches!(error, Error::Serialize(_)));
+        assert_eq!(state.get_top(), top);
+    }
+
+    #[test]
+    fn options() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Row {
+            name: Option<String>,
+            values: Vec<Option<i32>>,
+            kind: Kind,
+        }
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        enum Kind {
+            First,
+            Second,
+        }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        #[serde(untagged)]
+        enum Untyped {
+            Seq(Vec<i32>),
+            Map(HashMap<String, i32>),
+        }
+
+        let state = State::new();
+        state.open_libs();
+        let row = Row {
+            name: None,
+            values: vec![Some(1), None, Some(3)],
+            kind: Kind::Second,
+        };
+        let options = Options {
+            none: NoneAs::Null,
+            unit_variant: UnitVariantAs::Index,
+            array: ArrayAs::Marked,
+        };
+        state.serialize_with(&row, options).unwrap();
+        unsafe { sys::lua_setglobal(state.ptr(), cstr!("row")) };
+        state.set_global("null", NULL).unwrap();
+        state
+            .do_string(
+                "
+                assert(row.name == null and row.values[2] == null and #row.values == 3)
+                assert(row.kind == 1 and getmetatable(row.values) ~= nil)
+                ",
+            )
+            .unwrap();
+        state.get_global::<Value>("row").unwrap();
+        assert_eq!(state.deserialize::<Row>(-1).unwrap(), row);
+
+        state.serialize_with(&Vec::<i32>::new(), options).unwrap();
+        assert_eq!(
+            state.deserialize::<Untyped>(-1).unwrap(),
+            Untyped::Seq(vec![])
+        );
+        state.serialize(&Vec::<i32>::new()).unwrap();
+        assert_eq!(
+            state.deserialize::<Untyped>(-1).unwrap(),
+            Untyped::Map(HashMap::new())
+        );
+        state.do_string("return { 1, 2 }").unwrap();
+        assert_eq!(
+            state.deserialize::<Untyped>(-1).unwrap(),
+            Untyped::Seq(vec![1, 2])
+        );
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Record {
+        id: u32,
+        label: String,
+    }
+
+    struct Store {
+        records: Vec<Record>,
+    }
+
+    #[user_data]
+    impl Store {
+        pub fn add(&mut self, record: LuaSerde<Record>) -> usize {
+            self.records.push(record.into_inner());
+            self.records.len()
+        }
+
+        pub fn get(&self, id: u32) -> Option<LuaSerde<&Record>> {
+            self.records
+                .iter()
+                .find(|record| record.id == id)
+                .map(LuaSerde)
+        }
+
+        pub fn keyed(&self) -> LuaSerde<HashMap<Option<u32>, &str>> {
+            let keyed = self
+                .records
+                .iter()
+                .map(|record| (Some(record.id), record.label.as_str()));
+            LuaSerde(keyed.chain([(None, "none")]).collect())
+        }
+    }
+
+    #[test]
+    fn user_data_arguments() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global("store", Store { records: vec![] })
+            .unwrap();
+        state.set_global("defaults", LuaSerde(config())).unwrap();
+        state
+            .do_string(
+                "
+                store:add({ id = 1, label = defaults.name })
+                label = store:get(1).label
+                ok, err = pcall(store.add, store, { id = 'x' })
+                keyed_ok, keyed_err = pcall(store.keyed, store)
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("label").unwrap(), "soreto");
+        assert_eq!(
+            state.get_global::<String>("err").unwrap(),
+            "bad argument #2 (Record expected, got table)"
+        );
+        let defaults = state.get_global::<LuaSerde<Config>>("defaults").unwrap();
+        assert_eq!(*defaults, config());
+        assert!(state.get_global::<Value>("ok").unwrap() == Value::Boolean(false));
+        assert!(!state.get_global::<bool>("keyed_ok").unwrap());
+        assert!(state
+            .get_global::<String>("keyed_err")
+            .unwrap()
+            .starts_with("serialize error"));
+    }
+}
@@ -1,23 +1,45 @@
-use std::ffi::CString;
+use std::{ffi::CString, rc::Rc};
 
-use luajit2_sys as sys;
+use crate::sys;
+use macros::cstr;
 
 use crate::{
-    from_lua::FromLua, is_type::IsType, to_lua::ToLua, AnyLuaFunction, AnyUserData, Coroutine,
-    LightUserData, NativeFunction, Table,
+    bytes, closure,
+    error::Error,
+    ffi,
+    from_lua::{abs_index, FromLua},
+    future,
+    is_type::IsType,
+    reference,
+    to_lua::ToLua,
+    traceback, user_data, AsyncCall, Coroutine, LuaFunction, SandboxBuilder, Table, UserData,
+    Utf8Policy, Value,
 };
 
-pub struct State(*mut sys::lua_State, bool);
+/// A lua state, closed on drop when it was opened by [`State::new`].
+///
+/// Handles into it ([`Table`], [`LuaFunction`], ...) can outlive it: they panic when used once
+/// it's closed, and dropping them is fine.
+pub struct State(*mut sys::lua_State, Option<Rc<()>>);
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl State {
     const PP: &str = include_str!("./pp.lua");
 
     pub fn new() -> Self {
-        State(unsafe { sys::luaL_newstate() }, true)
+        let ptr = unsafe { sys::luaL_newstate() };
+        let open = Rc::new(());
+        reference::init(ptr, &open);
+        State(ptr, Some(open))
     }
 
     pub fn from_raw(ptr: *mut sys::lua_State) -> Self {
-        State(ptr, false)
+        State(ptr, None)
     }
 
     pub fn dump_stack(&self) {
@@ -27,33 +49,18 @@ impl State {
         println!("-----------------------------------");
         for i in 1..=size {
             print!("> [{i} / -{}] ", size - i + 1);
-            if self.is::<f64>(i) {
-                println!("{}", self.cast_to::<f64>(i).unwrap());
-            } else if self.is::<&str>(i) {
-                println!("{}", self.cast_to::<&str>(i).unwrap());
-            } else if self.is::<bool>(i) {
-                println!("{}", self.cast_to::<bool>(i).unwrap());
-            } else if self.is::<AnyLuaFunction>(i) {
-                println!("func");
-            } else if self.is::<Table>(i) {
-                println!("table");
-            } else if self.is::<NativeFunction>(i) {
-                println!("native func");
-            } else if self.is::<AnyUserData>(i) {
-                println!("user data");
-            } else if self.is::<LightUserData>(i) {
-                println!("light user data");
-            } else if self.is::<Coroutine>(i) {
-                println!("coroutine");
-            } else if self.is::<()>(i) {
-                println!("nil");
-            }
+            let value = self.cast_to::<Value>(i).unwrap_or_default();
+            println!("{value}");
         }
         println!("-----------------------------------");
     }
 
     pub(crate) fn owned(&self) -> bool {
-        self.1
+        self.1.is_some()
+    }
+
+    pub(crate) fn ptr(&self) -> *mut sys::lua_State {
+        self.0
     }
 
     pub fn open_libs(&self) {
@@ -69,13 +76,41 @@ impl State {
         T::is_type(self.0, idx)
     }
 
-    pub fn do_string(&self, code: &str) -> Result<(), &str> {
-        let cstring = CString::new(code).unwrap();
-        unsafe { sys::luaL_loadstring(self.0, cstring.as_ptr() as *const i8) };
-        if unsafe { sys::lua_pcall(self.0, 0, sys::LUA_MULTRET, 0) } != 0 {
-            Err(self.cast_to::<&str>(-1).unwrap())
-        } else {
-            Ok(())
+    /// Runs `code`, leaving whatever it returns on the stack.
+    pub fn do_string(&self, code: &str) -> crate::Result<()> {
+        self.load_chunk(code, cstr!("bt"))?;
+        self.call_chunk()
+    }
+
+    /// Pushes `code` compiled into a function. `mode` holds "t" if text chunks are accepted and
+    /// "b" if precompiled ones are.
+    pub(crate) fn load_chunk(&self, code: &str, mode: *const i8) -> crate::Result<()> {
+        // same chunk name `luaL_loadstring` would use, the code itself may contain NULs
+        let name = CString::new(code.split('\0').next().unwrap_or_default()).unwrap();
+        let status = unsafe {
+            sys::luaL_loadbufferx(
+                self.0,
+                code.as_ptr() as *const i8,
+                code.len(),
+                name.as_ptr(),
+                mode,
+            )
+        };
+        match status {
+            0 => Ok(()),
+            status => Err(Error::pop(self.0, status)),
+        }
+    }
+
+    /// Calls the function on top of the stack without arguments, leaving its results.
+    pub(crate) fn call_chunk(&self) -> crate::Result<()> {
+        let handler = self.get_top();
+        traceback::insert_handler(self.0, handler);
+        let status = unsafe { sys::lua_pcall(self.0, 0, sys::LUA_MULTRET, handler) };
+        unsafe { sys::lua_remove(self.0, handler) };
+        match status {
+            0 => Ok(()),
+            status => Err(Error::pop(self.0, status)),
         }
     }
 
@@ -95,44 +130,342 @@ impl State {
         value.to_lua(self.0);
     }
 
-    pub fn set_field(&self, idx: i32, name: &str, value: impl ToLua) {
+    /// Pushes `value` and returns how many values that took, which for a [`Variadic`] is only
+    /// known at runtime.
+    ///
+    /// [`Variadic`]: crate::Variadic
+    pub fn push_values(&self, value: impl ToLua) -> i32 {
+        let top = self.get_top();
         self.push(value);
-        let name = CString::new(name).unwrap();
-        unsafe { sys::lua_setfield(self.0, idx, name.into_raw()) }
+        self.get_top() - top
     }
 
-    pub fn get_field<'a, A: FromLua<'a>>(&self, idx: i32, name: &str) -> Option<A::Output> {
-        let name = CString::new(name).unwrap();
-        unsafe { sys::lua_getfield(self.0, idx, name.into_raw()) };
-        A::from_lua(self.0, idx)
+    /// [`State::push_values`] returning the error of a conversion that fails, such as a
+    /// [`LuaSerde`](crate::LuaSerde) that can't be serialized, instead of panicking. On error
+    /// nothing is pushed.
+    pub fn try_push_values(&self, value: impl ToLua) -> crate::Result<i32> {
+        let top = self.get_top();
+        match value.try_to_lua(self.0) {
+            Ok(()) => Ok(self.get_top() - top),
+            Err(error) => {
+                self.set_top(top);
+                Err(error)
+            }
+        }
     }
 
-    pub fn set_global(&self, name: &str, value: impl ToLua) {
+    /// `t[name] = value` for the table at `idx`, honoring `__newindex`. Errors raised by
+    /// `__newindex` are returned.
+    pub fn set_field(&self, idx: i32, name: &str, value: impl ToLua) -> crate::Result<()> {
+        let idx = abs_index(self.0, idx);
+        self.push(name);
         self.push(value);
-        let str = CString::new(name).unwrap();
-        unsafe { sys::lua_setglobal(self.0, str.into_raw()) }
+        ffi::set_table(self.0, idx)
+    }
+
+    /// Pushes `t[name]` for the table at `idx`, honoring `__index`, and converts it. Read a
+    /// missing field as `Option<A>`, `A` fails to convert nil. Nothing is pushed when `__index`
+    /// raises an error.
+    pub fn get_field<'a, A: FromLua<'a>>(&self, idx: i32, name: &str) -> crate::Result<A::Output> {
+        let idx = abs_index(self.0, idx);
+        self.push(name);
+        ffi::get_table(self.0, idx)?;
+        A::from_lua(self.0, -1).ok_or_else(|| Error::conversion::<A>(self.0, -1, None))
+    }
+
+    /// `_G[name] = value`, see [`State::set_field`].
+    pub fn set_global(&self, name: &str, value: impl ToLua) -> crate::Result<()> {
+        self.set_field(sys::LUA_GLOBALSINDEX, name, value)
+    }
+
+    /// Pushes the global `name` and converts it, see [`State::get_field`].
+    pub fn get_global<'a, T: FromLua<'a>>(&self, name: &str) -> crate::Result<T::Output> {
+        self.get_field::<T>(sys::LUA_GLOBALSINDEX, name)
+    }
+
+    /// Publishes the class table of `T` as a global named after it, so its static functions,
+    /// `#[lua(constructor)]` and associated consts can be reached without an instance:
+    /// `Vec2.new(1, 2)`, `Vec2(1, 2)`, `Vec2.ZERO`. Fails when a metamethod of the globals
+    /// table raises an error.
+    pub fn register_type<T: UserData>(&self) -> crate::Result<()> {
+        user_data::register_type::<T>(self.0)
+    }
+
+    /// Handle to the globals table.
+    pub fn globals(&self) -> Table {
+        unsafe { sys::lua_pushvalue(self.0, sys::LUA_GLOBALSINDEX) };
+        Table::pop(self.0)
+    }
+
+    /// Creates a new table with space preallocated for `narr` sequence and `nrec` other elements.
+    pub fn create_table(&self, narr: i32, nrec: i32) -> Table {
+        unsafe { sys::lua_createtable(self.0, narr, nrec) };
+        Table::pop(self.0)
+    }
+
+    /// Calls `func` in a new coroutine, driven by the returned future. Async functions of
+    /// `#[user_data]` types it calls are awaited as part of it, so it works with any executor.
+    pub fn run_async<'a, A, R>(&self, func: &LuaFunction<'a, A, R>, args: A) -> AsyncCall<'a, A, R>
+    where
+        A: ToLua,
+        R: for<'x> FromLua<'x, Output: 'static>,
+    {
+        AsyncCall::new(self.0, func, args)
+    }
+
+    /// Starts building a [`Sandbox`](crate::Sandbox) for untrusted scripts, holding only the globals of the state
+    /// it allows. Libraries it takes from must be open.
+    ///
+    /// Building the first sandbox changes the whole state: strings share one metatable, so its
+    /// `__index` is replaced with a function that looks methods up in the `string` library but
+    /// skips `dump`. Host code then gets `nil` for `("").dump` too, `string.dump` itself and every
+    /// other method keep working. The change stays after the sandbox is dropped.
+    pub fn sandbox(&self) -> SandboxBuilder<'_> {
+        SandboxBuilder::new(self)
+    }
+
+    /// Creates a coroutine that runs `func` once resumed, see [`Coroutine::resume`].
+    pub fn create_thread<A, B>(&self, func: &LuaFunction<A, B>) -> Coroutine {
+        Coroutine::create(self.0, func)
+    }
+
+    /// Wraps a rust closure into a lua function. Arguments are converted from the call stack
+    /// (trailing `Option`s may be left out) and the result is pushed back. Arguments are owned
+    /// types like `String`, borrows of the stack wouldn't outlive the call.
+    pub fn create_function<'a, A, R, F>(&self, func: F) -> LuaFunction<'a, A, R>
+    where
+        A: for<'x> FromLua<'x, Output = A> + 'static,
+        R: ToLua + 'static,
+        F: Fn(A) -> R + 'static,
+    {
+        closure::push(self.0, closure::wrap(func));
+        let func = LuaFunction::new(self.0, -1);
+        self.pop(1);
+        func
+    }
+
+    /// Same as [`State::create_function`] for closures that mutate their captures. Calling it
+    /// again from inside itself raises an error.
+    pub fn create_function_mut<'a, A, R, F>(&self, func: F) -> LuaFunction<'a, A, R>
+    where
+        A: for<'x> FromLua<'x, Output = A> + 'static,
+        R: ToLua + 'static,
+        F: FnMut(A) -> R + 'static,
+    {
+        closure::push(self.0, closure::wrap_mut(func));
+        let func = LuaFunction::new(self.0, -1);
+        self.pop(1);
+        func
     }
 
-    pub fn get_global<'a, T: FromLua<'a>>(&self, name: &str) -> Option<T::Output> {
-        let str = CString::new(name).unwrap();
-        unsafe { sys::lua_getglobal(self.0, str.into_raw()) };
-        self.cast_to::<T>(-1)
+    pub fn set_global_fn<A, R, F>(&self, name: &str, func: F) -> crate::Result<()>
+    where
+        A: for<'x> FromLua<'x, Output = A> + 'static,
+        R: ToLua + 'static,
+        F: Fn(A) -> R + 'static,
+    {
+        self.set_global(name, self.create_function(func))
+    }
+
+    pub fn set_global_fn_mut<A, R, F>(&self, name: &str, func: F) -> crate::Result<()>
+    where
+        A: for<'x> FromLua<'x, Output = A> + 'static,
+        R: ToLua + 'static,
+        F: FnMut(A) -> R + 'static,
+    {
+        self.set_global(name, self.create_function_mut(func))
     }
 
     pub fn cast_to<'a, T: FromLua<'a>>(&self, idx: i32) -> Option<T::Output> {
         T::from_lua(self.0, idx)
     }
 
-    pub fn protected_call<'a, A: ToLua, B: FromLua<'a>>(&self, args: A) -> Result<B::Output, &str> {
-        self.push(args);
-        if unsafe { sys::lua_pcall(self.0, A::len(), B::len(), 0) } != 0 {
-            Err(self.cast_to::<&str>(-1).unwrap())
-        } else {
-            if let Some(v) = B::from_lua(self.0, B::len() * -1) {
-                Ok(v)
-            } else {
-                Err("Failed to cast output.")
-            }
+    /// Converts `value` through [`Serializer`](crate::serde::Serializer) and pushes the result.
+    /// On error nothing is pushed.
+    #[cfg(feature = "serde")]
+    pub fn serialize<T: ::serde::Serialize + ?Sized>(&self, value: &T) -> crate::Result<()> {
+        self.serialize_with(value, crate::serde::Options::default())
+    }
+
+    /// [`State::serialize`] with custom representations for `None`, unit variants and arrays.
+    #[cfg(feature = "serde")]
+    pub fn serialize_with<T: ::serde::Serialize + ?Sized>(
+        &self,
+        value: &T,
+        options: crate::serde::Options,
+    ) -> crate::Result<()> {
+        let top = self.get_top();
+        let result = value.serialize(crate::serde::Serializer::with_options(self, options));
+        if result.is_err() {
+            self.set_top(top);
+        }
+        result
+    }
+
+    /// Reads the value at `idx` through [`Deserializer`](crate::serde::Deserializer), leaving
+    /// the stack as it was. The values it pushes are popped, so `T` can't borrow from them.
+    #[cfg(feature = "serde")]
+    pub fn deserialize<T: ::serde::de::DeserializeOwned>(&self, idx: i32) -> crate::Result<T> {
+        let top = self.get_top();
+        let result = T::deserialize(crate::serde::Deserializer::new(self, idx));
+        self.set_top(top);
+        result
+    }
+
+    /// Moves the userdata value at `idx` out of lua. The userdata stays around in a destroyed
+    /// state, later casts and method calls on it fail instead of touching the old value.
+    ///
+    /// `None` if the value isn't a live `T`, or while it's borrowed: by a pending async method or
+    /// by a rust function that is running, like a method taking its own receiver.
+    pub fn take<T: UserData>(&self, idx: i32) -> Option<T> {
+        user_data::take::<T>(self.0, idx)
+    }
+
+    /// Same as [`State::cast_to`] for the arguments of a function starting at `idx`, failures
+    /// report the position of the offending argument.
+    pub fn cast_args<'a, T: FromLua<'a>>(&self, idx: i32) -> crate::Result<T::Output> {
+        T::from_lua(self.0, idx).ok_or_else(|| Error::conversion::<T>(self.0, idx, Some(idx)))
+    }
+
+    /// [`State::cast_args`] for a single value, its type is inferred from where it's used.
+    #[doc(hidden)]
+    pub fn cast_arg<'a, T: FromLua<'a, Output = T>>(&self, idx: i32) -> crate::Result<T> {
+        self.cast_args::<T>(idx)
+    }
+
+    /// Runs `f` in protected mode: lua errors it raises, from `luaL_error`, `luaL_check*` or the
+    /// metamethods run by `lua_gettable` and friends, are returned instead of unwinding. That's
+    /// how rust functions called by lua use the parts of the C API that raise errors, which must
+    /// not unwind through them.
+    ///
+    /// `f` sees the values of the stack at the same indices, and the stack is left as it was.
+    /// It must not panic: the panic can't unwind through the lua frames in between and aborts.
+    pub fn protected<R>(&self, f: impl FnOnce(&State) -> R) -> crate::Result<R> {
+        let top = self.get_top();
+        if unsafe { sys::lua_checkstack(self.0, top + 1) } == 0 {
+            return Err(Error::runtime("stack overflow"));
+        }
+        for idx in 1..=top {
+            unsafe { sys::lua_pushvalue(self.0, idx) };
+        }
+
+        let mut result = None;
+        ffi::pcall(self.0, top, 0, |ptr| {
+            result = Some(f(&State::from_raw(ptr)));
+            0
+        })?;
+        Ok(result.expect("protected function didn't run"))
+    }
+
+    /// Body of the C functions generated by the macros: errors returned by `f` and panics are
+    /// raised as lua errors. `f` must not raise lua errors itself, the functions of this crate
+    /// don't and [`State::protected`] catches the ones of the C API.
+    #[doc(hidden)]
+    pub fn protect(
+        ptr: *mut sys::lua_State,
+        f: impl FnOnce(&State) -> crate::Result<std::ffi::c_int>,
+    ) -> std::ffi::c_int {
+        ffi::protect(ptr, f)
+    }
+
+    /// [`State::protect`] for async functions: `f` pushes a future with
+    /// [`State::push_future`], which is then yielded to the driver of the coroutine.
+    #[doc(hidden)]
+    pub fn protect_async(
+        ptr: *mut sys::lua_State,
+        f: impl FnOnce(&State) -> crate::Result<std::ffi::c_int>,
+    ) -> std::ffi::c_int {
+        let nresults = ffi::protect(ptr, f);
+        future::yield_future(ptr, nresults)
+    }
+
+    /// # Safety
+    ///
+    /// `future` may only borrow from the arguments of the running C function, which are
+    /// anchored with it.
+    #[doc(hidden)]
+    pub unsafe fn push_future<'a, F>(&self, future: F) -> std::ffi::c_int
+    where
+        F: std::future::Future + 'a,
+        F::Output: ToLua + 'a,
+    {
+        future::push(self.0, future)
+    }
+
+    #[doc(hidden)]
+    pub const fn extension<T: ?Sized>(
+        functions: fn() -> Vec<sys::luaL_Reg>,
+        meta_methods: fn() -> Vec<sys::luaL_Reg>,
+        properties: fn() -> Vec<crate::Property>,
+        constants: fn() -> Vec<sys::luaL_Reg>,
+    ) -> crate::Extension {
+        crate::Extension {
+            ty: std::any::type_name::<T>,
+            functions,
+            meta_methods,
+            properties,
+            constants,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn user_data_name<T: ?Sized>() -> *const i8 {
+        user_data::generic_name::<T>()
+    }
+
+    #[doc(hidden)]
+    pub fn user_data_key<T: ?Sized>() -> *const i8 {
+        user_data::key::<T>()
+    }
+
+    #[doc(hidden)]
+    pub const fn raw_function(func: ffi::UnwindFunction) -> crate::RawFunction {
+        // same ABI, see `ffi::cfunction`
+        unsafe { std::mem::transmute::<ffi::UnwindFunction, crate::RawFunction>(func) }
+    }
+
+    #[doc(hidden)]
+    pub fn cfunction(func: ffi::UnwindFunction) -> sys::lua_CFunction {
+        ffi::cfunction(func)
+    }
+
+    /// How `String` reads strings that aren't valid UTF-8, strict by default. Shared
+    /// by every coroutine of the state.
+    pub fn set_utf8_policy(&self, policy: Utf8Policy) {
+        bytes::set_policy(self.0, policy)
+    }
+
+    pub fn utf8_policy(&self) -> Utf8Policy {
+        bytes::policy(self.0)
+    }
+
+    /// Replaces the message handler of protected calls. It gets the error object and returns the
+    /// one the call fails with. `None` restores the default handler, which attaches a traceback
+    /// to [`Error::Runtime`].
+    pub fn set_message_handler<A, B>(&self, handler: Option<&LuaFunction<A, B>>) {
+        match handler {
+            Some(handler) => handler.push(self.0),
+            None => self.push(()),
+        }
+        unsafe { sys::lua_setfield(self.0, sys::LUA_REGISTRYINDEX, traceback::HANDLER_KEY) };
+    }
+
+    /// Calls the function pushed before `args`, its results are left on the stack. On error the
+    /// error object is popped.
+    pub fn protected_call<'a, A: ToLua, B: FromLua<'a>>(
+        &self,
+        args: A,
+    ) -> crate::Result<B::Output> {
+        let handler = self.get_top();
+        traceback::insert_handler(self.0, handler);
+        let nargs = self.push_values(args);
+        let status = unsafe { sys::lua_pcall(self.0, nargs, B::len(), handler) };
+        unsafe { sys::lua_remove(self.0, handler) };
+        // the results took the place of the function, right where the handler was
+        match status {
+            0 => B::from_lua(self.0, handler)
+                .ok_or_else(|| Error::conversion::<B>(self.0, handler, None)),
+            status => Err(Error::pop(self.0, status)),
         }
     }
 }
@@ -146,12 +479,28 @@ impl Drop for State {
 }
 
 #[cfg(test)]
+// the tests kept from before the lint setup are left as they were written
+#[allow(
+    unused_variables,
+    clippy::unnecessary_cast,
+    clippy::bool_assert_comparison,
+    clippy::len_zero,
+    clippy::neg_multiply,
+    clippy::new_ret_no_self
+)]
 pub mod tests {
-    use macros::{cstr, lua_func, lua_method, ref_to, user_data};
+    use macros::{cstr, lua_func, lua_function, lua_method, ref_to, user_data};
 
-    use crate::{LuaFunction, RelativeValue, UserData};
+    use crate::{LuaFunction, MetaTable, RelativeValue, UserData};
 
     use super::*;
+    use crate::{ByteBuf, Bytes};
+    use std::{
+        borrow::Cow,
+        cell::RefCell,
+        collections::{BTreeMap, HashMap, HashSet, VecDeque},
+        panic::AssertUnwindSafe,
+    };
 
     macro_rules! from_ptr {
         ($name:expr) => {
@@ -182,6 +531,37 @@ pub mod tests {
         assert_eq!(state.cast_to::<f64>(-1).unwrap(), 20.0);
     }
 
+    #[test]
+    fn push_integer_types() {
+        let state = State::new();
+        state.push((u8::MAX, i8::MIN, u16::MAX, i16::MIN, u32::MAX));
+        state.push((1_u64 << 53, -(1_i128 << 40), 7_usize, -7_isize));
+
+        assert_eq!(state.cast_to::<u8>(1).unwrap(), u8::MAX);
+        assert_eq!(state.cast_to::<i8>(2).unwrap(), i8::MIN);
+        assert_eq!(state.cast_to::<u16>(3).unwrap(), u16::MAX);
+        assert_eq!(state.cast_to::<i16>(4).unwrap(), i16::MIN);
+        assert_eq!(state.cast_to::<u32>(5).unwrap(), u32::MAX);
+        assert_eq!(state.cast_to::<u64>(6).unwrap(), 1 << 53);
+        assert_eq!(state.cast_to::<i128>(7).unwrap(), -(1 << 40));
+        assert_eq!(state.cast_to::<usize>(8).unwrap(), 7);
+        assert_eq!(state.cast_to::<isize>(9).unwrap(), -7);
+    }
+
+    #[test]
+    fn integer_range_checks() {
+        let state = State::new();
+        state.push((300, -1, 1.5, 2_f64.powi(40)));
+
+        assert!(state.cast_to::<u8>(1).is_none());
+        assert_eq!(state.cast_to::<u16>(1).unwrap(), 300);
+        assert!(state.cast_to::<u32>(2).is_none());
+        assert_eq!(state.cast_to::<i64>(2).unwrap(), -1);
+        assert!(state.cast_to::<i32>(3).is_none());
+        assert!(state.cast_to::<i32>(4).is_none());
+        assert_eq!(state.cast_to::<i64>(4).unwrap(), 1 << 40);
+    }
+
     #[test]
     fn push_float() {
         let state = State::new();
@@ -206,13 +586,125 @@ pub mod tests {
         assert_eq!(state.cast_to::<&str>(-1).unwrap(), name.as_str());
     }
 
+    #[test]
+    fn push_string_with_nul() {
+        let state = State::new();
+        state.set_global("value", "foo\0bar").unwrap();
+        state.do_string("len = #value").unwrap();
+
+        assert_eq!(state.get_global::<i32>("len").unwrap(), 7);
+        assert_eq!(state.get_global::<String>("value").unwrap(), "foo\0bar");
+        assert_eq!(state.get_global::<&str>("value").unwrap(), "foo\0bar");
+    }
+
+    #[test]
+    fn invalid_utf8_strings() {
+        let state = State::new();
+        state.push(Bytes(&[b'a', 0xff, 0]));
+
+        assert_eq!(state.utf8_policy(), Utf8Policy::Strict);
+        assert!(state.is::<String>(-1));
+        assert!(state.cast_to::<&str>(-1).is_none());
+        assert!(state.cast_to::<String>(-1).is_none());
+        assert_eq!(state.cast_to::<Cow<str>>(-1).unwrap(), "a\u{FFFD}\0");
+        assert_eq!(state.cast_to::<Bytes>(-1).unwrap(), Bytes(&[b'a', 0xff, 0]));
+    }
+
+    #[test]
+    fn strict_utf8_is_a_conversion_error() {
+        let state = State::new();
+        state
+            .set_global_fn("len", |value: String| value.len())
+            .unwrap();
+        state.set_global("payload", Bytes(&[b'a', 0xff])).unwrap();
+
+        let error = state.do_string("len(payload)").unwrap_err();
+        assert!(error.to_string().contains("valid UTF-8"), "{error}");
+    }
+
+    #[test]
+    fn lossy_utf8_strings() {
+        let state = State::new();
+        state.set_utf8_policy(Utf8Policy::Lossy);
+        state.push(Bytes(&[b'a', 0xff, 0]));
+        state.push(1);
+
+        assert_eq!(state.cast_to::<String>(-2).unwrap(), "a\u{FFFD}\0");
+        assert!(state.cast_to::<&str>(-2).is_none());
+        // the lua string is left as it was
+        assert_eq!(state.cast_to::<&[u8]>(-2).unwrap(), [b'a', 0xff, 0]);
+        assert_eq!(state.get_top(), 2);
+        assert_eq!(state.cast_to::<i32>(-1).unwrap(), 1);
+
+        state.set_utf8_policy(Utf8Policy::Strict);
+        state.push(Bytes(&[0xff]));
+        assert!(state.cast_to::<String>(-1).is_none());
+    }
+
+    #[test]
+    fn push_byte_buffers() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global("payload", ByteBuf(vec![0, 1, 2, 255]))
+            .unwrap();
+        state
+            .do_string("a, b, c, d = string.byte(payload, 1, -1); copy = payload .. '\\0'")
+            .unwrap();
+
+        assert_eq!(state.get_global::<u8>("a").unwrap(), 0);
+        assert_eq!(state.get_global::<u8>("d").unwrap(), 255);
+        assert_eq!(
+            state.get_global::<ByteBuf>("copy").unwrap(),
+            ByteBuf(vec![0, 1, 2, 255, 0])
+        );
+    }
+
+    #[test]
+    fn byte_vectors_are_strings() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("vec", vec![0u8, 1, 255]).unwrap();
+        state.set_global("slice", &[104u8, 105][..]).unwrap();
+        state
+            .do_string("kinds = type(vec) .. type(slice); copy = vec .. slice; numbers = {1, 2}")
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("kinds").unwrap(), "stringstring");
+        assert_eq!(
+            state.get_global::<Vec<u8>>("copy").unwrap(),
+            vec![0, 1, 255, 104, 105]
+        );
+        assert_eq!(state.get_global::<Vec<u16>>("numbers").unwrap(), vec![1, 2]);
+        assert!(state.get_global::<Vec<u8>>("numbers").is_err());
+
+        state.get_global::<Table>("numbers").unwrap();
+        state.push(Bytes(b"hi"));
+        assert!(!state.is::<Vec<u8>>(-2));
+        assert!(state.is::<Vec<u16>>(-2));
+        assert!(state.is::<&[u8]>(-1));
+        assert_eq!(state.cast_to::<&[u8]>(-1).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn push_cow_str() {
+        let state = State::new();
+        state.push(Cow::Borrowed("borrowed"));
+        state.push(Cow::<str>::Owned("owned".to_string()));
+
+        assert_eq!(state.cast_to::<String>(-2).unwrap(), "borrowed");
+        assert_eq!(state.cast_to::<Cow<str>>(-1).unwrap(), "owned");
+    }
+
     #[test]
     fn protected_call_with_single_return_arg() {
         let state = State::new();
         state
             .do_string("function sum(a, b) return a + b end")
             .unwrap();
-        state.get_global::<LuaFunction<(i32, i32), i32>>("sum");
+        state
+            .get_global::<LuaFunction<(i32, i32), i32>>("sum")
+            .unwrap();
 
         let result = state.protected_call::<_, i32>((2, 3));
         assert!(result.is_ok());
@@ -227,10 +719,10 @@ pub mod tests {
             .unwrap();
 
         let option = state.get_global::<LuaFunction<(i32, i32), (i32, i32)>>("double");
-        assert!(option.is_some());
+        assert!(option.is_ok());
 
         let double = option.unwrap();
-        let result = double((4, 8));
+        let result = double.call((4, 8));
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), (8, 16));
     }
@@ -243,11 +735,108 @@ pub mod tests {
             .unwrap();
 
         let double = state.get_global::<LuaFunction<i32, i32>>("double").unwrap();
-        let result = double(10);
+        let result = double.call(10);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 20);
     }
 
+    #[test]
+    fn lua_function_survives_stack_changes() {
+        let state = State::new();
+        state
+            .do_string("function double(a) return a * 2 end")
+            .unwrap();
+
+        let double = state.get_global::<LuaFunction<i32, i32>>("double").unwrap();
+        state.set_top(0);
+        state.push((1, 2, 3));
+
+        assert_eq!(double.call(2).unwrap(), 4);
+        assert_eq!(double.call(4).unwrap(), 8);
+        assert_eq!(state.get_top(), 3);
+    }
+
+    #[test]
+    fn lua_function_can_be_stored_and_cloned() {
+        struct Handler<'a> {
+            callback: LuaFunction<'a, i32, i32>,
+        }
+
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string("function double(a) return a * 2 end")
+            .unwrap();
+
+        let handler = Handler {
+            callback: state.get_global::<LuaFunction<i32, i32>>("double").unwrap(),
+        };
+        let callback = handler.callback.clone();
+        drop(handler);
+
+        state.set_top(0);
+        state.do_string("double = nil; collectgarbage()").unwrap();
+        assert_eq!(callback.call(3).unwrap(), 6);
+    }
+
+    #[test]
+    fn lua_function_reports_errors_and_balances_stack() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string("function fail() error('boom') end")
+            .unwrap();
+
+        let fail = state.get_global::<LuaFunction<(), ()>>("fail").unwrap();
+        let top = state.get_top();
+
+        let result = fail.call(());
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("boom"));
+        assert_eq!(state.get_top(), top);
+    }
+
+    #[test]
+    fn handles_outlive_the_state() {
+        let state = State::new();
+        state
+            .do_string("function double(a) return a * 2 end")
+            .unwrap();
+        let double = state.get_global::<LuaFunction<i32, i32>>("double").unwrap();
+        let table = state.create_table(0, 0);
+        drop(state);
+
+        let called = std::panic::catch_unwind(AssertUnwindSafe(|| double.call(1)));
+        assert!(called.is_err());
+        drop(double);
+        drop(table);
+    }
+
+    #[test]
+    fn handles_created_in_coroutines() {
+        let state = State::new();
+        state.open_libs();
+        let kept = Rc::new(RefCell::new(vec![]));
+        let keep = kept.clone();
+        state
+            .set_global_fn("keep", move |table: Table| keep.borrow_mut().push(table))
+            .unwrap();
+        state
+            .do_string(
+                r#"
+                local co = coroutine.create(function() keep({ 1, 2, 3 }) end)
+                coroutine.resume(co)
+                co = nil
+                collectgarbage()
+                "#,
+            )
+            .unwrap();
+
+        let table = kept.borrow_mut().pop().unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get::<_, i32>(3).unwrap(), 3);
+    }
+
     #[test]
     fn push_bool() {
         let state = State::new();
@@ -313,6 +902,141 @@ pub mod tests {
         assert_eq!(result.unwrap(), value);
     }
 
+    #[test]
+    fn push_sequences() {
+        let state = State::new();
+        state.push(vec![10, 20, 30]);
+        state.push([1.5, 2.5]);
+        state.push(&["a", "b"][..]);
+        state.push(VecDeque::from(vec![true]));
+
+        assert_eq!(state.get_top(), 4);
+        assert!(state.is::<Vec<i32>>(-4));
+        assert_eq!(state.cast_to::<Vec<i32>>(-4).unwrap(), vec![10, 20, 30]);
+        assert_eq!(state.cast_to::<[f64; 2]>(-3).unwrap(), [1.5, 2.5]);
+        assert_eq!(state.cast_to::<Vec<String>>(-2).unwrap(), vec!["a", "b"]);
+        assert_eq!(
+            state.cast_to::<VecDeque<bool>>(-1).unwrap(),
+            VecDeque::from(vec![true])
+        );
+        assert_eq!(state.get_top(), 4);
+    }
+
+    #[test]
+    fn sequences_are_one_based() {
+        let state = State::new();
+        state.set_global("list", vec!["a", "b", "c"]).unwrap();
+        state.do_string("first, size = list[1], #list").unwrap();
+
+        assert_eq!(state.get_global::<String>("first").unwrap(), "a");
+        assert_eq!(state.get_global::<i32>("size").unwrap(), 3);
+    }
+
+    #[test]
+    fn sequence_of_converted_strings() {
+        let state = State::new();
+        state.open_libs();
+        state.do_string("list = { 1.5, 2.5, 3.5 }").unwrap();
+        let list = state.get_global::<Vec<String>>("list").unwrap();
+        state.do_string("collectgarbage()").unwrap();
+
+        assert_eq!(list, ["1.5", "2.5", "3.5"]);
+    }
+
+    #[test]
+    fn sequence_with_wrong_element_type() {
+        let state = State::new();
+        state.push((10, "foo"));
+        state.push(vec![1, 2]);
+        state.do_string("list = { 1, 'two', 3 }").unwrap();
+
+        assert!(state.get_global::<Vec<i32>>("list").is_err());
+        assert!(state.get_global::<[i32; 3]>("list").is_err());
+        assert!(state.cast_to::<[i32; 3]>(3).is_none());
+    }
+
+    #[test]
+    fn push_maps() {
+        let state = State::new();
+        let hash_map = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        let btree_map = BTreeMap::from([(1, "one".to_string()), (2, "two".to_string())]);
+        state.push(hash_map.clone());
+        state.push(btree_map.clone());
+
+        assert!(state.is::<HashMap<String, i32>>(-2));
+        assert_eq!(state.cast_to::<HashMap<String, i32>>(-2).unwrap(), hash_map);
+        assert_eq!(
+            state.cast_to::<BTreeMap<i32, String>>(-1).unwrap(),
+            btree_map
+        );
+        assert!(state.cast_to::<HashMap<String, bool>>(-2).is_none());
+        assert_eq!(state.get_top(), 2);
+    }
+
+    #[test]
+    fn push_maps_without_nil_or_nan_keys() {
+        #[derive(PartialEq, Eq, Hash)]
+        enum Key {
+            Name(&'static str),
+            NotANumber,
+        }
+
+        impl ToLua for Key {
+            fn to_lua(self, state: *mut sys::lua_State) {
+                match self {
+                    Key::Name(name) => name.to_lua(state),
+                    Key::NotANumber => f64::NAN.to_lua(state),
+                }
+            }
+        }
+
+        let state = State::new();
+        state.push(HashMap::from([(Some("a"), 1), (None, 2)]));
+        state.push(HashMap::from([(Key::Name("b"), 3), (Key::NotANumber, 4)]));
+
+        assert_eq!(
+            state.cast_to::<HashMap<String, i32>>(-2).unwrap(),
+            HashMap::from([("a".to_string(), 1)])
+        );
+        assert_eq!(
+            state.cast_to::<HashMap<String, i32>>(-1).unwrap(),
+            HashMap::from([("b".to_string(), 3)])
+        );
+        assert_eq!(state.get_top(), 2);
+    }
+
+    #[test]
+    fn push_set() {
+        let state = State::new();
+        state.set_global("set", HashSet::from(["a", "b"])).unwrap();
+        state
+            .do_string("has_a, has_c = set.a, set.c; other = { x = true, y = false }")
+            .unwrap();
+
+        assert!(state.get_global::<bool>("has_a").unwrap());
+        assert!(state.get_global::<()>("has_c").is_ok());
+        assert_eq!(
+            state.get_global::<HashSet<String>>("set").unwrap(),
+            HashSet::from(["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            state.get_global::<HashSet<String>>("other").unwrap(),
+            HashSet::from(["x".to_string()])
+        );
+    }
+
+    #[test]
+    fn nested_collections() {
+        let state = State::new();
+        let value = vec![HashMap::from([("x".to_string(), vec![1, 2])])];
+        state.push(value.clone());
+
+        assert_eq!(
+            state.cast_to::<Vec<HashMap<String, Vec<i32>>>>(-1).unwrap(),
+            value
+        );
+    }
+
     #[test]
     fn take_user_data_ref_from_stack() {
         struct Test;
@@ -356,6 +1080,8 @@ pub mod tests {
             }
         }
 
+        impl MetaTable for Math {}
+
         impl UserData for Math {
             fn name() -> *const i8 {
                 cstr!("Math")
@@ -375,11 +1101,12 @@ pub mod tests {
         assert_eq!(state.get_top(), 1);
         assert!(state.is::<Math>(-1));
 
-        let option = state.get_field::<LuaFunction<(f64, f64), f64>>(-1, "sum");
-        assert!(option.is_some());
+        let option =
+            state.get_field::<LuaFunction<(RelativeValue<Math>, f64, f64), f64>>(-1, "sum");
+        assert!(option.is_ok());
 
         let sum = option.unwrap();
-        let result = sum((10.0, 12.0));
+        let result = sum.call((ref_to!(Math, 1), 10.0, 12.0));
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 22.0);
     }
@@ -415,16 +1142,35 @@ pub mod tests {
         state.push(Test {});
         let option =
             state.get_field::<LuaFunction<(RelativeValue<Test>, i32, i32), (bool, f32)>>(-1, "foo");
-        assert!(option.is_some());
+        assert!(option.is_ok());
 
         let foo = option.unwrap();
-        let result = foo((ref_to!(Test, -2), 2, 3));
+        let result = foo.call((ref_to!(Test, 1), 2, 3));
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), (true, 5.0));
     }
 
     #[test]
-    fn proc_macro_pub_function() {}
+    fn proc_macro_pub_function() {
+        struct Test;
+
+        #[user_data]
+        impl Test {
+            pub fn sum(a: i32, b: i32) -> i32 {
+                a + b
+            }
+        }
+
+        let state = State::new();
+        state.push(Test {});
+
+        let sum = state
+            .get_field::<LuaFunction<(i32, i32), i32>>(-1, "sum")
+            .unwrap();
+        let result = sum.call((2, 3));
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 5);
+    }
 
     #[test]
     fn proc_macro_pub_method_manual_impl() {
@@ -438,6 +1184,8 @@ pub mod tests {
             }
         }
 
+        impl MetaTable for Test {}
+
         impl UserData for Test {
             fn name() -> *const i8 {
                 cstr!("Test")
@@ -458,7 +1206,7 @@ pub mod tests {
                         state.push(result);
                         <i32 as ToLua>::len() as std::ffi::c_int
                     }
-                    luajit2_sys::luaL_Reg {
+                    sys::luaL_Reg {
                         name: cstr!("foo"),
                         func: Some(step),
                     }
@@ -470,31 +1218,391 @@ pub mod tests {
         state.push(Test { a: 2 });
         let option =
             state.get_field::<LuaFunction<(RelativeValue<Test>, i32, i32), i32>>(-1, "foo");
-        assert!(option.is_some());
+        assert!(option.is_ok());
 
         let foo = option.unwrap();
-        let result = foo((ref_to!(Test, -2), 2, 3));
+        let result = foo.call((ref_to!(Test, 1), 2, 3));
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 2 + 2 + 3);
     }
 
     #[test]
     fn proc_macro_pub_method() {
-        // struct Test;
-
-        // #[user_data]
-        // impl Test {
-        //     pub fn foo(&self, a: i32, b: i32) -> i32 {
-        //         a + b
-        //     }
-        // }
-
-        // let state = State::new();
-        // state.push(Test {});
-        // state.get_field(-1, "foo");
-
-        // let result = state.protected_call::<_, i32>((ref_to!(Test, -2), 2, 3));
-        // assert!(result.is_ok());
-        // assert_eq!(result.unwrap(), 2 + 3);
+        struct Test;
+
+        #[user_data]
+        impl Test {
+            pub fn foo(&self, a: i32, b: i32) -> i32 {
+                a + b
+            }
+        }
+
+        let state = State::new();
+        state.push(Test {});
+        state
+            .get_field::<LuaFunction<(RelativeValue<Test>, i32, i32), i32>>(-1, "foo")
+            .unwrap();
+
+        let result = state.protected_call::<_, i32>((ref_to!(Test, 1), 2, 3));
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2 + 3);
+    }
+
+    #[test]
+    fn proc_macro_optional_trailing_args() {
+        struct Greeter;
+
+        #[user_data]
+        impl Greeter {
+            pub fn greet(&self, name: &str, greeting: Option<&str>) -> String {
+                format!("{}, {}", greeting.unwrap_or("hello"), name)
+            }
+
+            pub fn shout(&self, name: &str) -> String {
+                name.to_uppercase()
+            }
+        }
+
+        let state = State::new();
+        state.set_global("greeter", Greeter).unwrap();
+        state
+            .do_string(
+                "a = greeter:greet('soreto'); b = greeter:greet('soreto', 'hi'); c = greeter:shout('soreto')",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("a").unwrap(), "hello, soreto");
+        assert_eq!(state.get_global::<String>("b").unwrap(), "hi, soreto");
+        assert_eq!(state.get_global::<String>("c").unwrap(), "SORETO");
+    }
+
+    #[test]
+    fn push_option() {
+        let state = State::new();
+        state.push(Some(10));
+        state.push(None::<i32>);
+
+        assert_eq!(state.get_top(), 2);
+        assert!(state.is::<Option<i32>>(-1));
+        assert_eq!(state.cast_to::<Option<i32>>(-2).unwrap(), Some(10));
+        assert_eq!(state.cast_to::<Option<i32>>(-1).unwrap(), None);
+        assert!(state.cast_to::<Option<bool>>(-2).is_none());
+    }
+
+    #[test]
+    fn tuple_with_missing_optional_values() {
+        let state = State::new();
+        state.push(10);
+
+        assert_eq!(
+            state
+                .cast_to::<(i32, Option<i32>, Option<&str>)>(1)
+                .unwrap(),
+            (10, None, None)
+        );
+        assert!(state.cast_to::<(i32, i32)>(1).is_none());
+    }
+
+    #[test]
+    fn tuple_far_past_the_top() {
+        let state = State::new();
+        state.push(10);
+
+        assert_eq!(
+            state.cast_to::<(Option<i32>, Option<i32>)>(60).unwrap(),
+            (None, None)
+        );
+        assert!(state.cast_to::<(Option<i32>, i32)>(60).is_none());
+        assert_eq!(state.get_top(), 1);
+    }
+
+    #[test]
+    fn lua_function_with_optional_return() {
+        let state = State::new();
+        state
+            .do_string("function find(found) if found then return 1, 2 end return 1 end")
+            .unwrap();
+
+        let find = state
+            .get_global::<LuaFunction<bool, (i32, Option<i32>)>>("find")
+            .unwrap();
+        assert_eq!(find.call(true).unwrap(), (1, Some(2)));
+        assert_eq!(find.call(false).unwrap(), (1, None));
+    }
+
+    #[test]
+    fn proc_macro_method_panics() {
+        struct Account {
+            balance: i32,
+        }
+
+        #[user_data]
+        impl Account {
+            pub fn withdraw(&mut self, amount: i32) -> i32 {
+                assert!(amount <= self.balance, "insufficient funds");
+                self.balance -= amount;
+                self.balance
+            }
+        }
+
+        let state = State::new();
+        state.push(Account { balance: 10 });
+        let withdraw = state
+            .get_field::<LuaFunction<(RelativeValue<Account>, i32), i32>>(-1, "withdraw")
+            .unwrap();
+
+        assert_eq!(withdraw.call((ref_to!(Account, 1), 3)).unwrap(), 7);
+        match withdraw.call((ref_to!(Account, 1), 20)).unwrap_err() {
+            crate::Error::Panic(panic) => assert_eq!(panic.message(), "insufficient funds"),
+            error => panic!("unexpected error: {error}"),
+        }
+        assert_eq!(withdraw.call((ref_to!(Account, 1), 7)).unwrap(), 0);
+    }
+
+    #[test]
+    fn proc_macro_bad_arguments() {
+        struct Test;
+
+        #[user_data]
+        impl Test {
+            pub fn sum(&self, a: i32, b: i32) -> i32 {
+                a + b
+            }
+        }
+
+        let state = State::new();
+        state.open_libs();
+        state.set_global("test", Test).unwrap();
+        state
+            .do_string("ok, err = pcall(test.sum, test, 1, 'two'); ok2, err2 = pcall(test.sum)")
+            .unwrap();
+
+        assert!(!state.get_global::<bool>("ok").unwrap());
+        assert_eq!(
+            state.get_global::<String>("err").unwrap(),
+            "bad argument #3 (i32 expected, got string)"
+        );
+        assert_eq!(
+            state.get_global::<String>("err2").unwrap(),
+            "bad argument #1 (Test expected, got no value)"
+        );
+    }
+
+    #[test]
+    fn lua_func_panics() {
+        fn explode(_: &mut State) -> i32 {
+            panic!("raw function exploded")
+        }
+
+        struct Bomb;
+
+        impl MetaTable for Bomb {}
+
+        impl UserData for Bomb {
+            fn name() -> *const i8 {
+                cstr!("Bomb")
+            }
+
+            fn functions() -> Vec<sys::luaL_Reg> {
+                vec![lua_func!(Bomb, explode, "explode")]
+            }
+        }
+
+        let state = State::new();
+        state.set_global("bomb", Bomb).unwrap();
+        match state.do_string("bomb.explode()").unwrap_err() {
+            crate::Error::Panic(panic) => assert_eq!(panic.message(), "raw function exploded"),
+            error => panic!("unexpected error: {error}"),
+        }
+    }
+
+    #[test]
+    fn lua_errors_inside_raw_functions() {
+        fn check(state: &mut State) -> i32 {
+            let result = state.protected(|state| unsafe {
+                let value = state.cast_to::<i32>(1).unwrap_or_default();
+                ffi::luaL_error(state.ptr(), cstr!("bad input %d"), value);
+            });
+            match result {
+                Ok(()) => 0,
+                Err(error) => {
+                    state.push(error.to_string());
+                    1
+                }
+            }
+        }
+
+        struct Checker;
+
+        impl MetaTable for Checker {}
+
+        impl UserData for Checker {
+            fn name() -> *const i8 {
+                cstr!("Checker")
+            }
+
+            fn functions() -> Vec<sys::luaL_Reg> {
+                vec![lua_func!(Checker, check, "check")]
+            }
+        }
+
+        let state = State::new();
+        state.set_global("checker", Checker).unwrap();
+        state.do_string("result = checker.check(7)").unwrap();
+
+        let result = state.get_global::<String>("result").unwrap();
+        assert!(result.contains("bad input 7"), "{result}");
+        assert_eq!(state.get_top(), 1);
+    }
+
+    #[test]
+    fn metamethod_errors_are_returned() {
+        let state = State::new();
+        state.open_libs();
+        let ptr = state.ptr();
+        state
+            .set_global_fn("read", move |name: String| {
+                let state = State::from_raw(ptr);
+                state
+                    .get_global::<Option<i32>>(&name)
+                    .err()
+                    .map(|error| error.to_string())
+            })
+            .unwrap();
+        state
+            .do_string(
+                r#"
+                setmetatable(_G, {
+                    __index = function(_, key) error('undefined ' .. key) end,
+                    __newindex = function(_, key) error('read only ' .. key) end,
+                })
+                rawset(_G, 'result', read('missing'))
+                "#,
+            )
+            .unwrap();
+
+        let result = state.get_global::<String>("result").unwrap();
+        assert!(result.contains("undefined missing"), "{result}");
+        let error = state.get_global::<i32>("other").unwrap_err();
+        assert!(error.to_string().contains("undefined other"), "{error}");
+        let error = state.set_global("other", 1).unwrap_err();
+        assert!(error.to_string().contains("read only other"), "{error}");
+    }
+
+    // examples/greeter.rs, built without the vendored LuaJIT so it runs on the one of the test
+    // binary, in a target dir of its own so it doesn't wait on the lock of `cargo test`. Only
+    // linux exports the lua symbols of the test binary, see build.rs
+    #[cfg(target_os = "linux")]
+    fn build_greeter() -> std::path::PathBuf {
+        let target = concat!(env!("CARGO_MANIFEST_DIR"), "/target/host-module");
+        let status = std::process::Command::new(env!("CARGO"))
+            .args(["build", "--example", "greeter", "--no-default-features"])
+            .args([
+                "--manifest-path",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"),
+            ])
+            .args(["--target-dir", target])
+            .status()
+            .unwrap();
+        assert!(status.success(), "building examples/greeter.rs failed");
+        std::path::Path::new(target).join("debug/examples")
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn load_lua_module() {
+        let dir = build_greeter();
+        let path = dir.join("libgreeter.so");
+
+        let state = State::new();
+        state.open_libs();
+        state.set_global("path", path.to_str().unwrap()).unwrap();
+        state
+            .set_global("cpath", format!("{}/lib?.so", dir.display()))
+            .unwrap();
+        state
+            .do_string(
+                "
+                local open = assert(package.loadlib(path, 'luaopen_greeter'))
+                local greeter = open()
+                greeting, version = greeter.greet('lua'), greeter.version
+
+                package.cpath = cpath
+                required = require('greeter').greet('require')
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            state.get_global::<String>("greeting").unwrap(),
+            "hello, lua"
+        );
+        assert_eq!(state.get_global::<i32>("version").unwrap(), 1);
+        assert_eq!(
+            state.get_global::<String>("required").unwrap(),
+            "hello, require"
+        );
+    }
+
+    #[lua_function]
+    pub fn clamp(x: f64, lo: f64, hi: f64) -> f64 {
+        x.max(lo).min(hi)
+    }
+
+    #[lua_function]
+    fn greet(name: &str, greeting: Option<&str>) -> String {
+        format!("{}, {name}", greeting.unwrap_or("hello"))
+    }
+
+    #[lua_function]
+    fn count_args(state: &State) -> i32 {
+        let count = state.get_top();
+        state.push(count);
+        1
+    }
+
+    #[lua_function]
+    fn fail() {
+        panic!("free function exploded")
+    }
+
+    #[test]
+    fn proc_macro_lua_function() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("clamp", clamp_lua).unwrap();
+        state.set_global("count_args", count_args_lua).unwrap();
+        let module = state.create_table(0, 2);
+        module.set("greet", greet_lua).unwrap();
+        module.set("fail", fail_lua).unwrap();
+        state.set_global("module", module).unwrap();
+        state
+            .do_string(
+                "
+                clamped = clamp(15, 0, 10)
+                count = count_args(1, 2, 3)
+                greeting = module.greet('soreto')
+                _, bad = pcall(clamp, 1, 'x', 2)
+                _, panicked = pcall(module.fail)
+                panicked = tostring(panicked)
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<f64>("clamped").unwrap(), 10.0);
+        assert_eq!(state.get_global::<i32>("count").unwrap(), 3);
+        assert_eq!(
+            state.get_global::<String>("greeting").unwrap(),
+            "hello, soreto"
+        );
+        assert_eq!(
+            state.get_global::<String>("bad").unwrap(),
+            "bad argument #2 (f64 expected, got string)"
+        );
+        assert_eq!(
+            state.get_global::<String>("panicked").unwrap(),
+            "rust panic: free function exploded"
+        );
+        assert_eq!(clamp(-1.0, 0.0, 1.0), 0.0);
     }
 }
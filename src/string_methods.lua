@@ -0,0 +1,10 @@
+-- `__index` of strings once a sandbox exists: the methods of the live `string` library minus
+-- `dump`, so scripts can't reach bytecode through `("").dump` while functions the host adds to
+-- the library keep working as methods. Called with the library.
+local methods = ...
+
+return function(_, key)
+	if key ~= "dump" then
+		return methods[key]
+	end
+end
@@ -0,0 +1,442 @@
+use std::{ffi::c_void, marker::PhantomData};
+
+use crate::sys;
+
+use crate::{
+    error::Error,
+    ffi,
+    from_lua::FromLua,
+    reference::Reference,
+    state::State,
+    to_lua::{is_valid_key, ToLua},
+};
+
+/// Handle to a lua table pinned in the registry.
+///
+/// Every operation pushes the table, does its work and restores the stack, so the handle can be
+/// kept around and used from anywhere. For the same reason values are read as owned types
+/// (`String`, not `&str`).
+///
+/// Errors raised by metamethods are returned, rust functions called by lua can use tables
+/// without the error unwinding through them.
+pub struct Table(Reference);
+
+impl Table {
+    pub(crate) fn new(ptr: *mut sys::lua_State, idx: i32) -> Self {
+        Self(Reference::new(ptr, idx))
+    }
+
+    pub(crate) fn pop(ptr: *mut sys::lua_State) -> Self {
+        Self(Reference::pop(ptr))
+    }
+
+    pub(crate) fn push(&self, ptr: *mut sys::lua_State) {
+        self.0.push(ptr)
+    }
+
+    /// Main thread of the state the table lives in.
+    pub(crate) fn ptr(&self) -> *mut sys::lua_State {
+        self.0.ptr()
+    }
+
+    pub(crate) fn to_pointer(&self) -> *const c_void {
+        self.0.to_pointer()
+    }
+
+    fn with_table<T>(&self, f: impl FnOnce(&State) -> T) -> T {
+        let state = State::from_raw(self.0.ptr());
+        let top = state.get_top();
+        self.push(self.0.ptr());
+        let result = f(&state);
+        state.set_top(top);
+        result
+    }
+
+    /// `t[key]`, honoring `__index`. Read a missing key as `Option<V>`, `V` fails to convert
+    /// nil.
+    pub fn get<K, V>(&self, key: K) -> crate::Result<<V as FromLua<'static>>::Output>
+    where
+        K: ToLua,
+        V: for<'x> FromLua<'x, Output: 'static>,
+    {
+        self.with_table(|state| {
+            let ptr = state.ptr();
+            state.push(key);
+            ffi::get_table(ptr, -2)?;
+            V::from_lua(ptr, -1).ok_or_else(|| Error::conversion::<V>(ptr, -1, None))
+        })
+    }
+
+    /// `t[key] = value`, honoring `__newindex`. Nil and NaN keys are an error.
+    pub fn set<K: ToLua, V: ToLua>(&self, key: K, value: V) -> crate::Result<()> {
+        self.with_table(|state| {
+            state.push(key);
+            state.push(value);
+            ffi::set_table(state.ptr(), -3)
+        })
+    }
+
+    /// `rawget(t, key)`
+    pub fn raw_get<K, V>(&self, key: K) -> Option<<V as FromLua<'static>>::Output>
+    where
+        K: ToLua,
+        V: for<'x> FromLua<'x, Output: 'static>,
+    {
+        self.with_table(|state| {
+            state.push(key);
+            unsafe { sys::lua_rawget(self.0.ptr(), -2) };
+            state.cast_to::<V>(-1)
+        })
+    }
+
+    /// `rawset(t, key, value)`, nil and NaN keys are an error.
+    pub fn raw_set<K: ToLua, V: ToLua>(&self, key: K, value: V) -> crate::Result<()> {
+        self.with_table(|state| {
+            let ptr = state.ptr();
+            state.push(key);
+            state.push(value);
+            if !is_valid_key(ptr, -2) {
+                return Err(Error::runtime(format!(
+                    "table index is {}",
+                    if state.is::<()>(-2) { "nil" } else { "NaN" }
+                )));
+            }
+            unsafe { sys::lua_rawset(ptr, -3) };
+            Ok(())
+        })
+    }
+
+    /// Length of the sequence part, same as `#t` without `__len`.
+    pub fn len(&self) -> usize {
+        self.with_table(|_| unsafe { sys::lua_objlen(self.0.ptr(), -1) })
+    }
+
+    /// Whether the table has no entries at all, including the hash part.
+    pub fn is_empty(&self) -> bool {
+        self.with_table(|state| {
+            state.push(());
+            unsafe { sys::lua_next(self.0.ptr(), -2) == 0 }
+        })
+    }
+
+    /// Whether `t[key]` isn't nil, honoring `__index`.
+    pub fn contains_key<K: ToLua>(&self, key: K) -> crate::Result<bool> {
+        self.with_table(|state| {
+            state.push(key);
+            ffi::get_table(state.ptr(), -2)?;
+            Ok(!state.is::<()>(-1))
+        })
+    }
+
+    pub fn metatable(&self) -> Option<Table> {
+        self.with_table(|_| {
+            if unsafe { sys::lua_getmetatable(self.0.ptr(), -1) } != 0 {
+                Some(Table::new(self.0.ptr(), -1))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn set_metatable(&self, metatable: Option<&Table>) {
+        self.with_table(|state| {
+            match metatable {
+                Some(metatable) => metatable.push(self.0.ptr()),
+                None => state.push(()),
+            }
+            unsafe { sys::lua_setmetatable(self.0.ptr(), -2) };
+        })
+    }
+
+    /// Iterates over every entry through `lua_next`, skipping the ones that can't be converted
+    /// to `(K, V)`. Iteration stops early when `lua_next` raises an error, which happens when
+    /// the table is changed in ways `next` doesn't support along the way.
+    pub fn pairs<K, V>(&self) -> Pairs<K, V>
+    where
+        K: for<'x> FromLua<'x, Output: 'static>,
+        V: for<'x> FromLua<'x, Output: 'static>,
+    {
+        Pairs {
+            table: self.clone(),
+            key: None,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Iterates over `t[1]`, `t[2]`, ... until the first nil or the first value that can't be
+    /// converted to `V`.
+    pub fn sequence<V>(&self) -> Sequence<V>
+    where
+        V: for<'x> FromLua<'x, Output: 'static>,
+    {
+        Sequence {
+            table: self.clone(),
+            idx: 0,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl Clone for Table {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+pub struct Pairs<K, V> {
+    table: Table,
+    key: Option<Reference>,
+    done: bool,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> Iterator for Pairs<K, V>
+where
+    K: for<'x> FromLua<'x, Output: 'static>,
+    V: for<'x> FromLua<'x, Output: 'static>,
+{
+    type Item = (
+        <K as FromLua<'static>>::Output,
+        <V as FromLua<'static>>::Output,
+    );
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ptr = self.table.0.ptr();
+        while !self.done {
+            let item = self.table.with_table(|state| {
+                match &self.key {
+                    Some(key) => key.push(ptr),
+                    None => state.push(()),
+                }
+                if !ffi::next(ptr, -2).unwrap_or(false) {
+                    return None;
+                }
+
+                // pin the key before converting it, `lua_tolstring` changes numbers in place
+                let key = Reference::new(ptr, -2);
+                let item = (state.cast_to::<K>(-2), state.cast_to::<V>(-1));
+                Some((key, item))
+            });
+
+            match item {
+                Some((key, (Some(k), Some(v)))) => {
+                    self.key = Some(key);
+                    return Some((k, v));
+                }
+                Some((key, _)) => self.key = Some(key),
+                None => self.done = true,
+            }
+        }
+        None
+    }
+}
+
+pub struct Sequence<V> {
+    table: Table,
+    idx: i32,
+    done: bool,
+    _marker: PhantomData<V>,
+}
+
+impl<V> Iterator for Sequence<V>
+where
+    V: for<'x> FromLua<'x, Output: 'static>,
+{
+    type Item = <V as FromLua<'static>>::Output;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        self.idx += 1;
+        let value = self.table.with_table(|state| {
+            unsafe { sys::lua_rawgeti(self.table.0.ptr(), -1, self.idx) };
+            if state.is::<()>(-1) {
+                None
+            } else {
+                state.cast_to::<V>(-1)
+            }
+        });
+
+        self.done = value.is_none();
+        value
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{state::State, Table};
+
+    #[test]
+    fn get_and_set() {
+        let state = State::new();
+        let table = state.create_table(0, 2);
+        table.set("name", "soreto").unwrap();
+        table.set(1, 10).unwrap();
+
+        assert_eq!(table.get::<_, String>("name").unwrap(), "soreto");
+        assert_eq!(table.get::<_, i32>(1).unwrap(), 10);
+        assert!(table.get::<_, i32>("missing").is_err());
+        assert_eq!(table.get::<_, Option<i32>>("missing").unwrap(), None);
+        assert_eq!(state.get_top(), 0);
+    }
+
+    #[test]
+    fn raw_access_skips_metamethods() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string("t = setmetatable({}, { __index = function() return 42 end })")
+            .unwrap();
+
+        let table = state.globals().get::<_, Table>("t").unwrap();
+        assert_eq!(table.get::<_, i32>("foo").unwrap(), 42);
+        assert!(table.raw_get::<_, i32>("foo").is_none());
+        assert!(table.contains_key("foo").unwrap());
+
+        table.raw_set("foo", 1).unwrap();
+        assert_eq!(table.get::<_, i32>("foo").unwrap(), 1);
+    }
+
+    #[test]
+    fn metamethod_errors_are_returned() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string(
+                "t = setmetatable({}, {
+                    __index = function(_, key) error('no ' .. key) end,
+                    __newindex = function(_, key) error('read-only ' .. key) end,
+                })",
+            )
+            .unwrap();
+
+        let table = state.get_global::<Table>("t").unwrap();
+        let error = table.get::<_, i32>("foo").unwrap_err();
+        assert!(error.to_string().contains("no foo"), "{error}");
+        let error = table.set("bar", 1).unwrap_err();
+        assert!(error.to_string().contains("read-only bar"), "{error}");
+        assert!(table.contains_key("baz").is_err());
+
+        assert!(table.raw_set((), 1).is_err());
+        assert!(table.raw_set(f64::NAN, 1).is_err());
+        assert_eq!(state.get_top(), 1);
+    }
+
+    #[test]
+    fn metamethod_errors_inside_rust_functions() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("lookup", |(table, key): (Table, String)| {
+                match table.get::<_, Option<i32>>(key) {
+                    Ok(value) => format!("{value:?}"),
+                    Err(error) => format!("failed: {error}"),
+                }
+            })
+            .unwrap();
+        state
+            .do_string(
+                "local t = setmetatable({}, { __index = function() error('boom') end })
+                result = lookup(t, 'foo')",
+            )
+            .unwrap();
+
+        let result = state.get_global::<String>("result").unwrap();
+        assert!(
+            result.starts_with("failed: ") && result.contains("boom"),
+            "{result}"
+        );
+    }
+
+    #[test]
+    fn len_and_contains_key() {
+        let state = State::new();
+        let table = state.create_table(3, 0);
+        assert!(table.is_empty());
+
+        table.set(1, "a").unwrap();
+        table.set(2, "b").unwrap();
+        table.set(3, "c").unwrap();
+
+        assert!(!table.is_empty());
+        assert_eq!(table.len(), 3);
+        assert!(table.contains_key(2).unwrap());
+        assert!(!table.contains_key(4).unwrap());
+    }
+
+    #[test]
+    fn metatable() {
+        let state = State::new();
+        let table = state.create_table(0, 0);
+        assert!(table.metatable().is_none());
+
+        let metatable = state.create_table(0, 1);
+        metatable.set("__index", &table).unwrap();
+        table.set_metatable(Some(&metatable));
+        assert!(table.metatable().is_some());
+
+        table.set_metatable(None);
+        assert!(table.metatable().is_none());
+        assert_eq!(state.get_top(), 0);
+    }
+
+    #[test]
+    fn pairs() {
+        let state = State::new();
+        state
+            .do_string("t = { a = 1, b = 2, c = 3, [1] = 'skipped' }")
+            .unwrap();
+        state.set_top(0);
+
+        let table = state.globals().get::<_, Table>("t").unwrap();
+        let mut pairs = table.pairs::<String, i32>().collect::<Vec<_>>();
+        pairs.sort();
+
+        assert_eq!(
+            pairs,
+            vec![
+                ("a".to_string(), 1),
+                ("b".to_string(), 2),
+                ("c".to_string(), 3)
+            ]
+        );
+        assert_eq!(state.get_top(), 0);
+    }
+
+    #[test]
+    fn pairs_with_number_keys() {
+        let state = State::new();
+        state.do_string("t = { 10, 20, 30 }").unwrap();
+
+        let table = state.globals().get::<_, Table>("t").unwrap();
+        let mut keys = table
+            .pairs::<String, i32>()
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        assert_eq!(keys, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn sequence() {
+        let state = State::new();
+        state.do_string("t = { 1, 2, 3, nil, 5 }").unwrap();
+
+        let table = state.globals().get::<_, Table>("t").unwrap();
+        let values = table.sequence::<i32>().collect::<Vec<_>>();
+        assert_eq!(values, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn globals() {
+        let state = State::new();
+        state.globals().set("answer", 42).unwrap();
+
+        assert_eq!(state.get_global::<i32>("answer").unwrap(), 42);
+    }
+}
@@ -1,17 +1,49 @@
-use luajit2_sys as sys;
-use macros::{cstr, generate_to_lua_tuple_impl};
+use crate::sys;
+use macros::generate_to_lua_tuple_impl;
 use std::{
-    ffi::{c_void, CString},
-    mem::size_of,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
 };
 
-use crate::{state::State, RawFunction, RelativeValue, UserData};
+use crate::{
+    bytes, int64, state::State, user_data, ByteBuf, Bytes, Int64, LuaFunction, RawFunction,
+    RelativeValue, Table, UInt64, UserData,
+};
 
 pub trait ToLua {
     fn to_lua(self, state: *mut sys::lua_State);
     fn len() -> i32 {
         1
     }
+
+    /// Pushes the value, returning the error `to_lua` would panic with for the types whose
+    /// conversion can fail. Values pushed before the error stay on the stack.
+    #[doc(hidden)]
+    fn try_to_lua(self, state: *mut sys::lua_State) -> crate::Result<()>
+    where
+        Self: Sized,
+    {
+        self.to_lua(state);
+        Ok(())
+    }
+
+    /// Pushes a `Vec` of this type, as a sequence unless overridden.
+    #[doc(hidden)]
+    fn push_vec(values: Vec<Self>, state: *mut sys::lua_State)
+    where
+        Self: Sized,
+    {
+        push_sequence(state, values.into_iter())
+    }
+
+    /// Pushes a slice of this type, as a sequence unless overridden.
+    #[doc(hidden)]
+    fn push_slice(values: &[Self], state: *mut sys::lua_State)
+    where
+        Self: Sized + Clone,
+    {
+        push_sequence(state, values.iter().cloned())
+    }
 }
 
 impl<T> ToLua for RelativeValue<T> {
@@ -20,17 +52,72 @@ impl<T> ToLua for RelativeValue<T> {
     }
 }
 
-impl ToLua for i32 {
+macro_rules! impl_small_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl ToLua for $ty {
+                #[inline]
+                fn to_lua(self, state: *mut sys::lua_State) {
+                    unsafe { sys::lua_pushinteger(state, self as isize) }
+                }
+            }
+        )*
+    };
+}
+
+impl_small_integer!(i8, i16, i32, u16, u32);
+
+/// `Vec<u8>` and `&[u8]` are pushed as strings, like `ByteBuf`.
+impl ToLua for u8 {
     #[inline]
     fn to_lua(self, state: *mut sys::lua_State) {
         unsafe { sys::lua_pushinteger(state, self as isize) }
     }
+
+    fn push_vec(values: Vec<Self>, state: *mut sys::lua_State) {
+        bytes::push_bytes(state, &values)
+    }
+
+    fn push_slice(values: &[Self], state: *mut sys::lua_State) {
+        bytes::push_bytes(state, values)
+    }
+}
+
+// lua numbers are doubles, which hold every integer up to 2^53. Larger values are pushed as
+// `int64_t`/`uint64_t` cdata rather than losing precision, and panic past 64 bits.
+macro_rules! impl_large_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl ToLua for $ty {
+                #[inline]
+                fn to_lua(self, state: *mut sys::lua_State) {
+                    match i128::try_from(self) {
+                        Ok(value) => int64::push_integer(state, value),
+                        Err(_) => panic!("{self} doesn't fit in 64 bits"),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_large_integer!(i64, u64, i128, u128, isize, usize);
+
+impl ToLua for Int64 {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        if let Err(error) = int64::push_int64(state, self.0) {
+            panic!("can't push {self:?} as cdata: {error}");
+        }
+    }
 }
 
-impl ToLua for i64 {
+impl ToLua for UInt64 {
     #[inline]
     fn to_lua(self, state: *mut sys::lua_State) {
-        unsafe { sys::lua_pushinteger(state, self as isize) }
+        if let Err(error) = int64::push_uint64(state, self.0) {
+            panic!("can't push {self:?} as cdata: {error}");
+        }
     }
 }
 
@@ -58,20 +145,49 @@ impl ToLua for bool {
 impl ToLua for &str {
     #[inline]
     fn to_lua(self, state: *mut sys::lua_State) {
-        #[allow(temporary_cstring_as_ptr)]
-        unsafe {
-            sys::lua_pushstring(state, CString::new(self).unwrap().as_ptr())
-        }
+        bytes::push_bytes(state, self.as_bytes())
     }
 }
 
 impl ToLua for String {
     #[inline]
     fn to_lua(self, state: *mut sys::lua_State) {
-        #[allow(temporary_cstring_as_ptr)]
-        unsafe {
-            sys::lua_pushstring(state, CString::new(self).unwrap().as_ptr())
-        }
+        bytes::push_bytes(state, self.as_bytes())
+    }
+}
+
+impl ToLua for &String {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        bytes::push_bytes(state, self.as_bytes())
+    }
+}
+
+impl ToLua for Cow<'_, str> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        bytes::push_bytes(state, self.as_bytes())
+    }
+}
+
+impl ToLua for Bytes<'_> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        bytes::push_bytes(state, self.0)
+    }
+}
+
+impl ToLua for ByteBuf {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        bytes::push_bytes(state, &self.0)
+    }
+}
+
+impl ToLua for &ByteBuf {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        bytes::push_bytes(state, &self.0)
     }
 }
 
@@ -82,6 +198,30 @@ impl ToLua for () {
     }
 }
 
+impl<T: ToLua> ToLua for Option<T> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        match self {
+            Some(value) => value.to_lua(state),
+            None => (0..T::len()).for_each(|_| unsafe { sys::lua_pushnil(state) }),
+        }
+    }
+
+    fn try_to_lua(self, state: *mut sys::lua_State) -> crate::Result<()> {
+        match self {
+            Some(value) => value.try_to_lua(state),
+            None => {
+                self.to_lua(state);
+                Ok(())
+            }
+        }
+    }
+
+    fn len() -> i32 {
+        T::len()
+    }
+}
+
 impl ToLua for RawFunction {
     #[inline]
     fn to_lua(self, state: *mut sys::lua_State) {
@@ -89,26 +229,126 @@ impl ToLua for RawFunction {
     }
 }
 
-impl<T: UserData> ToLua for T {
+impl<'a, A, B> ToLua for LuaFunction<'a, A, B> {
+    #[inline]
     fn to_lua(self, state: *mut sys::lua_State) {
-        let size = size_of::<T>();
-        let name = T::name();
-        let methods = T::functions();
-        let ptr = Box::into_raw(Box::new(self));
+        self.push(state)
+    }
+}
 
-        unsafe {
-            let managed_ptr = sys::lua_newuserdata(state, size);
-            std::ptr::copy_nonoverlapping(ptr as *mut c_void, managed_ptr, size);
+impl<'a, A, B> ToLua for &LuaFunction<'a, A, B> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        self.push(state)
+    }
+}
 
-            if sys::luaL_newmetatable(state, name) != 0 {
-                sys::lua_newtable(state);
-                sys::luaL_register(state, std::ptr::null(), methods.as_ptr());
-                sys::lua_setfield(state, -2, cstr!("__index"));
-            }
+impl ToLua for Table {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        self.push(state)
+    }
+}
+
+impl ToLua for &Table {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        self.push(state)
+    }
+}
 
-            sys::lua_setmetatable(state, -2);
+fn push_sequence<T: ToLua>(state: *mut sys::lua_State, values: impl ExactSizeIterator<Item = T>) {
+    unsafe { sys::lua_createtable(state, values.len() as i32, 0) };
+    for (i, value) in values.enumerate() {
+        value.to_lua(state);
+        unsafe { sys::lua_rawseti(state, -2, i as i32 + 1) };
+    }
+}
+
+/// Entries with a key that isn't a single value or pushes nil or NaN are left out, `lua_rawset`
+/// would raise an error for them.
+fn push_map<K: ToLua, V: ToLua>(
+    state: *mut sys::lua_State,
+    entries: impl ExactSizeIterator<Item = (K, V)>,
+) {
+    unsafe { sys::lua_createtable(state, 0, entries.len() as i32) };
+    let table = unsafe { sys::lua_gettop(state) };
+    for (key, value) in entries {
+        key.to_lua(state);
+        if unsafe { sys::lua_gettop(state) } != table + 1 || !is_valid_key(state, -1) {
+            unsafe { sys::lua_settop(state, table) };
+            continue;
+        }
+        value.to_lua(state);
+        unsafe {
+            sys::lua_settop(state, table + 2);
+            sys::lua_rawset(state, table);
         }
     }
 }
 
+pub(crate) fn is_valid_key(state: *mut sys::lua_State, idx: i32) -> bool {
+    match unsafe { sys::lua_type(state, idx) } as u32 {
+        sys::LUA_TNIL => false,
+        sys::LUA_TNUMBER => !unsafe { sys::lua_tonumber(state, idx) }.is_nan(),
+        _ => true,
+    }
+}
+
+impl<T: ToLua> ToLua for Vec<T> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        T::push_vec(self, state)
+    }
+}
+
+impl<T: ToLua> ToLua for VecDeque<T> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        push_sequence(state, self.into_iter())
+    }
+}
+
+impl<T: ToLua, const N: usize> ToLua for [T; N] {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        push_sequence(state, self.into_iter())
+    }
+}
+
+impl<T: ToLua + Clone> ToLua for &[T] {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        T::push_slice(self, state)
+    }
+}
+
+impl<K: ToLua, V: ToLua, S> ToLua for HashMap<K, V, S> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        push_map(state, self.into_iter())
+    }
+}
+
+impl<K: ToLua, V: ToLua> ToLua for BTreeMap<K, V> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        push_map(state, self.into_iter())
+    }
+}
+
+/// Pushed as `{ [value] = true }`.
+impl<T: ToLua, S> ToLua for HashSet<T, S> {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        push_map(state, self.into_iter().map(|value| (value, true)))
+    }
+}
+
+impl<T: UserData> ToLua for T {
+    fn to_lua(self, state: *mut sys::lua_State) {
+        user_data::push(state, self)
+    }
+}
+
 generate_to_lua_tuple_impl!(25);
@@ -0,0 +1,187 @@
+use std::{
+    ffi::{c_int, CStr},
+    fmt,
+};
+
+use crate::sys;
+use macros::cstr;
+
+use crate::ffi;
+
+pub(crate) const HANDLER_KEY: *const i8 = cstr!("lofy.message_handler");
+const TRACEBACK_KEY: *const i8 = cstr!("lofy.traceback");
+
+/// One level of the lua call stack, innermost first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Short chunk name, such as `[string "..."]` or `[C]`.
+    pub source: String,
+    /// Line being executed, `None` when no line information is available.
+    pub line: Option<u32>,
+    /// Name of the function, when lua can tell how it was called.
+    pub name: Option<String>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        match &self.name {
+            Some(name) => write!(f, ": in function '{name}'"),
+            None => write!(f, ": in ?"),
+        }
+    }
+}
+
+/// Inserts the message handler at `func`, moving the function (and anything above it) one
+/// slot up.
+pub(crate) fn insert_handler(ptr: *mut sys::lua_State, func: i32) {
+    unsafe {
+        sys::lua_getfield(ptr, sys::LUA_REGISTRYINDEX, HANDLER_KEY);
+        if sys::lua_isfunction(ptr, -1) == 0 {
+            sys::lua_pop(ptr, 1);
+            sys::lua_pushcfunction(ptr, ffi::cfunction(handler));
+        }
+        sys::lua_insert(ptr, func);
+    }
+}
+
+/// Default message handler, keeps the error object and attaches the frames of the stack
+/// that raised it.
+unsafe extern "C-unwind" fn handler(ptr: *mut sys::lua_State) -> c_int {
+    ffi::push_udata(ptr, TRACEBACK_KEY, collect(ptr, 1), |_| {});
+
+    // the original object lives in the environment table, it doesn't have to be a string
+    sys::lua_createtable(ptr, 1, 0);
+    sys::lua_pushvalue(ptr, 1);
+    sys::lua_rawseti(ptr, -2, 1);
+    sys::lua_setfenv(ptr, -2);
+    1
+}
+
+/// Walks the call stack from `level` outwards.
+fn collect(ptr: *mut sys::lua_State, level: i32) -> Vec<Frame> {
+    let mut frames = vec![];
+    let mut ar = unsafe { std::mem::zeroed::<sys::lua_Debug>() };
+    let mut level = level;
+    while unsafe { sys::lua_getstack(ptr, level, &mut ar) } != 0 {
+        unsafe { sys::lua_getinfo(ptr, cstr!("Snl"), &mut ar) };
+        let name = (!ar.name.is_null()).then(|| {
+            unsafe { CStr::from_ptr(ar.name) }
+                .to_string_lossy()
+                .into_owned()
+        });
+        let source = unsafe { CStr::from_ptr(ar.short_src.as_ptr()) };
+
+        frames.push(Frame {
+            source: source.to_string_lossy().into_owned(),
+            line: u32::try_from(ar.currentline).ok(),
+            name,
+        });
+        level += 1;
+    }
+    frames
+}
+
+/// Replaces an error object produced by the default handler at the top of the stack with the
+/// original one, returning the frames it carried.
+pub(crate) fn unwrap(ptr: *mut sys::lua_State) -> Option<Vec<Frame>> {
+    let frames = ffi::get_udata::<Vec<Frame>>(ptr, -1, TRACEBACK_KEY)?;
+    unsafe {
+        let frames = (*frames).clone();
+        sys::lua_getfenv(ptr, -1);
+        sys::lua_rawgeti(ptr, -1, 1);
+        sys::lua_replace(ptr, -3);
+        sys::lua_pop(ptr, 1);
+        Some(frames)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{state::State, Error, LuaFunction};
+
+    const CODE: &str = "\
+local function inner(t)
+    return t.x
+end
+
+function outer(t)
+    local value = inner(t)
+    return value
+end
+";
+
+    fn traceback(error: Error) -> Vec<super::Frame> {
+        match error {
+            Error::Runtime { traceback, .. } => traceback,
+            error => panic!("unexpected error: {error}"),
+        }
+    }
+
+    #[test]
+    fn frames_of_a_runtime_error() {
+        let state = State::new();
+        let code = format!("{CODE}outer(nil)");
+        let frames = traceback(state.do_string(&code).unwrap_err());
+
+        assert_eq!(frames[0].line, Some(2));
+        assert_eq!(frames[0].name.as_deref(), Some("inner"));
+        assert_eq!(frames[1].line, Some(6));
+        assert_eq!(frames[1].name.as_deref(), Some("outer"));
+        assert_eq!(frames[2].line, Some(9));
+        assert!(frames[0]
+            .source
+            .starts_with("[string \"local function inner"));
+    }
+
+    #[test]
+    fn lua_function_errors_carry_frames() {
+        let state = State::new();
+        state.do_string(CODE).unwrap();
+
+        let outer = state.get_global::<LuaFunction<(), ()>>("outer").unwrap();
+        let top = state.get_top();
+        let error = outer.call(()).unwrap_err();
+        assert!(error.to_string().contains("stack traceback:"));
+
+        let frames = traceback(error);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].line, Some(6));
+        assert_eq!(state.get_top(), top);
+    }
+
+    #[test]
+    fn frames_through_rust_functions() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global_fn("check", |value: i32| value).unwrap();
+        let frames = traceback(state.do_string("\nlocal x = check('x')").unwrap_err());
+
+        assert_eq!(frames[0].source, "[C]");
+        assert_eq!(frames[0].name.as_deref(), Some("check"));
+        assert_eq!(frames[1].line, Some(2));
+    }
+
+    #[test]
+    fn custom_message_handler() {
+        let state = State::new();
+        state.open_libs();
+        let handler = state.create_function(|message: String| format!("handled: {message}"));
+        state.set_message_handler(Some(&handler));
+
+        match state.do_string("error('boom', 0)").unwrap_err() {
+            Error::Runtime { message, traceback } => {
+                assert_eq!(message, "handled: boom");
+                assert!(traceback.is_empty());
+            }
+            error => panic!("unexpected error: {error}"),
+        }
+
+        state.set_message_handler::<(), ()>(None);
+        assert!(!traceback(state.do_string("error('boom')").unwrap_err()).is_empty());
+        assert_eq!(state.get_top(), 0);
+    }
+}
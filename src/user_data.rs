@@ -0,0 +1,1171 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    ffi::{c_int, CStr, CString},
+    mem::{align_of, size_of},
+    sync::Mutex,
+};
+
+use crate::sys;
+use macros::cstr;
+
+use crate::{error::Error, ffi, reference::Reference, Extension, UserData};
+
+/// Stored in front of every value pushed through [`UserData`], the value itself follows at the
+/// first offset that satisfies its alignment.
+struct Header {
+    alive: bool,
+    /// Pending async calls and running rust functions holding a shared reference to the value,
+    /// which can't be mutably borrowed or moved out in the meantime.
+    borrows: usize,
+    /// A running rust function holds a mutable reference to the value.
+    borrowed_mut: bool,
+}
+
+/// Lua only guarantees pointer alignment for userdata blocks, so room for the worst padding is
+/// reserved and the value offset is computed from the block address.
+fn value_ptr<T>(block: *mut Header) -> *mut T {
+    let start = block as usize + size_of::<Header>();
+    let aligned = (start + align_of::<T>() - 1) & !(align_of::<T>() - 1);
+    (block as *mut u8).wrapping_add(aligned - block as usize) as *mut T
+}
+
+/// Moves `value` into a new userdata block. Its metatable, registered under `T::key()`, holds
+/// the methods of `T` and of its extension blocks, its metamethods, a `__gc` that drops it, the
+/// type name in `__name` and its own key in `__key`. `__metatable` hides it from scripts.
+/// Types with properties get `__index`/`__newindex` functions looking them up before the methods.
+pub(crate) fn push<T: UserData>(ptr: *mut sys::lua_State, value: T) {
+    unsafe {
+        let size = size_of::<Header>() + align_of::<T>() - 1 + size_of::<T>();
+        let block = sys::lua_newuserdata(ptr, size) as *mut Header;
+        block.write(Header {
+            alive: true,
+            borrows: 0,
+            borrowed_mut: false,
+        });
+        value_ptr::<T>(block).write(value);
+
+        if sys::luaL_newmetatable(ptr, T::key()) != 0 {
+            let extensions = extensions::<T>();
+            let mut methods = T::functions();
+            methods.extend(extensions.iter().flat_map(|ext| (ext.functions)()));
+            sys::lua_newtable(ptr);
+            register(ptr, methods);
+            let mut properties = T::properties();
+            properties.extend(extensions.iter().flat_map(|ext| (ext.properties)()));
+            if properties.is_empty() {
+                sys::lua_setfield(ptr, -2, cstr!("__index"));
+            } else {
+                push_accessors(ptr, &properties, |property| property.get);
+                push_accessors(ptr, &properties, |property| property.set);
+                // methods, getters, setters
+                sys::lua_pushvalue(ptr, -2);
+                sys::lua_insert(ptr, -3);
+                sys::lua_pushcclosure(ptr, ffi::cfunction(new_index), 2);
+                sys::lua_setfield(ptr, -4, cstr!("__newindex"));
+                sys::lua_pushcclosure(ptr, ffi::cfunction(index), 2);
+                sys::lua_setfield(ptr, -2, cstr!("__index"));
+            }
+
+            let mut meta_methods = T::meta_methods();
+            meta_methods.extend(extensions.iter().flat_map(|ext| (ext.meta_methods)()));
+            register(ptr, meta_methods);
+
+            sys::lua_pushcfunction(ptr, ffi::cfunction(gc::<T>));
+            sys::lua_setfield(ptr, -2, cstr!("__gc"));
+            sys::lua_pushstring(ptr, T::name());
+            sys::lua_setfield(ptr, -2, cstr!("__name"));
+            sys::lua_pushstring(ptr, T::key());
+            sys::lua_setfield(ptr, -2, cstr!("__key"));
+            sys::lua_pushboolean(ptr, 0);
+            sys::lua_setfield(ptr, -2, cstr!("__metatable"));
+        }
+        sys::lua_setmetatable(ptr, -2);
+    }
+}
+
+/// Adds `functions` to the table at the top of the stack.
+unsafe fn register(ptr: *mut sys::lua_State, mut functions: Vec<sys::luaL_Reg>) {
+    functions.push(sys::luaL_Reg {
+        name: std::ptr::null(),
+        func: None,
+    });
+    sys::luaL_register(ptr, std::ptr::null(), functions.as_ptr());
+}
+
+/// Publishes the class table of `T` as a global named `T::name()`. It holds the functions of
+/// `T` and the values of its constants, `new` is also reachable by calling the table.
+pub(crate) fn register_type<T: UserData>(ptr: *mut sys::lua_State) -> crate::Result<()> {
+    unsafe {
+        let extensions = extensions::<T>();
+        let mut functions = T::functions();
+        functions.extend(extensions.iter().flat_map(|ext| (ext.functions)()));
+        sys::lua_newtable(ptr);
+        register(ptr, functions);
+
+        let mut constants = T::constants();
+        constants.extend(extensions.iter().flat_map(|ext| (ext.constants)()));
+        for constant in constants {
+            // the generated functions only push the value, they're run in place
+            if let Some(push) = constant.func {
+                std::mem::transmute::<crate::RawFunction, ffi::UnwindFunction>(push)(ptr);
+                sys::lua_setfield(ptr, -2, constant.name);
+            }
+        }
+
+        sys::lua_getfield(ptr, -1, cstr!("new"));
+        if sys::lua_iscfunction(ptr, -1) != 0 {
+            sys::lua_createtable(ptr, 0, 1);
+            sys::lua_insert(ptr, -2);
+            sys::lua_pushcclosure(ptr, ffi::cfunction(construct), 1);
+            sys::lua_setfield(ptr, -2, cstr!("__call"));
+            sys::lua_setmetatable(ptr, -2);
+        } else {
+            sys::lua_pop(ptr, 1);
+        }
+        sys::lua_pushstring(ptr, T::name());
+        sys::lua_insert(ptr, -2);
+        ffi::set_table(ptr, sys::LUA_GLOBALSINDEX)
+    }
+}
+
+/// `__call` of class tables, runs the constructor in its upvalue without the class table.
+unsafe extern "C-unwind" fn construct(ptr: *mut sys::lua_State) -> c_int {
+    let new = sys::lua_tocfunction(ptr, ffi::upvalue_index(1));
+    sys::lua_remove(ptr, 1);
+    match new {
+        Some(new) => std::mem::transmute::<crate::RawFunction, ffi::UnwindFunction>(new)(ptr),
+        None => 0,
+    }
+}
+
+/// `#[user_data(extend)]` blocks registered for `T`.
+fn extensions<T: UserData>() -> Vec<&'static Extension> {
+    let ty = std::any::type_name::<T>();
+    inventory::iter::<Extension>
+        .into_iter()
+        .filter(|ext| (ext.ty)() == ty)
+        .collect()
+}
+
+/// Whether the value at `idx` was pushed as a `T`, told by the identity of its metatable.
+pub(crate) fn is<T: UserData>(ptr: *mut sys::lua_State, idx: i32) -> bool {
+    ffi::test_udata::<Header>(ptr, idx, T::key()).is_some()
+}
+
+/// The value at `idx`, `None` for anything but a live `T` or while it's mutably borrowed.
+/// Inside a rust function called by lua, the value counts as borrowed until it returns.
+pub(crate) fn get<T: UserData>(ptr: *mut sys::lua_State, idx: i32) -> Option<*mut T> {
+    let block = ffi::test_udata::<Header>(ptr, idx, T::key())?;
+    if unsafe { !(*block).alive || (*block).borrowed_mut } {
+        return None;
+    }
+    hold(ptr, idx, block, false);
+    Some(value_ptr::<T>(block))
+}
+
+/// [`get`] for a mutable reference, also `None` while anything else borrows the value.
+pub(crate) fn get_mut<T: UserData>(ptr: *mut sys::lua_State, idx: i32) -> Option<*mut T> {
+    let block = ffi::test_udata::<Header>(ptr, idx, T::key())?;
+    if unsafe { !is_free(block) } {
+        return None;
+    }
+    hold(ptr, idx, block, true);
+    Some(value_ptr::<T>(block))
+}
+
+/// Moves the value out of the userdata at `idx`, later uses see it as destroyed. `None` while
+/// an async call or a running rust function borrows the value.
+pub(crate) fn take<T: UserData>(ptr: *mut sys::lua_State, idx: i32) -> Option<T> {
+    let block = ffi::test_udata::<Header>(ptr, idx, T::key())?;
+    unsafe {
+        if !is_free(block) {
+            return None;
+        }
+        (*block).alive = false;
+        Some(value_ptr::<T>(block).read())
+    }
+}
+
+type Names = Mutex<BTreeMap<&'static str, &'static CStr>>;
+
+/// The entry of `T` in `names`, created by `name` once per type and kept for the life of the
+/// program.
+fn interned<T: ?Sized>(names: &Names, name: impl FnOnce() -> String) -> *const i8 {
+    let mut names = names.lock().unwrap_or_else(|error| error.into_inner());
+    let name = names.entry(std::any::type_name::<T>()).or_insert_with(|| {
+        let name = CString::new(name()).unwrap_or_default();
+        Box::leak(name.into_boxed_c_str())
+    });
+    name.as_ptr()
+}
+
+/// Name of a type whose name depends on its generic arguments, `Pool<Enemy>` and `Pool<Item>`
+/// get metatables of their own.
+pub(crate) fn generic_name<T: ?Sized>() -> *const i8 {
+    static NAMES: Names = Mutex::new(BTreeMap::new());
+    interned::<T>(&NAMES, crate::error::type_name::<T>)
+}
+
+/// Metatable key of a type that isn't known until runtime, its fully qualified name.
+pub(crate) fn key<T: ?Sized>() -> *const i8 {
+    static KEYS: Names = Mutex::new(BTreeMap::new());
+    interned::<T>(&KEYS, || std::any::type_name::<T>().to_string())
+}
+
+pub(crate) fn name<T: UserData>() -> String {
+    unsafe { CStr::from_ptr(T::name()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Type name of the userdata at `idx`, `None` if it wasn't pushed through [`UserData`].
+pub(crate) fn type_name(ptr: *mut sys::lua_State, idx: i32) -> Option<String> {
+    let (name, block) = header(ptr, idx)?;
+    Some(if unsafe { (*block).alive } {
+        name
+    } else {
+        format!("destroyed {name}")
+    })
+}
+
+/// [`type_name`] for conversion errors, which also tells borrowed values apart.
+pub(crate) fn describe(ptr: *mut sys::lua_State, idx: i32) -> Option<String> {
+    let (name, block) = header(ptr, idx)?;
+    Some(match unsafe { &*block } {
+        Header { alive: false, .. } => format!("destroyed {name}"),
+        Header { borrows: 1.., .. }
+        | Header {
+            borrowed_mut: true, ..
+        } => {
+            format!("borrowed {name}")
+        }
+        _ => name,
+    })
+}
+
+/// Alive and not borrowed at all, so it can be mutably borrowed or moved out.
+unsafe fn is_free(block: *mut Header) -> bool {
+    (*block).alive && (*block).borrows == 0 && !(*block).borrowed_mut
+}
+
+/// The borrows taken by the rust functions running on this thread, see [`scope`].
+#[derive(Default)]
+struct Scope {
+    depth: usize,
+    borrows: Vec<Held>,
+}
+
+thread_local! {
+    static SCOPE: RefCell<Scope> = RefCell::default();
+}
+
+/// Runs `f`, the body of a rust function called by lua. The userdata values it borrows through
+/// [`get`] and [`get_mut`] stay borrowed until it returns, so they can't be aliased by another
+/// argument or taken from under it.
+pub(crate) fn scope<R>(f: impl FnOnce() -> R) -> R {
+    let start = SCOPE.with_borrow_mut(|scope| {
+        scope.depth += 1;
+        scope.borrows.len()
+    });
+    let result = f();
+    let released = SCOPE.with_borrow_mut(|scope| {
+        scope.depth -= 1;
+        scope.borrows.split_off(start)
+    });
+    drop(released);
+    result
+}
+
+/// Records a borrow of the value at `idx` in the running [`scope`], outside of one the
+/// reference isn't tracked.
+fn hold(ptr: *mut sys::lua_State, idx: i32, block: *mut Header, exclusive: bool) {
+    SCOPE.with_borrow_mut(|scope| {
+        if scope.depth == 0 {
+            return;
+        }
+        unsafe {
+            if exclusive {
+                (*block).borrowed_mut = true;
+            } else {
+                (*block).borrows += 1;
+            }
+        }
+        let anchor = Reference::new(ptr, idx);
+        scope.borrows.push(Held {
+            block,
+            exclusive,
+            _anchor: anchor,
+        });
+    })
+}
+
+/// Borrow of a userdata value by a running rust function, anchored so the block outlives it.
+struct Held {
+    block: *mut Header,
+    exclusive: bool,
+    _anchor: Reference,
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        unsafe {
+            if self.exclusive {
+                (*self.block).borrowed_mut = false;
+            } else {
+                (*self.block).borrows -= 1;
+            }
+        }
+    }
+}
+
+/// Marks the value at `idx` as borrowed by an async call until the returned guard is dropped,
+/// `None` if it isn't a live userdata pushed through [`UserData`].
+pub(crate) fn borrow(ptr: *mut sys::lua_State, idx: i32) -> Option<Borrow> {
+    let (_, block) = header(ptr, idx)?;
+    unsafe {
+        if !(*block).alive {
+            return None;
+        }
+        (*block).borrows += 1;
+    }
+    Some(Borrow(block))
+}
+
+/// Shared borrow of a userdata value held by a pending async call, see [`borrow`].
+pub(crate) struct Borrow(*mut Header);
+
+impl Drop for Borrow {
+    fn drop(&mut self) {
+        unsafe { (*self.0).borrows -= 1 };
+    }
+}
+
+/// The type name and header of the userdata at `idx`, found through the key its metatable
+/// records.
+fn header(ptr: *mut sys::lua_State, idx: i32) -> Option<(String, *mut Header)> {
+    unsafe {
+        if sys::lua_type(ptr, idx) != sys::LUA_TUSERDATA as c_int
+            || sys::lua_getmetatable(ptr, idx) == 0
+        {
+            return None;
+        }
+
+        sys::lua_getfield(ptr, -1, cstr!("__name"));
+        sys::lua_getfield(ptr, -2, cstr!("__key"));
+        let name = crate::bytes::read_bytes(ptr, -2)
+            .map(|name| String::from_utf8_lossy(name).into_owned());
+        let key = crate::bytes::read_bytes(ptr, -1).and_then(|key| CString::new(key).ok());
+        sys::lua_pop(ptr, 3);
+        let (name, key) = (name?, key?);
+
+        let block = ffi::test_udata::<Header>(ptr, idx, key.as_ptr())?;
+        Some((name, block))
+    }
+}
+
+/// Pushes a table from property names to the accessor picked by `accessor`.
+unsafe fn push_accessors(
+    ptr: *mut sys::lua_State,
+    properties: &[crate::Property],
+    accessor: impl Fn(&crate::Property) -> sys::lua_CFunction,
+) {
+    sys::lua_newtable(ptr);
+    for property in properties {
+        if let Some(func) = accessor(property) {
+            sys::lua_pushcfunction(ptr, Some(func));
+            sys::lua_setfield(ptr, -2, property.name);
+        }
+    }
+}
+
+/// Accessor stored under the key at 2 in the table at `upvalue`.
+unsafe fn accessor(ptr: *mut sys::lua_State, upvalue: i32) -> Option<ffi::UnwindFunction> {
+    sys::lua_pushvalue(ptr, 2);
+    sys::lua_rawget(ptr, ffi::upvalue_index(upvalue));
+    let func = sys::lua_tocfunction(ptr, -1);
+    sys::lua_pop(ptr, 1);
+    // accessors are generated as unwinding functions, see `ffi::cfunction`
+    func.map(|func| std::mem::transmute::<crate::RawFunction, ffi::UnwindFunction>(func))
+}
+
+/// `__index` of types with properties, upvalues are the methods and the getters.
+unsafe extern "C-unwind" fn index(ptr: *mut sys::lua_State) -> c_int {
+    if let Some(get) = accessor(ptr, 2) {
+        sys::lua_settop(ptr, 2);
+        return get(ptr);
+    }
+    sys::lua_pushvalue(ptr, 2);
+    sys::lua_rawget(ptr, ffi::upvalue_index(1));
+    1
+}
+
+/// `__newindex` of types with properties, upvalues are the getters and the setters. Fields
+/// without a setter and unknown keys are errors.
+unsafe extern "C-unwind" fn new_index(ptr: *mut sys::lua_State) -> c_int {
+    if let Some(set) = accessor(ptr, 2) {
+        return set(ptr);
+    }
+
+    let name = type_name(ptr, 1).unwrap_or_default();
+    // checked first, `lua_tolstring` would turn numbers into strings in place
+    let key = if sys::lua_type(ptr, 2) == sys::LUA_TSTRING as c_int {
+        let key = crate::bytes::read_bytes(ptr, 2).unwrap_or_default();
+        format!("'{}'", String::from_utf8_lossy(key))
+    } else {
+        let ty = CStr::from_ptr(sys::lua_typename(ptr, sys::lua_type(ptr, 2)));
+        format!("({} key)", ty.to_string_lossy())
+    };
+    let message = if accessor(ptr, 1).is_some() {
+        format!("field {key} of {name} is read-only")
+    } else {
+        format!("{name} has no field {key}")
+    };
+    ffi::raise(ptr, Error::runtime(message))
+}
+
+unsafe extern "C-unwind" fn gc<T: UserData>(ptr: *mut sys::lua_State) -> c_int {
+    let Some(block) = ffi::test_udata::<Header>(ptr, 1, T::key()) else {
+        return 0;
+    };
+    // only `debug.getmetatable` reaches a borrowed value, it's leaked rather than freed under
+    // its borrower
+    if is_free(block) {
+        (*block).alive = false;
+        std::ptr::drop_in_place(value_ptr::<T>(block));
+    }
+    0
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{
+        cell::Cell,
+        rc::Rc,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::sys;
+    use macros::{cstr, user_data};
+
+    use crate::{state::State, MetaTable, Property, UserData, Value};
+
+    struct Handle(Rc<Cell<usize>>);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[user_data]
+    impl Handle {
+        pub fn drops(&self) -> usize {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn drop_runs_when_collected() {
+        let drops = Rc::new(Cell::new(0));
+        let state = State::new();
+        state.open_libs();
+        state.set_global("handle", Handle(drops.clone())).unwrap();
+        state.do_string("assert(handle:drops() == 0)").unwrap();
+        assert_eq!(drops.get(), 0);
+
+        state.do_string("handle = nil; collectgarbage()").unwrap();
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn drop_runs_when_state_closes() {
+        let drops = Rc::new(Cell::new(0));
+        let state = State::new();
+        state.push(Handle(drops.clone()));
+        state.push(Handle(drops.clone()));
+
+        drop(state);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn take_leaves_a_destroyed_userdata() {
+        let drops = Rc::new(Cell::new(0));
+        let state = State::new();
+        state.open_libs();
+        state.set_global("handle", Handle(drops.clone())).unwrap();
+        state.get_global::<Value>("handle").unwrap();
+
+        let handle = state.take::<Handle>(-1).unwrap();
+        assert!(state.cast_to::<&Handle>(-1).is_none());
+        assert!(state.take::<Handle>(-1).is_none());
+        assert!(state.do_string("handle:drops()").is_err());
+
+        drop(handle);
+        state.set_top(0);
+        state.do_string("handle = nil; collectgarbage()").unwrap();
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn gc_checks_its_argument() {
+        let drops = Rc::new(Cell::new(0));
+        let state = State::new();
+        state.open_libs();
+        state.set_global("handle", Handle(drops.clone())).unwrap();
+        state
+            .do_string(
+                r#"
+                hidden = getmetatable(handle) == false
+                local gc = debug.getmetatable(handle).__gc
+                gc(io.stdout); gc({}); gc(42)
+                before = handle:drops()
+                gc(handle); gc(handle)
+                "#,
+            )
+            .unwrap();
+
+        assert!(state.get_global::<bool>("hidden").unwrap());
+        assert_eq!(state.get_global::<i32>("before").unwrap(), 0);
+        assert_eq!(drops.get(), 1);
+        state.do_string("handle = nil; collectgarbage()").unwrap();
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[repr(align(64))]
+    struct Aligned(u64);
+
+    #[user_data]
+    impl Aligned {
+        pub fn address(&self) -> usize {
+            self as *const Self as usize
+        }
+
+        pub fn value(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn over_aligned_values() {
+        let state = State::new();
+        for value in 0..8 {
+            state.push(Aligned(value));
+        }
+
+        for idx in 1..=8 {
+            let aligned = state.cast_to::<&Aligned>(idx).unwrap();
+            assert_eq!(aligned.address() % 64, 0);
+            assert_eq!(aligned.value(), idx as u64 - 1);
+        }
+    }
+
+    static MARKER_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Marker;
+
+    impl Drop for Marker {
+        fn drop(&mut self) {
+            MARKER_DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[user_data]
+    impl Marker {
+        pub fn ping(&self) -> i32 {
+            1
+        }
+    }
+
+    #[test]
+    fn zero_sized_values() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("marker", Marker).unwrap();
+        state.do_string("pong = marker:ping()").unwrap();
+        assert_eq!(state.get_global::<i32>("pong").unwrap(), 1);
+
+        drop(state);
+        assert_eq!(MARKER_DROPS.load(Ordering::SeqCst), 1);
+    }
+
+    struct Socket;
+
+    #[user_data]
+    impl Socket {
+        pub fn port(&self) -> i32 {
+            8080
+        }
+    }
+
+    struct Vec2(f32, f32);
+
+    #[user_data]
+    impl Vec2 {
+        pub fn dot(&self, other: &Vec2) -> f32 {
+            self.0 * other.0 + self.1 * other.1
+        }
+    }
+
+    #[test]
+    fn casts_check_the_type() {
+        let state = State::new();
+        state.push(Socket);
+        state.push(Vec2(1.0, 2.0));
+
+        assert!(state.is::<Socket>(1));
+        assert!(!state.is::<Socket>(2));
+        assert!(!state.is::<Vec2>(1));
+        assert!(state.cast_to::<&Socket>(2).is_none());
+        assert!(state.cast_to::<&mut Vec2>(1).is_none());
+        assert_eq!(state.cast_to::<&Vec2>(2).unwrap().1, 2.0);
+    }
+
+    #[test]
+    fn wrong_userdata_arguments() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("socket", Socket).unwrap();
+        state.set_global("v", Vec2(1.0, 2.0)).unwrap();
+        state
+            .do_string(
+                "
+                _, self_err = pcall(socket.port, v)
+                _, arg_err = pcall(v.dot, v, socket)
+                _, nil_err = pcall(v.dot, v)
+                dot = v:dot(v)
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            state.get_global::<String>("self_err").unwrap(),
+            "bad argument #1 (Socket expected, got Vec2)"
+        );
+        assert_eq!(
+            state.get_global::<String>("arg_err").unwrap(),
+            "bad argument #2 (Vec2 expected, got Socket)"
+        );
+        assert_eq!(
+            state.get_global::<String>("nil_err").unwrap(),
+            "bad argument #2 (Vec2 expected, got no value)"
+        );
+        assert_eq!(state.get_global::<f32>("dot").unwrap(), 5.0);
+    }
+
+    #[test]
+    fn destroyed_userdata_arguments() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("socket", Socket).unwrap();
+        state.get_global::<Value>("socket").unwrap();
+        state.take::<Socket>(-1).unwrap();
+        state
+            .do_string("_, err = pcall(socket.port, socket)")
+            .unwrap();
+
+        assert_eq!(
+            state.get_global::<String>("err").unwrap(),
+            "bad argument #1 (Socket expected, got destroyed Socket)"
+        );
+    }
+
+    struct Tally(i32);
+
+    #[user_data]
+    impl Tally {
+        pub fn merge(&mut self, other: &Tally) {
+            self.0 += other.0;
+        }
+
+        pub fn take_self(&self, state: &State) -> i32 {
+            state.push(state.take::<Tally>(1).is_some());
+            1
+        }
+
+        pub fn value(&self) -> i32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn running_methods_borrow_their_arguments() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("a", Tally(1)).unwrap();
+        state.set_global("b", Tally(2)).unwrap();
+        state
+            .do_string(
+                "
+                _, alias_err = pcall(a.merge, a, a)
+                taken = a:take_self()
+                a:merge(b)
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            state.get_global::<String>("alias_err").unwrap(),
+            "bad argument #2 (Tally expected, got borrowed Tally)"
+        );
+        assert!(!state.get_global::<bool>("taken").unwrap());
+        // the borrows end with the calls
+        assert_eq!(state.get_global::<&Tally>("a").unwrap().0, 3);
+        state.get_global::<Value>("a").unwrap();
+        assert_eq!(state.take::<Tally>(-1).unwrap().0, 3);
+    }
+
+    #[derive(Debug, Clone, PartialEq, PartialOrd)]
+    struct Money(i64);
+
+    impl std::ops::Add for Money {
+        type Output = Money;
+
+        fn add(self, other: Money) -> Money {
+            Money(self.0 + other.0)
+        }
+    }
+
+    impl std::ops::Sub for Money {
+        type Output = Money;
+
+        fn sub(self, other: Money) -> Money {
+            Money(self.0 - other.0)
+        }
+    }
+
+    impl std::ops::Neg for Money {
+        type Output = Money;
+
+        fn neg(self) -> Money {
+            Money(-self.0)
+        }
+    }
+
+    impl std::fmt::Display for Money {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "${}", self.0)
+        }
+    }
+
+    #[user_data(Add, Sub, Neg, PartialEq, PartialOrd, Display)]
+    impl Money {
+        pub fn cents(&self) -> i64 {
+            self.0 * 100
+        }
+    }
+
+    #[test]
+    fn metamethods_from_traits() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("a", Money(5)).unwrap();
+        state.set_global("b", Money(3)).unwrap();
+        state.set_global("c", Money(5)).unwrap();
+        state
+            .do_string(
+                "
+                sum = tostring(a + b)
+                diff = tostring(a - b)
+                neg = tostring(-a)
+                eq, ne = a == c, a == b
+                lt, le, gt = b < a, a <= c, a < b
+                cents = (a + b):cents()
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("sum").unwrap(), "$8");
+        assert_eq!(state.get_global::<String>("diff").unwrap(), "$2");
+        assert_eq!(state.get_global::<String>("neg").unwrap(), "$-5");
+        assert!(state.get_global::<bool>("eq").unwrap());
+        assert!(!state.get_global::<bool>("ne").unwrap());
+        assert!(state.get_global::<bool>("lt").unwrap());
+        assert!(state.get_global::<bool>("le").unwrap());
+        assert!(!state.get_global::<bool>("gt").unwrap());
+        assert_eq!(state.get_global::<i64>("cents").unwrap(), 800);
+    }
+
+    struct Path(Vec<String>);
+
+    #[user_data]
+    impl Path {
+        #[lua(concat)]
+        fn join(&self, segment: &str) -> Path {
+            let mut segments = self.0.clone();
+            segments.push(segment.to_string());
+            Path(segments)
+        }
+
+        #[lua(len)]
+        fn segments(&self) -> usize {
+            self.0.len()
+        }
+
+        #[lua(call)]
+        fn segment(&self, idx: usize) -> Option<String> {
+            self.0.get(idx.checked_sub(1)?).cloned()
+        }
+
+        #[lua(eq)]
+        fn same(&self, other: &Path) -> bool {
+            self.0 == other.0
+        }
+
+        #[lua(tostring)]
+        pub fn display(&self) -> String {
+            self.0.join("/")
+        }
+    }
+
+    #[test]
+    fn metamethods_from_attributes() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global("root", Path(vec!["usr".to_string()]))
+            .unwrap();
+        state
+            .do_string(
+                "
+                local path = (root .. 'lib') .. 'lua'
+                text = tostring(path)
+                len = #path
+                second = path(2)
+                missing = path(10)
+                same = (root .. 'x') == (root .. 'x')
+                has_display = root.display ~= nil
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("text").unwrap(), "usr/lib/lua");
+        assert_eq!(state.get_global::<i32>("len").unwrap(), 3);
+        assert_eq!(state.get_global::<String>("second").unwrap(), "lib");
+        assert_eq!(state.get_global::<Option<String>>("missing").unwrap(), None);
+        assert!(state.get_global::<bool>("same").unwrap());
+        assert!(!state.get_global::<bool>("has_display").unwrap());
+    }
+
+    struct Player {
+        name: String,
+        health: i32,
+    }
+
+    #[user_data(fields(name, mut health))]
+    impl Player {
+        pub fn is_alive(&self) -> bool {
+            self.health > 0
+        }
+    }
+
+    #[test]
+    fn fields_as_properties() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global(
+                "player",
+                Player {
+                    name: "soreto".to_string(),
+                    health: 100,
+                },
+            )
+            .unwrap();
+        state
+            .do_string(
+                "
+                player.health = player.health - 10
+                name = player.name
+                alive = player:is_alive()
+                missing = player.mana
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("name").unwrap(), "soreto");
+        assert!(state.get_global::<bool>("alive").unwrap());
+        assert!(state
+            .get_global::<Option<i32>>("missing")
+            .unwrap()
+            .is_none());
+        state.get_global::<Value>("player").unwrap();
+        assert_eq!(state.cast_to::<&Player>(-1).unwrap().health, 90);
+    }
+
+    #[test]
+    fn invalid_property_writes() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global(
+                "player",
+                Player {
+                    name: "soreto".to_string(),
+                    health: 100,
+                },
+            )
+            .unwrap();
+        state
+            .do_string(
+                "
+                _, read_only = pcall(function() player.name = 'x' end)
+                _, unknown = pcall(function() player.mana = 10 end)
+                _, method = pcall(function() player.is_alive = 10 end)
+                _, wrong_type = pcall(function() player.health = 'full' end)
+                ",
+            )
+            .unwrap();
+
+        let error = |name: &str| state.get_global::<String>(name).unwrap();
+        assert!(error("read_only").ends_with("field 'name' of Player is read-only"));
+        assert!(error("unknown").ends_with("Player has no field 'mana'"));
+        assert!(error("method").ends_with("Player has no field 'is_alive'"));
+        assert_eq!(
+            error("wrong_type"),
+            "bad argument #3 (i32 expected, got string)"
+        );
+    }
+
+    struct Enemy;
+    struct Item;
+
+    struct Pool<T> {
+        items: Vec<T>,
+    }
+
+    #[user_data]
+    impl<T> Pool<T>
+    where
+        T: Default + 'static,
+    {
+        pub fn new() -> Self {
+            Pool { items: vec![] }
+        }
+
+        pub fn spawn(&mut self) -> usize {
+            self.items.push(T::default());
+            self.items.len()
+        }
+    }
+
+    impl Default for Enemy {
+        fn default() -> Self {
+            Enemy
+        }
+    }
+
+    impl Default for Item {
+        fn default() -> Self {
+            Item
+        }
+    }
+
+    #[test]
+    fn generic_user_data() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("enemies", Pool::<Enemy>::new()).unwrap();
+        state.set_global("items", Pool::<Item>::new()).unwrap();
+        state
+            .do_string(
+                "
+                enemies:spawn()
+                count = enemies:spawn()
+                _, err = pcall(enemies.spawn, items)
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<i32>("count").unwrap(), 2);
+        assert_eq!(
+            state.get_global::<String>("err").unwrap(),
+            "bad argument #1 (Pool<Enemy> expected, got Pool<Item>)"
+        );
+    }
+
+    mod net {
+        pub struct Conn(pub u16);
+    }
+
+    #[user_data]
+    impl net::Conn {
+        pub fn port(&self) -> u16 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn paths() {
+        let state = State::new();
+        state.set_global("conn", net::Conn(8080)).unwrap();
+        state.do_string("port = conn:port()").unwrap();
+
+        assert_eq!(state.get_global::<u16>("port").unwrap(), 8080);
+        state.get_global::<Value>("conn").unwrap();
+        assert!(state.is::<net::Conn>(-1));
+    }
+
+    mod left {
+        use super::*;
+
+        pub struct Side(pub i32);
+
+        #[user_data]
+        impl Side {
+            pub fn left(&self) -> i32 {
+                self.0
+            }
+        }
+    }
+
+    mod right {
+        use super::*;
+
+        pub struct Side(pub i32);
+
+        #[user_data]
+        impl Side {
+            pub fn right(&self) -> i32 {
+                self.0
+            }
+        }
+    }
+
+    #[test]
+    fn same_names_in_different_modules() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("l", left::Side(1)).unwrap();
+        state.set_global("r", right::Side(2)).unwrap();
+        state
+            .do_string("a = l:left(); b = r:right(); mixed = l.right == nil and r.left == nil")
+            .unwrap();
+
+        assert_eq!(state.get_global::<i32>("a").unwrap(), 1);
+        assert_eq!(state.get_global::<i32>("b").unwrap(), 2);
+        assert!(state.get_global::<bool>("mixed").unwrap());
+        state.get_global::<Value>("l").unwrap();
+        assert!(state.is::<left::Side>(-1));
+        assert!(!state.is::<right::Side>(-1));
+    }
+
+    struct Counter {
+        value: i32,
+    }
+
+    #[user_data(fields(value))]
+    impl Counter {
+        pub fn get(&self) -> i32 {
+            self.value
+        }
+    }
+
+    #[user_data(extend)]
+    impl Counter {
+        pub fn increment(&mut self) {
+            self.value += 1;
+        }
+
+        #[lua(tostring)]
+        fn show(&self) -> String {
+            format!("Counter({})", self.value)
+        }
+    }
+
+    #[cfg(any())]
+    #[user_data(extend)]
+    impl Counter {
+        pub fn disabled(&self) {}
+    }
+
+    #[test]
+    fn extension_blocks() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("counter", Counter { value: 1 }).unwrap();
+        state
+            .do_string(
+                "
+                counter:increment()
+                value = counter:get()
+                text = tostring(counter)
+                disabled = counter.disabled == nil
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<i32>("value").unwrap(), 2);
+        assert_eq!(state.get_global::<String>("text").unwrap(), "Counter(2)");
+        assert!(state.get_global::<bool>("disabled").unwrap());
+    }
+
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    #[user_data(fields(x, y))]
+    impl Point {
+        pub const ZERO: Point = Point { x: 0.0, y: 0.0 };
+        pub const DIMENSIONS: i32 = 2;
+        const HIDDEN: i32 = 0;
+
+        #[lua(constructor)]
+        fn create(x: f64, y: f64) -> Self {
+            Point { x, y }
+        }
+
+        pub fn lerp(a: &Point, b: &Point, t: f64) -> Point {
+            Point::create(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t)
+        }
+
+        pub fn length(&self) -> f64 {
+            (self.x * self.x + self.y * self.y).sqrt() + Self::HIDDEN as f64
+        }
+    }
+
+    #[test]
+    fn class_tables() {
+        let state = State::new();
+        state.open_libs();
+        state.register_type::<Point>().unwrap();
+        state
+            .do_string(
+                "
+                local a = Point.new(3, 4)
+                local b = Point(5, 6)
+                local mid = Point.lerp(a, b, 0.5)
+                length = a:length()
+                mid_x, mid_y = mid.x, mid.y
+                zero = Point.ZERO:length()
+                dimensions = Point.DIMENSIONS
+                hidden = Point.HIDDEN
+                via_class = Point.length(b)
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<f64>("length").unwrap(), 5.0);
+        assert_eq!(state.get_global::<f64>("mid_x").unwrap(), 4.0);
+        assert_eq!(state.get_global::<f64>("mid_y").unwrap(), 5.0);
+        assert_eq!(state.get_global::<f64>("zero").unwrap(), 0.0);
+        assert_eq!(state.get_global::<i32>("dimensions").unwrap(), 2);
+        assert!(state.get_global::<Option<i32>>("hidden").unwrap().is_none());
+        assert!(state.get_global::<f64>("via_class").unwrap() > 7.8);
+    }
+
+    #[test]
+    fn class_without_constructor() {
+        let state = State::new();
+        state.open_libs();
+        state.register_type::<Socket>().unwrap();
+        state
+            .do_string("ok = pcall(Socket); has_new = Socket.new ~= nil")
+            .unwrap();
+
+        assert!(!state.get_global::<bool>("ok").unwrap());
+        assert!(!state.get_global::<bool>("has_new").unwrap());
+    }
+}
@@ -0,0 +1,623 @@
+use std::{borrow::Cow, ffi::c_void, fmt};
+
+use crate::sys;
+
+use crate::{
+    bytes,
+    coroutine::Coroutine,
+    from_lua::FromLua,
+    int64::{self, Boxed},
+    is_type::IsType,
+    reference::Reference,
+    to_lua::ToLua,
+    user_data, ByteBuf, LuaFunction, Table, UserData, Variadic,
+};
+
+/// Any number of values of any type, what an untyped lua function takes and returns.
+pub type MultiValue = Variadic<Value>;
+
+/// Handle to a full userdata of any type, pinned in the registry.
+pub struct AnyUserData(Reference);
+
+impl AnyUserData {
+    /// Whether the userdata was pushed as a `T`.
+    pub fn is<T: UserData>(&self) -> bool {
+        let ptr = self.0.ptr();
+        self.0.push(ptr);
+        let is = user_data::is::<T>(ptr, -1);
+        unsafe { sys::lua_pop(ptr, 1) };
+        is
+    }
+
+    /// Name of the type it was pushed as, `None` if it wasn't pushed through [`UserData`].
+    pub fn type_name(&self) -> Option<String> {
+        let ptr = self.0.ptr();
+        self.0.push(ptr);
+        let name = user_data::type_name(ptr, -1);
+        unsafe { sys::lua_pop(ptr, 1) };
+        name
+    }
+}
+
+impl Clone for AnyUserData {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+/// A bare pointer, lua neither owns nor inspects it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightUserData(pub *mut c_void);
+
+/// Handle to a cdata object other than `int64_t`/`uint64_t`, pinned in the registry.
+pub struct CData(Reference);
+
+impl Clone for CData {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+/// Any lua value, for when the type is only known at runtime.
+///
+/// As an argument it accepts anything, including a missing value which reads as `Nil`.
+/// Reference types are handles pinned in the registry, cloning one gives another handle to the
+/// same object and equality compares identity. `int64_t` cdata read as `Integer` and `uint64_t`
+/// cdata as `UInteger`, both are pushed as numbers up to 2^53 and as cdata of their type past
+/// that.
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Integer(i64),
+    UInteger(u64),
+    String(ByteBuf),
+    Table(Table),
+    Function(LuaFunction<'static, MultiValue, MultiValue>),
+    UserData(AnyUserData),
+    LightUserData(LightUserData),
+    Thread(Coroutine),
+    CData(CData),
+}
+
+impl Value {
+    /// What `type()` returns for the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Integer(value) if value.unsigned_abs() <= int64::MAX_SAFE_INTEGER => "number",
+            Value::UInteger(value) if *value <= int64::MAX_SAFE_INTEGER => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+            Value::UserData(_) | Value::LightUserData(_) => "userdata",
+            Value::Thread(_) => "thread",
+            Value::Integer(_) | Value::UInteger(_) | Value::CData(_) => "cdata",
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Numbers and integers, the latter may lose precision past 2^53.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            Value::Integer(value) => Some(*value as f64),
+            Value::UInteger(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    /// Integers and numbers without a fractional part that fit an `i64`.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            Value::UInteger(value) => i64::try_from(*value).ok(),
+            Value::Number(value) if value.fract() == 0.0 && value.abs() < 2f64.powi(63) => {
+                Some(*value as i64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Strict, strings that aren't valid UTF-8 give `None`.
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(self.as_bytes()?).ok()
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&LuaFunction<'static, MultiValue, MultiValue>> {
+        match self {
+            Value::Function(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_user_data(&self) -> Option<&AnyUserData> {
+        match self {
+            Value::UserData(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_light_user_data(&self) -> Option<*mut c_void> {
+        match self {
+            Value::LightUserData(value) => Some(value.0),
+            _ => None,
+        }
+    }
+
+    pub fn as_thread(&self) -> Option<&Coroutine> {
+        match self {
+            Value::Thread(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_cdata(&self) -> Option<&CData> {
+        match self {
+            Value::CData(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Address of reference types, `None` for plain values.
+    fn to_pointer(&self) -> Option<*const c_void> {
+        match self {
+            Value::Table(value) => Some(value.to_pointer()),
+            Value::Function(value) => Some(value.to_pointer()),
+            Value::UserData(value) => Some(value.0.to_pointer()),
+            Value::LightUserData(value) => Some(value.0),
+            Value::Thread(value) => Some(value.to_pointer()),
+            Value::CData(value) => Some(value.0.to_pointer()),
+            _ => None,
+        }
+    }
+
+    fn push(&self, ptr: *mut sys::lua_State) {
+        match self {
+            Value::Nil => unsafe { sys::lua_pushnil(ptr) },
+            Value::Boolean(value) => value.to_lua(ptr),
+            Value::Number(value) => value.to_lua(ptr),
+            Value::Integer(value) => int64::push_integer(ptr, *value as i128),
+            Value::UInteger(value) if *value <= int64::MAX_SAFE_INTEGER => unsafe {
+                sys::lua_pushnumber(ptr, *value as f64)
+            },
+            Value::UInteger(value) => {
+                if let Err(error) = int64::push_uint64(ptr, *value) {
+                    panic!("can't push {value} as 64-bit cdata: {error}");
+                }
+            }
+            Value::String(value) => bytes::push_bytes(ptr, value),
+            Value::Table(value) => value.push(ptr),
+            Value::Function(value) => value.push(ptr),
+            Value::UserData(value) => value.0.push(ptr),
+            Value::LightUserData(value) => value.to_lua(ptr),
+            Value::Thread(value) => value.push(ptr),
+            Value::CData(value) => value.0.push(ptr),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::UInteger(a), Value::UInteger(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (a, b) => {
+                std::mem::discriminant(a) == std::mem::discriminant(b)
+                    && a.to_pointer().is_some()
+                    && a.to_pointer() == b.to_pointer()
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "Nil"),
+            Value::Boolean(value) => write!(f, "Boolean({value})"),
+            Value::Number(value) => write!(f, "Number({value:?})"),
+            Value::Integer(value) => write!(f, "Integer({value})"),
+            Value::UInteger(value) => write!(f, "UInteger({value})"),
+            Value::String(value) => write!(f, "String({:?})", String::from_utf8_lossy(value)),
+            Value::UserData(value) => match value.type_name() {
+                Some(name) => write!(f, "UserData({name}: {:p})", value.0.to_pointer()),
+                None => write!(f, "UserData({:p})", value.0.to_pointer()),
+            },
+            Value::Table(_) => write!(f, "Table({:p})", self.to_pointer().unwrap()),
+            Value::Function(_) => write!(f, "Function({:p})", self.to_pointer().unwrap()),
+            Value::LightUserData(_) => write!(f, "LightUserData({:p})", self.to_pointer().unwrap()),
+            Value::Thread(_) => write!(f, "Thread({:p})", self.to_pointer().unwrap()),
+            Value::CData(_) => write!(f, "CData({:p})", self.to_pointer().unwrap()),
+        }
+    }
+}
+
+/// Formats the value the way `tostring` would without metamethods, `table: 0x...` for
+/// reference types.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(value) => write!(f, "{value}"),
+            Value::Number(value) => write!(f, "{value}"),
+            // plain numbers once pushed, cdata past 2^53
+            Value::Integer(value) if value.unsigned_abs() <= int64::MAX_SAFE_INTEGER => {
+                write!(f, "{value}")
+            }
+            Value::UInteger(value) if *value <= int64::MAX_SAFE_INTEGER => write!(f, "{value}"),
+            Value::Integer(value) => write!(f, "{value}LL"),
+            Value::UInteger(value) => write!(f, "{value}ULL"),
+            Value::String(value) => write!(f, "{}", String::from_utf8_lossy(value)),
+            _ => write!(f, "{}: {:p}", self.type_name(), self.to_pointer().unwrap()),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::UInteger(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(ByteBuf(value.as_bytes().to_vec()))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(ByteBuf(value.into_bytes()))
+    }
+}
+
+impl From<Cow<'_, str>> for Value {
+    fn from(value: Cow<'_, str>) -> Self {
+        value.into_owned().into()
+    }
+}
+
+impl From<Table> for Value {
+    fn from(value: Table) -> Self {
+        Value::Table(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+impl<'a> FromLua<'a> for Value {
+    type Output = Value;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        let kind = unsafe { sys::lua_type(ptr, idx) };
+        Some(match kind as u32 {
+            sys::LUA_TBOOLEAN => Value::Boolean(unsafe { sys::lua_toboolean(ptr, idx) } != 0),
+            sys::LUA_TNUMBER => Value::Number(unsafe { sys::lua_tonumber(ptr, idx) }),
+            sys::LUA_TSTRING => Value::String(ByteBuf(bytes::read_bytes(ptr, idx)?.to_vec())),
+            sys::LUA_TTABLE => Value::Table(Table::new(ptr, idx)),
+            sys::LUA_TFUNCTION => Value::Function(LuaFunction::new(ptr, idx)),
+            sys::LUA_TUSERDATA => Value::UserData(AnyUserData(Reference::new(ptr, idx))),
+            sys::LUA_TLIGHTUSERDATA => {
+                Value::LightUserData(LightUserData(unsafe { sys::lua_touserdata(ptr, idx) }))
+            }
+            sys::LUA_TTHREAD => Value::Thread(Coroutine::new(ptr, idx)),
+            _ if kind == int64::LUA_TCDATA => match int64::read_boxed(ptr, idx) {
+                Some(Boxed::Int64(value)) => Value::Integer(value),
+                Some(Boxed::UInt64(value)) => Value::UInteger(value),
+                None => Value::CData(CData(Reference::new(ptr, idx))),
+            },
+            _ => Value::Nil,
+        })
+    }
+}
+
+impl IsType for Value {
+    #[inline]
+    fn is_type(_: *mut sys::lua_State, _: i32) -> bool {
+        true
+    }
+}
+
+impl ToLua for Value {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        self.push(state)
+    }
+}
+
+impl ToLua for &Value {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        self.push(state)
+    }
+}
+
+impl ToLua for LightUserData {
+    #[inline]
+    fn to_lua(self, state: *mut sys::lua_State) {
+        unsafe { sys::lua_pushlightuserdata(state, self.0) }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::sys;
+    use macros::{cstr, user_data};
+
+    use super::{MultiValue, Value};
+    use crate::{state::State, LuaFunction, MetaTable, Table, UserData, Variadic};
+
+    #[test]
+    fn read_every_type() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string(
+                "
+                local ffi = require('ffi')
+                return nil, true, 1.5, 'text', {}, print, coroutine.create(print),
+                    ffi.new('int64_t', 7), ffi.new('uint64_t', -1LL), ffi.new('int[2]')
+                ",
+            )
+            .unwrap();
+
+        let values = state.cast_to::<Variadic<Value>>(1).unwrap();
+        let names = values.iter().map(Value::type_name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "nil", "boolean", "number", "string", "table", "function", "thread", "number",
+                "cdata", "cdata"
+            ]
+        );
+        assert_eq!(values[1].as_bool(), Some(true));
+        assert_eq!(values[2].as_number(), Some(1.5));
+        assert_eq!(values[3].as_str(), Some("text"));
+        assert!(values[4].as_table().is_some());
+        assert!(values[6].as_thread().is_some());
+        assert_eq!(values[7], Value::Integer(7));
+        assert_eq!(values[8], Value::UInteger(u64::MAX));
+        assert!(values[9].as_cdata().is_some());
+        assert!(state.cast_to::<Value>(100).unwrap().is_nil());
+    }
+
+    #[test]
+    fn accessors() {
+        assert_eq!(Value::Number(3.0).as_integer(), Some(3));
+        assert_eq!(Value::Number(3.5).as_integer(), None);
+        assert_eq!(Value::Integer(-2).as_number(), Some(-2.0));
+        assert_eq!(Value::UInteger(u64::MAX).as_integer(), None);
+        assert_eq!(Value::Integer(1).type_name(), "number");
+        assert_eq!(Value::UInteger(1 << 60).type_name(), "cdata");
+        assert_eq!(Value::from("x").as_bytes(), Some(&b"x"[..]));
+        assert_eq!(Value::String(crate::ByteBuf(vec![0xff])).as_str(), None);
+        assert_eq!(Value::from(None::<bool>), Value::Nil);
+        assert_eq!(Value::Boolean(false).as_number(), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string("t = {}; f = function() end; co = coroutine.create(f)")
+            .unwrap();
+
+        for name in ["t", "f", "co"] {
+            let value = state.get_global::<Value>(name).unwrap();
+            state.set_global("copy", &value).unwrap();
+            state.do_string(&format!("assert(copy == {name})")).unwrap();
+            assert_eq!(state.get_global::<Value>("copy").unwrap(), value);
+        }
+
+        state.set_global("big", Value::Integer(i64::MAX)).unwrap();
+        state
+            .do_string("assert(big == 9223372036854775807LL)")
+            .unwrap();
+        state.set_global("small", Value::Integer(-7)).unwrap();
+        state
+            .do_string("assert(type(small) == 'number' and small == -7)")
+            .unwrap();
+        state.set_global("s", Value::from("hi")).unwrap();
+        assert_eq!(state.get_global::<String>("s").unwrap(), "hi");
+        assert_ne!(
+            state.get_global::<Value>("t").unwrap(),
+            state.get_global::<Value>("f").unwrap()
+        );
+    }
+
+    #[test]
+    fn unsigned_cdata_keeps_its_type() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string("ffi = require('ffi'); u = ffi.new('uint64_t', 2^60)")
+            .unwrap();
+
+        let value = state.get_global::<Value>("u").unwrap();
+        assert_eq!(value, Value::UInteger(1 << 60));
+        state.set_global("copy", &value).unwrap();
+        state
+            .do_string("assert(ffi.istype('uint64_t', copy) and copy == u)")
+            .unwrap();
+    }
+
+    #[test]
+    fn formatting() {
+        let state = State::new();
+        state.open_libs();
+        state.do_string("t = {}").unwrap();
+
+        assert_eq!(Value::Nil.to_string(), "nil");
+        assert_eq!(Value::Number(2.5).to_string(), "2.5");
+        assert_eq!(Value::Integer(-5).to_string(), "-5");
+        assert_eq!(Value::UInteger(5).to_string(), "5");
+        assert_eq!(
+            Value::Integer(i64::MIN).to_string(),
+            "-9223372036854775808LL"
+        );
+        assert_eq!(
+            Value::UInteger(u64::MAX).to_string(),
+            "18446744073709551615ULL"
+        );
+        assert_eq!(Value::from("a").to_string(), "a");
+        assert_eq!(format!("{:?}", Value::from("a")), "String(\"a\")");
+
+        state.do_string("return tostring(t)").unwrap();
+        let expected = state.cast_to::<String>(-1).unwrap();
+        let table = state.get_global::<Value>("t").unwrap();
+        assert_eq!(table.to_string(), expected);
+        assert!(format!("{table:?}").starts_with("Table(0x"));
+    }
+
+    #[test]
+    fn catch_all_arguments() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("describe", |values: MultiValue| {
+                values
+                    .iter()
+                    .map(Value::type_name)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap();
+        state
+            .set_global_fn("first", |table: Table| table.get::<_, Value>(1).unwrap())
+            .unwrap();
+        state
+            .do_string(
+                "
+                kinds = describe(1, 'a', nil, {}, false)
+                a, b = first({42}), first({})
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            state.get_global::<String>("kinds").unwrap(),
+            "number,string,nil,table,boolean"
+        );
+        assert_eq!(state.get_global::<Value>("a").unwrap(), Value::Number(42.0));
+        assert!(state.get_global::<Value>("b").unwrap().is_nil());
+    }
+
+    #[test]
+    fn untyped_functions() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string("function pick(flag) if flag then return 1, 'two' end return {} end")
+            .unwrap();
+
+        let pick = state
+            .get_global::<LuaFunction<MultiValue, MultiValue>>("pick")
+            .unwrap();
+        let values = pick.call(Variadic(vec![Value::Boolean(true)])).unwrap();
+        assert_eq!(*values, vec![Value::Number(1.0), Value::from("two")]);
+        let values = pick.call(Variadic::new()).unwrap();
+        assert!(values[0].as_table().is_some());
+
+        let function = state.get_global::<Value>("pick").unwrap();
+        let values = function
+            .as_function()
+            .unwrap()
+            .call(Variadic::new())
+            .unwrap();
+        assert_eq!(values.len(), 1);
+    }
+
+    struct Probe;
+
+    #[user_data]
+    impl Probe {
+        pub fn kind(&self, value: Value) -> String {
+            value.type_name().to_string()
+        }
+
+        pub fn echo(&self, value: Value) -> Value {
+            value
+        }
+    }
+
+    #[test]
+    fn user_data_values() {
+        let state = State::new();
+        state.open_libs();
+        state.set_global("probe", Probe).unwrap();
+        state
+            .do_string(
+                "
+                kind = probe:kind(probe)
+                same = probe:echo(probe) == probe
+                missing = probe:kind()
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<String>("kind").unwrap(), "userdata");
+        assert!(state.get_global::<bool>("same").unwrap());
+        assert_eq!(state.get_global::<String>("missing").unwrap(), "nil");
+
+        let probe = state.get_global::<Value>("probe").unwrap();
+        let user_data = probe.as_user_data().unwrap();
+        assert!(user_data.is::<Probe>());
+        assert_eq!(user_data.type_name().as_deref(), Some("Probe"));
+    }
+}
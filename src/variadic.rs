@@ -0,0 +1,230 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::sys;
+
+use crate::{error, from_lua::FromLua, state::State, to_lua::ToLua};
+
+/// Any number of values of the same type, `...` in lua.
+///
+/// As an argument it takes every value left on the stack, so it goes last in the tuple of
+/// arguments. As a result it pushes all of its values, and as the output of a
+/// [`LuaFunction`](crate::LuaFunction) it collects everything the function returned.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Variadic<T>(pub Vec<T>);
+
+impl<T> Variadic<T> {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<T> Default for Variadic<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for Variadic<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Variadic<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T> From<Vec<T>> for Variadic<T> {
+    fn from(values: Vec<T>) -> Self {
+        Self(values)
+    }
+}
+
+impl<T> FromIterator<T> for Variadic<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<T> IntoIterator for Variadic<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<T: ToLua> ToLua for Variadic<T> {
+    fn to_lua(self, ptr: *mut sys::lua_State) {
+        let state = State::from_raw(ptr);
+        self.0.into_iter().for_each(|value| state.push(value));
+    }
+
+    /// Only known once pushed, callers count the values they find on the stack instead.
+    fn len() -> i32 {
+        0
+    }
+}
+
+impl<'a, T: FromLua<'a, Output = T>> FromLua<'a> for Variadic<T> {
+    type Output = Variadic<T>;
+
+    fn from_lua(ptr: *mut sys::lua_State, idx: i32) -> Option<Self::Output> {
+        let state = State::from_raw(ptr);
+        let idx = crate::from_lua::abs_index(ptr, idx);
+        (idx..=state.get_top())
+            .map(|idx| T::from_lua(ptr, idx))
+            .collect()
+    }
+
+    fn len() -> i32 {
+        sys::LUA_MULTRET
+    }
+
+    fn mismatch(ptr: *mut sys::lua_State, idx: i32) -> Option<(i32, String)> {
+        let idx = crate::from_lua::abs_index(ptr, idx);
+        (idx..=State::from_raw(ptr).get_top())
+            .find(|&i| T::from_lua(ptr, i).is_none())
+            .map(|i| (i - idx, error::type_name::<T>()))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::sys;
+    use macros::{cstr, user_data};
+
+    use super::Variadic;
+    use crate::{state::State, LuaFunction, MetaTable, UserData, Value};
+
+    #[test]
+    fn variadic_arguments() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("sum", |values: Variadic<i32>| values.iter().sum::<i32>())
+            .unwrap();
+        state
+            .set_global_fn("join", |(sep, parts): (String, Variadic<String>)| {
+                parts.join(&sep)
+            })
+            .unwrap();
+        state
+            .do_string(
+                "
+                total, empty = sum(1, 2, 3), sum()
+                joined = join(', ', 'a', 'b', 'c')
+                _, err = pcall(sum, 1, 2, 'x')
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(state.get_global::<i32>("total").unwrap(), 6);
+        assert_eq!(state.get_global::<i32>("empty").unwrap(), 0);
+        assert_eq!(state.get_global::<String>("joined").unwrap(), "a, b, c");
+        assert_eq!(
+            state.get_global::<String>("err").unwrap(),
+            "bad argument #3 (i32 expected, got string)"
+        );
+    }
+
+    #[test]
+    fn variadic_results() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global_fn("range", |n: i32| (1..=n).collect::<Variadic<i32>>())
+            .unwrap();
+        state
+            .do_string("count = select('#', range(4)); last = select(4, range(4))")
+            .unwrap();
+
+        assert_eq!(state.get_global::<i32>("count").unwrap(), 4);
+        assert_eq!(state.get_global::<i32>("last").unwrap(), 4);
+    }
+
+    #[test]
+    fn lua_functions_with_variadics() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .do_string("function pack(...) return select('#', ...), ... end")
+            .unwrap();
+
+        let pack = state
+            .get_global::<LuaFunction<Variadic<i32>, (i32, Variadic<i32>)>>("pack")
+            .unwrap();
+        let top = state.get_top();
+        let (count, values) = pack.call(Variadic(vec![5, 6, 7])).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(values.into_inner(), vec![5, 6, 7]);
+        assert_eq!(state.get_top(), top);
+
+        let all = state
+            .get_global::<LuaFunction<Variadic<i32>, Variadic<i32>>>("pack")
+            .unwrap();
+        assert_eq!(*all.call(Variadic::new()).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn protected_call_with_variadics() {
+        let state = State::new();
+        state.open_libs();
+        state.do_string("function double(...) local t = {...} for i, v in ipairs(t) do t[i] = v * 2 end return unpack(t) end").unwrap();
+
+        state.get_global::<Value>("double").unwrap();
+        let values = state
+            .protected_call::<_, Variadic<i32>>(Variadic(vec![1, 2, 3]))
+            .unwrap();
+        assert_eq!(*values, vec![2, 4, 6]);
+    }
+
+    struct Logger {
+        lines: Vec<String>,
+    }
+
+    #[user_data]
+    impl Logger {
+        pub fn log(&mut self, level: String, parts: Variadic<String>) -> usize {
+            self.lines.push(format!("[{level}] {}", parts.join(" ")));
+            self.lines.len()
+        }
+
+        pub fn lines(&self) -> Variadic<String> {
+            self.lines.iter().cloned().collect()
+        }
+    }
+
+    #[test]
+    fn user_data_variadics() {
+        let state = State::new();
+        state.open_libs();
+        state
+            .set_global("logger", Logger { lines: vec![] })
+            .unwrap();
+        state
+            .do_string(
+                "
+                logger:log('info', 'hello', 'world')
+                logger:log('warn')
+                first, second = logger:lines()
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            state.get_global::<String>("first").unwrap(),
+            "[info] hello world"
+        );
+        assert_eq!(state.get_global::<String>("second").unwrap(), "[warn] ");
+    }
+}